@@ -0,0 +1,153 @@
+#![allow(dead_code)]
+use cgmath::{Quaternion, InnerSpace};
+
+use animation::skeleton::{JointTransform, decode_frame, sample_pose, interpolate_orientation};
+use md5::md5anim::{Md5Anim, Joint as AnimJoint};
+
+// Blending of local poses, as returned by `sample_pose`, before `to_object_space`
+// turns them into the skeleton given to the renderer. Poses being blended have
+// the joints of the same hierarchy, in the same order.
+
+// Weight of each joint in a blend, from 0 (untouched) to 1
+pub type JointMask = Vec<f32>;
+
+// `a` faded into `b` by `weight`, 0 giving `a` and 1 `b`
+pub fn crossfade(a: &[JointTransform], b: &[JointTransform], weight: f32) -> Vec<JointTransform> {
+    a.iter().zip(b.iter()).map(|(ja, jb)| blend_joint(ja, jb, weight)).collect()
+}
+
+// Crossfade of `a` at `time_a` and `b` at `time_b`, looping both
+pub fn crossfade_clips(a: &Md5Anim, time_a: f32, b: &Md5Anim, time_b: f32, weight: f32) -> Vec<JointTransform> {
+    crossfade(&sample_pose(a, time_a, true), &sample_pose(b, time_b, true), weight)
+}
+
+// `layer` over `base` by `weight` times the weight of each joint in `mask`, joints
+// past the end of the mask keeping `base`
+pub fn blend_masked(base: &[JointTransform], layer: &[JointTransform], weight: f32, mask: &[f32]) -> Vec<JointTransform> {
+    base.iter().zip(layer.iter()).enumerate().map(|(i, (jb, jl))| {
+        blend_joint(jb, jl, weight * mask.get(i).cloned().unwrap_or(0.0))
+    }).collect()
+}
+
+// Difference of `pose` from `reference`, joint by joint, for `apply_additive`
+pub fn additive_delta(pose: &[JointTransform], reference: &[JointTransform]) -> Vec<JointTransform> {
+    pose.iter().zip(reference.iter()).map(|(p, r)| {
+        JointTransform {
+            position: p.position - r.position,
+            orientation: (r.orientation.conjugate() * p.orientation).normalize(),
+        }
+    }).collect()
+}
+
+// Difference of `anim` at `time` from its frame `reference`, usually the first one
+pub fn additive_clip_delta(anim: &Md5Anim, time: f32, reference: usize) -> Vec<JointTransform> {
+    additive_delta(&sample_pose(anim, time, true), &decode_frame(anim, reference))
+}
+
+// `delta` added to `base` by `weight`, and by the weight of each joint in `mask`
+// when given
+pub fn apply_additive(base: &[JointTransform], delta: &[JointTransform], weight: f32, mask: Option<&[f32]>) -> Vec<JointTransform> {
+    let identity = Quaternion::new(1.0, 0.0, 0.0, 0.0);
+
+    base.iter().zip(delta.iter()).enumerate().map(|(i, (b, d))| {
+        let w = weight * mask.map(|m| m.get(i).cloned().unwrap_or(0.0)).unwrap_or(1.0);
+        JointTransform {
+            position: b.position + d.position * w,
+            orientation: (b.orientation * interpolate_orientation(identity, d.orientation, w)).normalize(),
+        }
+    }).collect()
+}
+
+// Mask of the joint named `root` and every joint under it, None when there is no
+// such joint
+pub fn mask_from_root(hierarchy: &[AnimJoint], root: &str) -> Option<JointMask> {
+    let root = match hierarchy.iter().position(|j| j.name == root) {
+        Some(root) => root,
+        None => return None
+    };
+
+    // parents come before their children
+    let mut mask = vec![0.0; hierarchy.len()];
+    for (i, joint) in hierarchy.iter().enumerate() {
+        if i == root || (joint.index >= 0 && mask.get(joint.index as usize) == Some(&1.0)) {
+            mask[i] = 1.0;
+        }
+    }
+    Some(mask)
+}
+
+// Joints left out of `mask`, such as the lower body for an upper body mask
+pub fn invert_mask(mask: &[f32]) -> JointMask {
+    mask.iter().map(|w| 1.0 - w).collect()
+}
+
+fn blend_joint(a: &JointTransform, b: &JointTransform, weight: f32) -> JointTransform {
+    JointTransform {
+        position: a.position + (b.position - a.position) * weight,
+        orientation: interpolate_orientation(a.orientation, b.orientation, weight),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector3, Quaternion, Rotation3, Deg, InnerSpace};
+    use animation::skeleton::{JointTransform, decode_frame};
+    use animation::skeleton::tests::anim;
+    use md5::md5anim::Joint;
+
+    fn joint(x: f32, angle: f32) -> JointTransform {
+        JointTransform { position: Vector3::new(x, 0.0, 0.0), orientation: Quaternion::from_angle_z(Deg(angle)) }
+    }
+
+    fn close(a: &JointTransform, b: &JointTransform) -> bool {
+        (a.position - b.position).magnitude() < 1e-5 && a.orientation.dot(b.orientation).abs() > 1.0 - 1e-5
+    }
+
+    #[test]
+    fn crossfade() {
+        let pose = super::crossfade(&[joint(0.0, 0.0)], &[joint(2.0, 90.0)], 0.5);
+        assert!(close(&pose[0], &joint(1.0, 45.0)));
+
+        let a = anim();
+        assert_eq!(super::crossfade_clips(&a, 0.0, &a, 1.0 / 24.0, 1.0), decode_frame(&a, 1));
+    }
+
+    #[test]
+    fn additive() {
+        let reference = vec![joint(0.0, 0.0), joint(1.0, 10.0)];
+        let pose = vec![joint(0.0, 0.0), joint(2.0, 40.0)];
+        let delta = super::additive_delta(&pose, &reference);
+
+        // the reference itself adds nothing
+        assert!(super::additive_delta(&reference, &reference).iter().all(|d| close(d, &joint(0.0, 0.0))));
+
+        let base = vec![joint(5.0, 90.0), joint(0.0, 0.0)];
+        let added = super::apply_additive(&base, &delta, 1.0, None);
+        assert!(close(&added[1], &joint(1.0, 30.0)));
+        let half = super::apply_additive(&base, &delta, 0.5, None);
+        assert!(close(&half[1], &joint(0.5, 15.0)));
+        let masked = super::apply_additive(&base, &delta, 1.0, Some(&[1.0, 0.0]));
+        assert!(close(&masked[1], &base[1]));
+    }
+
+    #[test]
+    fn masks() {
+        let hierarchy = vec![
+            Joint { name: String::from("origin"), index: -1, flag: 0, start_index: 0 },
+            Joint { name: String::from("spine"), index: 0, flag: 0, start_index: 0 },
+            Joint { name: String::from("leg"), index: 0, flag: 0, start_index: 0 },
+            Joint { name: String::from("arm"), index: 1, flag: 0, start_index: 0 },
+            Joint { name: String::from("hand"), index: 3, flag: 0, start_index: 0 },
+        ];
+        let mask = super::mask_from_root(&hierarchy, "spine").unwrap();
+
+        assert_eq!(mask, vec![0.0, 1.0, 0.0, 1.0, 1.0]);
+        assert_eq!(super::invert_mask(&mask), vec![1.0, 0.0, 1.0, 0.0, 0.0]);
+        assert_eq!(super::mask_from_root(&hierarchy, "tail"), None);
+
+        let base = vec![joint(0.0, 0.0); 5];
+        let layer = vec![joint(1.0, 0.0); 5];
+        let pose = super::blend_masked(&base, &layer, 1.0, &mask);
+        assert_eq!(pose.iter().map(|j| j.position.x).collect::<Vec<_>>(), vec![0.0, 1.0, 0.0, 1.0, 1.0]);
+    }
+}
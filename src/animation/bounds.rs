@@ -0,0 +1,117 @@
+#![allow(dead_code)]
+use cgmath::Vector3;
+use std::f32;
+
+use animation::skeleton::{frames_at, frame_skeleton};
+use md5::md5anim::{Md5Anim, Bound};
+use md5::md5mesh::Md5Mesh;
+use vertex_computation::compute::{prepare_skinned_mesh, compute_bounds};
+
+// Frame whose bounds in the file don't match the skinned mesh
+#[derive(Clone, PartialEq, Debug)]
+pub struct BoundsMismatch {
+    pub frame: usize,
+    // None when the file has no bounds for the frame
+    pub stored: Option<(Vector3<f32>, Vector3<f32>)>,
+    pub computed: (Vector3<f32>, Vector3<f32>),
+    // largest distance between a face of the stored box and the same face of the
+    // computed one
+    pub error: f32,
+}
+
+// Bounds of frame `frame` in object space, None when the clip has none for it
+pub fn frame_bounds(anim: &Md5Anim, frame: usize) -> Option<(Vector3<f32>, Vector3<f32>)> {
+    anim.bounds.get(frame).map(|b| (b.bound_min, b.bound_max))
+}
+
+// Bounds at `time` (in seconds), interpolated between the bounds of the two
+// closest frames like the poses are
+pub fn bounds_at(anim: &Md5Anim, time: f32, looping: bool) -> Option<(Vector3<f32>, Vector3<f32>)> {
+    if anim.frames.is_empty() {
+        return None;
+    }
+
+    let (current, next, t) = frames_at(anim, time, looping);
+    match (frame_bounds(anim, current), frame_bounds(anim, next)) {
+        (Some((min_a, max_a)), Some((min_b, max_b))) => Some((min_a + (min_b - min_a) * t, max_a + (max_b - max_a) * t)),
+        (a, _) => a
+    }
+}
+
+// Bounds of `mesh` skinned in each frame of `anim`
+pub fn skinned_bounds(anim: &Md5Anim, mesh: &Md5Mesh) -> Vec<(Vector3<f32>, Vector3<f32>)> {
+    (0..anim.frames.len()).map(|frame| {
+        let (vertices, _, _) = prepare_skinned_mesh(mesh, &frame_skeleton(anim, frame));
+        compute_bounds(&vertices)
+    }).collect()
+}
+
+// Frames of `anim` whose bounds are off by more than `tolerance` from the ones of
+// `mesh` skinned in the frame
+pub fn verify_bounds(anim: &Md5Anim, mesh: &Md5Mesh, tolerance: f32) -> Vec<BoundsMismatch> {
+    skinned_bounds(anim, mesh).into_iter().enumerate().filter_map(|(frame, computed)| {
+        let stored = frame_bounds(anim, frame);
+        let error = match stored {
+            Some((min, max)) => {
+                let (d_min, d_max) = (min - computed.0, max - computed.1);
+                [d_min.x, d_min.y, d_min.z, d_max.x, d_max.y, d_max.z].iter().fold(0.0f32, |e, d| e.max(d.abs()))
+            },
+            None => f32::INFINITY
+        };
+
+        if error > tolerance {
+            Some(BoundsMismatch { frame: frame, stored: stored, computed: computed, error: error })
+        } else {
+            None
+        }
+    }).collect()
+}
+
+// Replaces the bounds of `anim` by the ones of `mesh` skinned in each frame
+pub fn recompute_bounds(anim: &mut Md5Anim, mesh: &Md5Mesh) {
+    anim.bounds = skinned_bounds(anim, mesh).into_iter()
+        .map(|(min, max)| Bound { bound_min: min, bound_max: max })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+    use animation::skeleton::tests::{anim, mesh};
+    use md5::md5anim::Bound;
+
+    #[test]
+    fn bounds_at() {
+        let mut anim = anim();
+        assert_eq!(super::bounds_at(&anim, 0.0, true), None);
+
+        anim.bounds = vec![
+            Bound { bound_min: Vector3::new(0.0, 0.0, 0.0), bound_max: Vector3::new(1.0, 1.0, 1.0) },
+            Bound { bound_min: Vector3::new(2.0, 0.0, 0.0), bound_max: Vector3::new(3.0, 1.0, 2.0) },
+        ];
+        assert_eq!(super::bounds_at(&anim, 0.5 / 24.0, false),
+                   Some((Vector3::new(1.0, 0.0, 0.0), Vector3::new(2.0, 1.0, 1.5))));
+        // looping from the last frame back to the first
+        assert_eq!(super::bounds_at(&anim, 1.5 / 24.0, true),
+                   Some((Vector3::new(1.0, 0.0, 0.0), Vector3::new(2.0, 1.0, 1.5))));
+    }
+
+    #[test]
+    fn verify_bounds() {
+        let mut anim = anim();
+        anim.bounds = vec![
+            Bound { bound_min: Vector3::new(0.0, 0.0, 0.0), bound_max: Vector3::new(1.0, 1.0, 0.0) },
+            Bound { bound_min: Vector3::new(-3.0, -1.0, 0.0), bound_max: Vector3::new(3.0, 1.0, 2.0) },
+        ];
+
+        // the second frame has the mirrored bounds of a symmetric exporter
+        let mismatches = super::verify_bounds(&anim, &mesh(), 1e-4);
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].frame, 1);
+        assert_eq!(mismatches[0].computed, (Vector3::new(0.0, 0.0, 2.0), Vector3::new(3.0, 1.0, 2.0)));
+        assert_eq!(mismatches[0].error, 3.0);
+
+        super::recompute_bounds(&mut anim, &mesh());
+        assert!(super::verify_bounds(&anim, &mesh(), 1e-4).is_empty());
+    }
+}
@@ -0,0 +1,194 @@
+#![allow(dead_code)]
+use std::fmt;
+
+use md5::md5anim::{Md5Anim, Joint as AnimJoint, Frame};
+use md5::md5mesh::Md5Mesh;
+
+// Difference between the hierarchy of an anim and the joints of a mesh
+#[derive(Clone, PartialEq, Debug)]
+pub enum Mismatch {
+    JointCount { anim: usize, mesh: usize },
+    // a joint of both, at different indices
+    Order { name: String, anim: usize, mesh: usize },
+    // joints at the same index, of different names, neither found elsewhere
+    Name { index: usize, anim: String, mesh: String },
+    // a joint of both, under different parents, by name
+    Parent { name: String, anim: Option<String>, mesh: Option<String> },
+    MissingInMesh(String),
+    MissingInAnim(String),
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let parent = |p: &Option<String>| p.clone().unwrap_or(String::from("no parent"));
+
+        match *self {
+            Mismatch::JointCount { anim, mesh } => write!(f, "{} joints in the anim, {} in the mesh", anim, mesh),
+            Mismatch::Order { ref name, anim, mesh } => write!(f, "joint {} is {} in the anim, {} in the mesh", name, anim, mesh),
+            Mismatch::Name { index, ref anim, ref mesh } => write!(f, "joint {} is {} in the anim, {} in the mesh", index, anim, mesh),
+            Mismatch::Parent { ref name, ref anim, ref mesh } =>
+                write!(f, "joint {} is under {} in the anim, {} in the mesh", name, parent(anim), parent(mesh)),
+            Mismatch::MissingInMesh(ref name) => write!(f, "joint {} of the anim is not in the mesh", name),
+            Mismatch::MissingInAnim(ref name) => write!(f, "joint {} of the mesh is not in the anim", name),
+        }
+    }
+}
+
+// Everything preventing `anim` from being played on `mesh` as it is: joint count,
+// names, order and parents. Empty when they match.
+pub fn check_hierarchy(anim: &Md5Anim, mesh: &Md5Mesh) -> Vec<Mismatch> {
+    let mut mismatches = Vec::new();
+    let anim_names = anim.hierarchies.iter().map(|j| j.name.as_str()).collect::<Vec<_>>();
+    let mesh_names = mesh.joints.iter().map(|j| j.name.as_str()).collect::<Vec<_>>();
+
+    if anim_names.len() != mesh_names.len() {
+        mismatches.push(Mismatch::JointCount { anim: anim_names.len(), mesh: mesh_names.len() });
+    }
+
+    for (i, name) in anim_names.iter().enumerate() {
+        match mesh_names.iter().position(|n| n == name) {
+            Some(j) if j != i => mismatches.push(Mismatch::Order { name: name.to_string(), anim: i, mesh: j }),
+            Some(_) => (),
+            None => match mesh_names.get(i) {
+                Some(mesh_name) if !anim_names.contains(mesh_name) =>
+                    mismatches.push(Mismatch::Name { index: i, anim: name.to_string(), mesh: mesh_name.to_string() }),
+                _ => mismatches.push(Mismatch::MissingInMesh(name.to_string()))
+            }
+        }
+    }
+
+    for (i, name) in mesh_names.iter().enumerate() {
+        let renamed = mismatches.iter().any(|m| match *m {
+            Mismatch::Name { index, .. } => index == i,
+            _ => false
+        });
+        if !anim_names.contains(name) && !renamed {
+            mismatches.push(Mismatch::MissingInAnim(name.to_string()));
+        }
+    }
+
+    // parents by name, so that a reordering doesn't show up twice
+    let parent_name = |names: &[&str], parent: i32| if parent >= 0 { names.get(parent as usize).map(|n| n.to_string()) } else { None };
+    for (joint, name) in anim.hierarchies.iter().zip(anim_names.iter()) {
+        if let Some(mesh_joint) = mesh.joints.iter().find(|j| &j.name == name) {
+            let (anim_parent, mesh_parent) = (parent_name(&anim_names, joint.index), parent_name(&mesh_names, mesh_joint.parent_index));
+            if anim_parent != mesh_parent {
+                mismatches.push(Mismatch::Parent { name: name.to_string(), anim: anim_parent, mesh: mesh_parent });
+            }
+        }
+    }
+
+    mismatches
+}
+
+// `anim` with its joints in the order of the joints of `mesh`, matched by name.
+// Fails with the mismatches of `check_hierarchy` that a reordering can't fix:
+// missing joints, different parents or parents coming after their children.
+pub fn remap_by_name(anim: &Md5Anim, mesh: &Md5Mesh) -> Result<Md5Anim, Vec<Mismatch>> {
+    let blocking = check_hierarchy(anim, mesh).into_iter().filter(|m| match *m {
+        Mismatch::Order { .. } | Mismatch::JointCount { .. } => false,
+        _ => true
+    }).collect::<Vec<_>>();
+    if !blocking.is_empty() {
+        return Err(blocking);
+    }
+
+    // anim index of each mesh joint, and the other way around
+    let order = mesh.joints.iter()
+        .map(|j| anim.hierarchies.iter().position(|a| a.name == j.name).unwrap_or(0))
+        .collect::<Vec<_>>();
+    let mut new_index = vec![0; anim.hierarchies.len()];
+    for (i, &old) in order.iter().enumerate() {
+        new_index[old] = i;
+    }
+
+    let mut hierarchies: Vec<AnimJoint> = Vec::with_capacity(order.len());
+    let mut start_index = 0;
+    for (i, &old) in order.iter().enumerate() {
+        let joint = &anim.hierarchies[old];
+        let parent = if joint.index >= 0 { new_index[joint.index as usize] as i32 } else { -1 };
+        if parent >= i as i32 {
+            return Err(vec![Mismatch::Order { name: joint.name.clone(), anim: old, mesh: i }]);
+        }
+
+        hierarchies.push(AnimJoint { name: joint.name.clone(), index: parent, flag: joint.flag, start_index: start_index });
+        start_index += (joint.flag & 63).count_ones() as i32;
+    }
+
+    let frames = anim.frames.iter().map(|frame| {
+        let mut data = Vec::with_capacity(frame.frame_data.len());
+        for &old in &order {
+            let joint = &anim.hierarchies[old];
+            let start = joint.start_index.max(0) as usize;
+            data.extend(frame.frame_data.iter().skip(start).take((joint.flag & 63).count_ones() as usize));
+        }
+        Frame { frame_number: frame.frame_number, frame_data: data }
+    }).collect();
+
+    let mut remapped = anim.clone();
+    remapped.base_frame.position = order.iter().map(|&old| anim.base_frame.position[old]).collect();
+    remapped.base_frame.orientation = order.iter().map(|&old| anim.base_frame.orientation[old]).collect();
+    remapped.hierarchies = hierarchies;
+    remapped.frames = frames;
+    Ok(remapped)
+}
+
+#[cfg(test)]
+mod tests {
+    use animation::skeleton::tests::{anim, mesh};
+    use animation::skeleton::frame_skeleton;
+    use md5::md5anim::Joint;
+    use super::Mismatch;
+
+    #[test]
+    fn check_hierarchy() {
+        let mut mesh = mesh();
+        assert!(super::check_hierarchy(&anim(), &mesh).is_empty());
+
+        mesh.joints[1].name = String::from("hand");
+        assert_eq!(super::check_hierarchy(&anim(), &mesh),
+                   vec![Mismatch::Name { index: 1, anim: String::from("arm"), mesh: String::from("hand") }]);
+
+        mesh.joints[1].name = String::from("arm");
+        mesh.joints.push(mesh.joints[1].clone());
+        mesh.joints[2].name = String::from("hand");
+        mesh.joints[2].parent_index = 1;
+        assert_eq!(super::check_hierarchy(&anim(), &mesh),
+                   vec![Mismatch::JointCount { anim: 2, mesh: 3 }, Mismatch::MissingInAnim(String::from("hand"))]);
+    }
+
+    #[test]
+    fn remap_by_name() {
+        // the anim with a second child of the origin, listed first
+        let mut anim = anim();
+        anim.hierarchies = vec![
+            Joint { name: String::from("origin"), index: -1, flag: 7, start_index: 0 },
+            Joint { name: String::from("leg"), index: 0, flag: 1, start_index: 3 },
+            Joint { name: String::from("arm"), index: 0, flag: 9, start_index: 4 },
+        ];
+        anim.base_frame.position.insert(1, anim.base_frame.position[1] * -1.0);
+        anim.base_frame.orientation.insert(1, anim.base_frame.orientation[1]);
+        for frame in &mut anim.frames {
+            frame.frame_data.insert(3, 5.0);
+        }
+
+        let mut mesh = mesh();
+        mesh.joints.push(mesh.joints[1].clone());
+        mesh.joints[2].name = String::from("leg");
+
+        assert_eq!(super::check_hierarchy(&anim, &mesh),
+                   vec![Mismatch::Order { name: String::from("leg"), anim: 1, mesh: 2 },
+                        Mismatch::Order { name: String::from("arm"), anim: 2, mesh: 1 }]);
+
+        let remapped = super::remap_by_name(&anim, &mesh).unwrap();
+        assert!(super::check_hierarchy(&remapped, &mesh).is_empty());
+        assert_eq!(remapped.hierarchies[2], Joint { name: String::from("leg"), index: 0, flag: 1, start_index: 5 });
+
+        let (before, after) = (frame_skeleton(&anim, 1), frame_skeleton(&remapped, 1));
+        assert_eq!(after[1], before[2]);
+        assert_eq!(after[2], before[1]);
+
+        mesh.joints[2].name = String::from("foot");
+        assert!(super::remap_by_name(&anim, &mesh).is_err());
+    }
+}
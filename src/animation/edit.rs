@@ -0,0 +1,187 @@
+#![allow(dead_code)]
+use md5::md5anim::{Md5Anim, Bound};
+use md5::md5mesh::Md5Mesh;
+use animation::skeleton::{JointTransform, decode_frame, encode_frames, sample_pose};
+use animation::bounds::{bounds_at, recompute_bounds};
+
+// Cutting and joining clips. Each operation renumbers the frames from 0 and
+// recomputes the header counts and start indices. Flags are only ever added, so
+// components left constant stay animated, see `optimize::reduce_channels`.
+// Bounds are kept along with their frames: clips with bounds for every frame give
+// clips that can be saved, clips without give clips without, which
+// `write_md5anim` refuses until `bounds::recompute_bounds` makes them.
+
+// Frames `first` to `last` of `anim`, both included. None for an empty or out of
+// range selection.
+pub fn trim(anim: &Md5Anim, first: usize, last: usize) -> Option<Md5Anim> {
+    if first > last || last >= anim.frames.len() {
+        return None;
+    }
+
+    let poses = (first..last + 1).map(|i| decode_frame(anim, i)).collect::<Vec<_>>();
+    let bounds = if has_bounds(anim) { anim.bounds[first..last + 1].to_vec() } else { Vec::new() };
+    Some(with_frames(anim, &poses, bounds))
+}
+
+// `b` played after `a`, at the frame rate of `a`. When either clip lacks bounds,
+// the bounds of the result are computed on `mesh`. None when their hierarchies
+// differ in names or parents.
+pub fn splice(a: &Md5Anim, b: &Md5Anim, mesh: &Md5Mesh) -> Option<Md5Anim> {
+    let same = a.hierarchies.len() == b.hierarchies.len() &&
+        a.hierarchies.iter().zip(b.hierarchies.iter()).all(|(ja, jb)| ja.name == jb.name && ja.index == jb.index);
+    if !same {
+        return None;
+    }
+
+    let resampled;
+    let b = if b.frame_rate != a.frame_rate {
+        resampled = resample(b, a.frame_rate);
+        &resampled
+    } else {
+        b
+    };
+
+    let poses = decoded(a).into_iter().chain(decoded(b).into_iter()).collect::<Vec<_>>();
+    if has_bounds(a) && has_bounds(b) {
+        let bounds = a.bounds.iter().chain(b.bounds.iter()).cloned().collect();
+        Some(with_frames(a, &poses, bounds))
+    } else {
+        let mut spliced = with_frames(a, &poses, Vec::new());
+        recompute_bounds(&mut spliced, mesh);
+        Some(spliced)
+    }
+}
+
+// `anim` at `frame_rate` frames per second, from its first to its last frame,
+// poses and bounds being interpolated between the original frames. Clips without
+// frames or frame rate are returned as they are.
+pub fn resample(anim: &Md5Anim, frame_rate: i32) -> Md5Anim {
+    if anim.frames.is_empty() || anim.frame_rate <= 0 || frame_rate <= 0 {
+        return anim.clone();
+    }
+
+    let last = (anim.frames.len() - 1) as f32 * frame_rate as f32 / anim.frame_rate as f32;
+    let count = last.round() as usize + 1;
+    let times = (0..count).map(|i| i as f32 / frame_rate as f32).collect::<Vec<_>>();
+
+    let poses = times.iter().map(|&time| sample_pose(anim, time, false)).collect::<Vec<_>>();
+    let bounds = if has_bounds(anim) {
+        times.iter().filter_map(|&time| bounds_at(anim, time, false))
+            .map(|(min, max)| Bound { bound_min: min, bound_max: max })
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let mut resampled = with_frames(anim, &poses, bounds);
+    resampled.frame_rate = frame_rate;
+    resampled
+}
+
+// `anim` played backwards
+pub fn reverse(anim: &Md5Anim) -> Md5Anim {
+    let mut poses = decoded(anim);
+    poses.reverse();
+    let bounds = if has_bounds(anim) { anim.bounds.iter().rev().cloned().collect() } else { Vec::new() };
+    with_frames(anim, &poses, bounds)
+}
+
+fn decoded(anim: &Md5Anim) -> Vec<Vec<JointTransform>> {
+    (0..anim.frames.len()).map(|i| decode_frame(anim, i)).collect()
+}
+
+fn has_bounds(anim: &Md5Anim) -> bool {
+    !anim.frames.is_empty() && anim.bounds.len() == anim.frames.len()
+}
+
+fn with_frames(anim: &Md5Anim, poses: &[Vec<JointTransform>], bounds: Vec<Bound>) -> Md5Anim {
+    let mut edited = anim.clone();
+    encode_frames(&mut edited, poses);
+    edited.num_joints = edited.hierarchies.len() as i32;
+    edited.bounds = bounds;
+    edited
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+    use nom::IResult::Done;
+    use md5::md5anim::{Md5Anim, Bound};
+    use md5::md5anim_parser::parse_anim;
+    use md5::md5anim_writer::write_md5anim;
+    use animation::skeleton::tests::{anim, mesh};
+    use animation::skeleton::decode_frame;
+
+    fn bounded() -> Md5Anim {
+        let mut anim = anim();
+        anim.bounds = (0..2).map(|i| Bound { bound_min: Vector3::new(0.0, 0.0, 0.0), bound_max: Vector3::new(1.0, 1.0, i as f32) }).collect();
+        anim
+    }
+
+    #[test]
+    fn trim() {
+        let anim = bounded();
+        let trimmed = super::trim(&anim, 1, 1).unwrap();
+
+        assert_eq!(trimmed.num_frames, 1);
+        assert_eq!(trimmed.frames[0].frame_number, 0);
+        assert_eq!(decode_frame(&trimmed, 0), decode_frame(&anim, 1));
+        assert_eq!(trimmed.bounds, vec![anim.bounds[1].clone()]);
+        assert_eq!(super::trim(&anim, 1, 2), None);
+    }
+
+    #[test]
+    fn splice() {
+        let (a, b) = (bounded(), anim());
+        let spliced = super::splice(&a, &super::reverse(&a), &mesh()).unwrap();
+
+        assert_eq!(spliced.num_frames, 4);
+        assert_eq!(spliced.frames.iter().map(|f| f.frame_number).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+        assert_eq!(decode_frame(&spliced, 2), decode_frame(&a, 1));
+        assert_eq!(spliced.bounds, a.bounds.iter().chain(a.bounds.iter().rev()).cloned().collect::<Vec<_>>());
+
+        // without bounds for all of them, recomputed, which can be saved
+        let spliced = super::splice(&a, &b, &mesh()).unwrap();
+        assert_eq!(spliced.bounds.len(), 4);
+        let mut out: Vec<u8> = Vec::new();
+        write_md5anim(&mut out, &spliced).unwrap();
+        match parse_anim(&out) {
+            Done(_, parsed) => assert_eq!(parsed.frames, spliced.frames),
+            e => panic!("{:?}", e)
+        }
+
+        let mut other = anim();
+        other.hierarchies[1].name = String::from("leg");
+        assert_eq!(super::splice(&a, &other, &mesh()), None);
+    }
+
+    #[test]
+    fn resample() {
+        let anim = bounded();
+        let resampled = super::resample(&anim, 48);
+
+        // the first frame, the middle and the last frame
+        assert_eq!(resampled.frame_rate, 48);
+        assert_eq!(resampled.num_frames, 3);
+        assert_eq!(decode_frame(&resampled, 1)[0].position, Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(decode_frame(&resampled, 2), decode_frame(&anim, 1));
+        assert_eq!(resampled.bounds[1].bound_max, Vector3::new(1.0, 1.0, 0.5));
+        assert_eq!(resampled.num_animated_components, anim.num_animated_components);
+
+        assert_eq!(super::resample(&resampled, 24).frames, anim.frames);
+
+        let mut still = anim.clone();
+        still.frame_rate = 0;
+        assert_eq!(super::resample(&still, 48), still);
+    }
+
+    #[test]
+    fn reverse() {
+        let anim = bounded();
+        let reversed = super::reverse(&anim);
+
+        assert_eq!(decode_frame(&reversed, 0), decode_frame(&anim, 1));
+        assert_eq!(reversed.bounds[0], anim.bounds[1]);
+        assert_eq!(super::reverse(&reversed), anim);
+    }
+}
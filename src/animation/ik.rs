@@ -0,0 +1,320 @@
+#![allow(dead_code)]
+use cgmath::{Vector3, Quaternion, Rotation, Rotation3, Rad, InnerSpace};
+
+use md5::md5anim::Joint as AnimJoint;
+use md5::md5mesh::Joint;
+use animation::skeleton::{to_object_space, to_local_space};
+use animation::blend::crossfade;
+
+// Inverse kinematics on object space skeletons, as returned by `to_object_space`,
+// joints being moved by rotating them with everything under them. The solvers
+// return the distance left between the end of the chain and the target, None
+// when the joints are not found.
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct IkSettings {
+    pub iterations: usize,
+    // distance to the target under which the chain has reached it
+    pub tolerance: f32,
+    // share of the solved pose in the result, 0 leaving the pose as it is
+    pub weight: f32,
+    // share of its rotation each joint of the chain takes at each CCD step,
+    // missing ones being 1. Stiff joints let the others do the work.
+    pub joint_weights: Vec<f32>,
+}
+
+impl IkSettings {
+    pub fn new() -> IkSettings {
+        IkSettings {
+            iterations: 16,
+            tolerance: 1e-3,
+            weight: 1.0,
+            joint_weights: Vec::new(),
+        }
+    }
+}
+
+// Indices of the joints named `names`, each one being under the previous one
+pub fn find_chain(skeleton: &[Joint], names: &[&str]) -> Option<Vec<usize>> {
+    let mut chain: Vec<usize> = Vec::with_capacity(names.len());
+
+    for name in names {
+        let index = match skeleton.iter().position(|j| &j.name == name) {
+            Some(index) => index,
+            None => return None
+        };
+        if let Some(&previous) = chain.last() {
+            if !is_under(skeleton, index, previous) {
+                return None;
+            }
+        }
+        chain.push(index);
+    }
+    Some(chain)
+}
+
+// Bends `root` and `mid` so that `end` reaches `target`, or points at it when out
+// of reach. The knee or elbow goes towards `pole` when given, otherwise it keeps
+// bending the way it does.
+pub fn two_bone_ik(skeleton: &mut Vec<Joint>, root: &str, mid: &str, end: &str, target: Vector3<f32>, pole: Option<Vector3<f32>>, weight: f32) -> Option<f32> {
+    let chain = match find_chain(skeleton, &[root, mid, end]) {
+        Some(chain) => chain,
+        None => return None
+    };
+    let (ia, ib, ic) = (chain[0], chain[1], chain[2]);
+    let original = skeleton.clone();
+
+    let (a, b, c) = (skeleton[ia].position, skeleton[ib].position, skeleton[ic].position);
+    let (lab, lcb) = ((b - a).magnitude(), (c - b).magnitude());
+    let lat = (target - a).magnitude().max(1e-4).min(lab + lcb - 1e-4);
+
+    // normal of the plane of the chain, the pole one for a straight chain
+    let mut axis = (c - a).cross(b - a);
+    if axis.magnitude2() < 1e-10 {
+        axis = (c - a).cross(pole.unwrap_or(Vector3::unit_z()) - a);
+    }
+    if axis.magnitude2() < 1e-10 {
+        axis = (c - a).cross(Vector3::unit_x());
+    }
+    let axis = axis.normalize();
+
+    let angle = |u: Vector3<f32>, v: Vector3<f32>| u.normalize().dot(v.normalize()).max(-1.0).min(1.0).acos();
+    let cosine = |opposite: f32, x: f32, y: f32| ((x * x + y * y - opposite * opposite) / (2.0 * x * y)).max(-1.0).min(1.0).acos();
+
+    // opening the angles at the root and the mid joint to the lengths of the triangle
+    let root_delta = cosine(lcb, lab, lat) - angle(c - a, b - a);
+    let mid_delta = cosine(lat, lab, lcb) - angle(a - b, c - b);
+    rotate_joint(skeleton, ia, Quaternion::from_axis_angle(axis, Rad(root_delta)));
+    rotate_joint(skeleton, ib, Quaternion::from_axis_angle(axis, Rad(mid_delta)));
+
+    let c = skeleton[ic].position;
+    rotate_joint(skeleton, ia, rotation_between(c - a, target - a));
+
+    if let Some(pole) = pole {
+        // turning the chain around its own line, towards the pole
+        let line = (target - a).normalize();
+        let flat = |v: Vector3<f32>| v - line * v.dot(line);
+        let (to_mid, to_pole) = (flat(skeleton[ib].position - a), flat(pole - a));
+        if to_mid.magnitude2() > 1e-10 && to_pole.magnitude2() > 1e-10 {
+            let twist = line.dot(to_mid.cross(to_pole)).atan2(to_mid.dot(to_pole));
+            rotate_joint(skeleton, ia, Quaternion::from_axis_angle(line, Rad(twist)));
+        }
+    }
+
+    Some(finish(skeleton, &original, ic, target, weight))
+}
+
+// Cyclic coordinate descent: from the end of `chain` back to its start, each joint
+// turns to point the end at `target`, until it is reached or out of iterations
+pub fn ccd_ik(skeleton: &mut Vec<Joint>, chain: &[&str], target: Vector3<f32>, settings: &IkSettings) -> Option<f32> {
+    let chain = match find_chain(skeleton, chain) {
+        Some(ref chain) if chain.len() >= 2 => chain.clone(),
+        _ => return None
+    };
+    let end = chain[chain.len() - 1];
+    let original = skeleton.clone();
+    let identity = Quaternion::new(1.0, 0.0, 0.0, 0.0);
+
+    for _ in 0..settings.iterations {
+        if (skeleton[end].position - target).magnitude() < settings.tolerance {
+            break;
+        }
+        for (k, &joint) in chain.iter().enumerate().rev().skip(1) {
+            let pivot = skeleton[joint].position;
+            let rotation = rotation_between(skeleton[end].position - pivot, target - pivot);
+            let weight = settings.joint_weights.get(k).cloned().unwrap_or(1.0);
+            rotate_joint(skeleton, joint, identity.nlerp(rotation, weight));
+        }
+    }
+
+    Some(finish(skeleton, &original, end, target, settings.weight))
+}
+
+// Forward and backward reaching: the joint positions are solved keeping their
+// distances, then each joint turns to point at the next one
+pub fn fabrik_ik(skeleton: &mut Vec<Joint>, chain: &[&str], target: Vector3<f32>, settings: &IkSettings) -> Option<f32> {
+    let chain = match find_chain(skeleton, chain) {
+        Some(ref chain) if chain.len() >= 2 => chain.clone(),
+        _ => return None
+    };
+    let end = chain[chain.len() - 1];
+    let original = skeleton.clone();
+
+    let mut points = chain.iter().map(|&i| skeleton[i].position).collect::<Vec<_>>();
+    let lengths = points.windows(2).map(|p| (p[1] - p[0]).magnitude()).collect::<Vec<_>>();
+    let start = points[0];
+    let n = points.len();
+
+    if (target - start).magnitude() >= lengths.iter().sum::<f32>() {
+        // out of reach, stretched towards it
+        let direction = (target - start).normalize();
+        for i in 1..n {
+            points[i] = points[i - 1] + direction * lengths[i - 1];
+        }
+    } else {
+        for _ in 0..settings.iterations {
+            if (points[n - 1] - target).magnitude() < settings.tolerance {
+                break;
+            }
+            points[n - 1] = target;
+            for i in (0..n - 1).rev() {
+                points[i] = points[i + 1] + (points[i] - points[i + 1]).normalize() * lengths[i];
+            }
+            points[0] = start;
+            for i in 1..n {
+                points[i] = points[i - 1] + (points[i] - points[i - 1]).normalize() * lengths[i - 1];
+            }
+        }
+    }
+
+    for i in 0..n - 1 {
+        let (joint, next) = (chain[i], chain[i + 1]);
+        let (pivot, current) = (skeleton[joint].position, skeleton[next].position);
+        rotate_joint(skeleton, joint, rotation_between(current - pivot, points[i + 1] - pivot));
+    }
+
+    Some(finish(skeleton, &original, end, target, settings.weight))
+}
+
+// Rotates joint `index` and the joints under it around the joint
+fn rotate_joint(skeleton: &mut [Joint], index: usize, rotation: Quaternion<f32>) {
+    let pivot = skeleton[index].position;
+    let mut moved = vec![false; skeleton.len()];
+
+    // parents come before their children
+    for i in index..skeleton.len() {
+        let parent = skeleton[i].parent_index;
+        moved[i] = i == index || (parent >= 0 && moved[parent as usize]);
+        if moved[i] {
+            skeleton[i].position = pivot + rotation.rotate_vector(skeleton[i].position - pivot);
+            skeleton[i].orientation = (rotation * skeleton[i].orientation).normalize();
+        }
+    }
+}
+
+// Shortest rotation taking the direction of `from` to that of `to`. Unlike
+// `Quaternion::from_arc`, small angles aren't rounded to no rotation, which
+// would stall the solvers close to the target.
+fn rotation_between(from: Vector3<f32>, to: Vector3<f32>) -> Quaternion<f32> {
+    let (dot, cross) = (from.dot(to), from.cross(to));
+    let length = (from.magnitude2() * to.magnitude2()).sqrt();
+
+    if length < 1e-12 || (dot > 0.0 && cross.magnitude2() < 1e-24) {
+        Quaternion::new(1.0, 0.0, 0.0, 0.0)
+    } else if dot < 0.0 && cross.magnitude2() < 1e-12 * length * length {
+        Quaternion::from_arc(from, to, None)
+    } else {
+        Quaternion::from_sv(length + dot, cross).normalize()
+    }
+}
+
+fn is_under(skeleton: &[Joint], joint: usize, ancestor: usize) -> bool {
+    let mut parent = skeleton[joint].parent_index;
+    while parent >= 0 {
+        if parent as usize == ancestor {
+            return true;
+        }
+        parent = skeleton[parent as usize].parent_index;
+    }
+    false
+}
+
+// Blends the solved `skeleton` with `original` by `weight`, in local space so
+// that bones keep their lengths, and returns the distance of `end` to `target`
+fn finish(skeleton: &mut Vec<Joint>, original: &[Joint], end: usize, target: Vector3<f32>, weight: f32) -> f32 {
+    if weight < 1.0 {
+        let hierarchy = original.iter()
+            .map(|j| AnimJoint { name: j.name.clone(), index: j.parent_index, flag: 0, start_index: 0 })
+            .collect::<Vec<_>>();
+        let blended = crossfade(&to_local_space(original), &to_local_space(skeleton), weight.max(0.0));
+        *skeleton = to_object_space(&hierarchy, &blended);
+    }
+    (skeleton[end].position - target).magnitude()
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector3, Quaternion, InnerSpace};
+    use md5::md5mesh::Joint;
+    use super::IkSettings;
+
+    // a leg along -Z, hip to knee to ankle, with a toe in front of the ankle
+    fn leg() -> Vec<Joint> {
+        let joint = |name: &str, parent_index: i32, position: Vector3<f32>| Joint {
+            name: String::from(name),
+            parent_index: parent_index,
+            position: position,
+            orientation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+        };
+        vec![
+            joint("thigh.L", -1, Vector3::new(0.0, 0.0, 2.0)),
+            joint("shin.L", 0, Vector3::new(0.0, 0.1, 1.0)),
+            joint("ankle.L", 1, Vector3::new(0.0, 0.0, 0.0)),
+            joint("toe.L", 2, Vector3::new(0.0, 0.3, 0.0)),
+        ]
+    }
+
+    fn lengths(skeleton: &[Joint]) -> Vec<f32> {
+        skeleton.windows(2).map(|j| (j[1].position - j[0].position).magnitude()).collect()
+    }
+
+    #[test]
+    fn find_chain() {
+        let leg = leg();
+        assert_eq!(super::find_chain(&leg, &["thigh.L", "ankle.L"]), Some(vec![0, 2]));
+        assert_eq!(super::find_chain(&leg, &["ankle.L", "thigh.L"]), None);
+        assert_eq!(super::find_chain(&leg, &["thigh.L", "foot.L"]), None);
+    }
+
+    #[test]
+    fn two_bone_ik() {
+        let mut skeleton = leg();
+        let target = Vector3::new(0.5, 0.0, 0.5);
+        let pole = Vector3::new(0.0, 5.0, 1.0);
+
+        let distance = super::two_bone_ik(&mut skeleton, "thigh.L", "shin.L", "ankle.L", target, Some(pole), 1.0).unwrap();
+        assert!(distance < 1e-4);
+        assert!((lengths(&skeleton)[0] - lengths(&leg())[0]).abs() < 1e-4);
+        // the knee still points forward
+        assert!(skeleton[1].position.y > 0.1);
+        // the toe follows the ankle
+        assert!(((skeleton[3].position - skeleton[2].position).magnitude() - 0.3).abs() < 1e-4);
+
+        // half way between the pose and the solve
+        let mut half = leg();
+        super::two_bone_ik(&mut half, "thigh.L", "shin.L", "ankle.L", target, Some(pole), 0.5).unwrap();
+        assert!((half[2].position - target).magnitude() > 1e-2);
+        assert!((lengths(&half)[1] - lengths(&leg())[1]).abs() < 1e-4);
+
+        // out of reach, pointing at the target
+        let mut far = leg();
+        let distance = super::two_bone_ik(&mut far, "thigh.L", "shin.L", "ankle.L", Vector3::new(5.0, 0.0, 2.0), None, 1.0).unwrap();
+        assert!(distance > 2.9);
+        assert!(far[2].position.x > 1.9);
+    }
+
+    #[test]
+    fn chain_solvers() {
+        let target = Vector3::new(0.6, 0.2, 0.4);
+        let chain = ["thigh.L", "shin.L", "ankle.L"];
+
+        // CCD creeps in on a nearly straight chain
+        let mut settings = IkSettings::new();
+        settings.iterations = 64;
+        let mut ccd = leg();
+        assert!(super::ccd_ik(&mut ccd, &chain, target, &settings).unwrap() < 1e-3);
+        let mut fabrik = leg();
+        assert!(super::fabrik_ik(&mut fabrik, &chain, target, &IkSettings::new()).unwrap() < 1e-3);
+        for solved in &[ccd, fabrik] {
+            for (a, b) in lengths(solved).iter().zip(lengths(&leg()).iter()) {
+                assert!((a - b).abs() < 1e-4);
+            }
+        }
+
+        // a stiff knee and a single iteration leave it short
+        settings.iterations = 1;
+        settings.joint_weights = vec![0.5, 0.0];
+        let mut stiff = leg();
+        assert!(super::ccd_ik(&mut stiff, &chain, target, &settings).unwrap() > 1e-2);
+    }
+}
@@ -0,0 +1,197 @@
+#![allow(dead_code)]
+use cgmath::{Vector3, Quaternion, InnerSpace};
+
+use md5::md5anim::{Md5Anim, Joint as AnimJoint, Bound};
+use md5::md5mesh::Joint;
+use animation::skeleton::{JointTransform, decode_frame, encode_frames, to_object_space, to_local_space, stored_orientation};
+
+// Plane through the origin the anim is mirrored across, by its normal
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum MirrorPlane {
+    // left and right along X, as for Bob
+    YZ,
+    XZ,
+    XY,
+}
+
+impl MirrorPlane {
+    fn normal(&self) -> Vector3<f32> {
+        match *self {
+            MirrorPlane::YZ => Vector3::unit_x(),
+            MirrorPlane::XZ => Vector3::unit_y(),
+            MirrorPlane::XY => Vector3::unit_z(),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct MirrorOptions {
+    // left and right suffixes of paired joints, such as upperarm.L and upperarm.R
+    pub suffixes: Vec<(String, String)>,
+    pub plane: MirrorPlane,
+}
+
+impl MirrorOptions {
+    pub fn new() -> MirrorOptions {
+        MirrorOptions {
+            suffixes: vec![(String::from(".L"), String::from(".R")), (String::from("_L"), String::from("_R"))],
+            plane: MirrorPlane::YZ,
+        }
+    }
+}
+
+// Index of the joint paired with each joint of `hierarchy`, joints without a pair
+// being their own
+pub fn mirror_pairs(hierarchy: &[AnimJoint], suffixes: &[(String, String)]) -> Vec<usize> {
+    hierarchy.iter().enumerate().map(|(i, joint)| {
+        let other = suffixes.iter().filter_map(|&(ref left, ref right)| {
+            if joint.name.ends_with(left.as_str()) {
+                Some(format!("{}{}", &joint.name[..joint.name.len() - left.len()], right))
+            } else if joint.name.ends_with(right.as_str()) {
+                Some(format!("{}{}", &joint.name[..joint.name.len() - right.len()], left))
+            } else {
+                None
+            }
+        }).next();
+
+        other.and_then(|name| hierarchy.iter().position(|j| j.name == name)).unwrap_or(i)
+    }).collect()
+}
+
+// `anim` reflected across `options.plane`, each joint playing the reflected motion
+// of its pair. The base frame is taken as the rest pose, so that joints whose
+// rest orientations aren't reflections of each other still turn the mirrored way.
+pub fn mirror_anim(anim: &Md5Anim, options: &MirrorOptions) -> Md5Anim {
+    let pairs = mirror_pairs(&anim.hierarchies, &options.suffixes);
+    let normal = options.plane.normal();
+
+    let rest_local = anim.base_frame.position.iter().zip(anim.base_frame.orientation.iter())
+        .map(|(&position, &orientation)| JointTransform { position: position, orientation: orientation })
+        .collect::<Vec<_>>();
+    let rest = to_object_space(&anim.hierarchies, &rest_local);
+
+    let mirror_pose = |local: &[JointTransform]| -> Vec<JointTransform> {
+        let skeleton = to_object_space(&anim.hierarchies, local);
+        let mirrored = skeleton.iter().enumerate().map(|(i, joint)| {
+            let pair = pairs[i];
+            // from the reflected rest of the pair to the rest of the joint
+            let correction = reflect_orientation(rest[pair].orientation, normal).conjugate() * rest[i].orientation;
+            Joint {
+                name: joint.name.clone(),
+                parent_index: joint.parent_index,
+                position: reflect(skeleton[pair].position, normal),
+                orientation: (reflect_orientation(skeleton[pair].orientation, normal) * correction).normalize(),
+            }
+        }).collect::<Vec<_>>();
+        to_local_space(&mirrored)
+    };
+
+    let mut mirrored = anim.clone();
+    let base = mirror_pose(&rest_local);
+    mirrored.base_frame.position = base.iter().map(|t| t.position).collect();
+    mirrored.base_frame.orientation = base.iter().map(|t| stored_orientation(t.orientation)).collect();
+
+    let poses = (0..anim.frames.len()).map(|i| mirror_pose(&decode_frame(anim, i))).collect::<Vec<_>>();
+    encode_frames(&mut mirrored, &poses);
+
+    mirrored.bounds = anim.bounds.iter().map(|bound| {
+        let (a, b) = (reflect(bound.bound_min, normal), reflect(bound.bound_max, normal));
+        Bound {
+            bound_min: Vector3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z)),
+            bound_max: Vector3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z)),
+        }
+    }).collect();
+    mirrored
+}
+
+fn reflect(v: Vector3<f32>, normal: Vector3<f32>) -> Vector3<f32> {
+    v - normal * (2.0 * v.dot(normal))
+}
+
+// Rotation seen in the mirror, reflection * rotation * reflection
+fn reflect_orientation(q: Quaternion<f32>, normal: Vector3<f32>) -> Quaternion<f32> {
+    Quaternion::from_sv(q.s, -reflect(q.v, normal))
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector3, Quaternion, Rotation3, Deg, InnerSpace};
+    use md5::md5anim::Joint;
+    use md5::md5mesh::Joint as MeshJoint;
+    use animation::skeleton::tests::anim;
+    use animation::skeleton::{encode_frames, decode_frame, frame_skeleton};
+    use super::MirrorOptions;
+
+    #[test]
+    fn mirror_pairs() {
+        let joint = |name: &str| Joint { name: String::from(name), index: -1, flag: 0, start_index: 0 };
+        let hierarchy = vec![joint("spine"), joint("arm.R"), joint("hand_L"), joint("arm.L"), joint("leg.L")];
+
+        assert_eq!(super::mirror_pairs(&hierarchy, &MirrorOptions::new().suffixes), vec![0, 3, 2, 1, 4]);
+    }
+
+    #[test]
+    fn mirror_anim() {
+        // an origin with two arms reaching out along X, the left one raised
+        let mut anim = anim();
+        anim.hierarchies = vec![
+            Joint { name: String::from("origin"), index: -1, flag: 0, start_index: 0 },
+            Joint { name: String::from("arm.L"), index: 0, flag: 0, start_index: 0 },
+            Joint { name: String::from("arm.R"), index: 0, flag: 0, start_index: 0 },
+        ];
+        anim.base_frame.position = vec![Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 1.0), Vector3::new(-1.0, 0.0, 1.0)];
+        anim.base_frame.orientation = vec![Quaternion::new(1.0, 0.0, 0.0, 0.0); 3];
+        let rest = decode_frame(&anim, 0);
+        let mut raised = rest.clone();
+        raised[1].orientation = Quaternion::from_angle_y(Deg(-45.0));
+        encode_frames(&mut anim, &[rest, raised]);
+
+        let mirrored = super::mirror_anim(&anim, &MirrorOptions::new());
+        let (before, after) = (frame_skeleton(&anim, 1), frame_skeleton(&mirrored, 1));
+
+        // the right arm is raised the same way, the left one back to rest
+        let tip = |joint: &MeshJoint| joint.position + joint.orientation * Vector3::new(1.0, 0.0, 0.0);
+        assert!((tip(&before[1]) - Vector3::new(2.0f32.sqrt() / 2.0 + 1.0, 0.0, 1.0 + 2.0f32.sqrt() / 2.0)).magnitude() < 1e-5);
+        assert!(after[1].orientation.dot(Quaternion::new(1.0, 0.0, 0.0, 0.0)).abs() > 1.0 - 1e-5);
+        assert!((after[2].position - Vector3::new(-1.0, 0.0, 1.0)).magnitude() < 1e-5);
+        let reflected = tip(&before[1]);
+        let mirrored_tip = after[2].position + after[2].orientation * Vector3::new(-1.0, 0.0, 0.0);
+        assert!((mirrored_tip - Vector3::new(-reflected.x, reflected.y, reflected.z)).magnitude() < 1e-5);
+
+        // twice is the original
+        let back = super::mirror_anim(&mirrored, &MirrorOptions::new());
+        for (a, b) in frame_skeleton(&back, 1).iter().zip(before.iter()) {
+            assert!((a.position - b.position).magnitude() < 1e-5);
+            assert!(a.orientation.dot(b.orientation).abs() > 1.0 - 1e-5);
+        }
+    }
+
+    #[test]
+    fn rest_orientation() {
+        // arms turned away from each other at rest, the left one raised in frame 1
+        let mut anim = anim();
+        anim.hierarchies = vec![
+            Joint { name: String::from("origin"), index: -1, flag: 0, start_index: 0 },
+            Joint { name: String::from("arm.L"), index: 0, flag: 0, start_index: 0 },
+            Joint { name: String::from("arm.R"), index: 0, flag: 0, start_index: 0 },
+        ];
+        anim.base_frame.position = vec![Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 1.0), Vector3::new(-1.0, 0.0, 1.0)];
+        anim.base_frame.orientation = vec![Quaternion::new(1.0, 0.0, 0.0, 0.0), Quaternion::from_angle_z(Deg(30.0)), Quaternion::from_angle_z(Deg(-30.0))];
+        let rest = decode_frame(&anim, 0);
+        let mut raised = rest.clone();
+        raised[1].orientation = Quaternion::from_angle_y(Deg(-45.0)) * rest[1].orientation;
+        encode_frames(&mut anim, &[rest, raised]);
+
+        let mirrored = super::mirror_anim(&anim, &MirrorOptions::new());
+        assert!(mirrored.base_frame.orientation.iter().all(|q| q.s <= 0.0));
+
+        // joints at rest keep their rest orientation
+        let (rest, before, after) = (frame_skeleton(&anim, 0), frame_skeleton(&anim, 1), frame_skeleton(&mirrored, 1));
+        for (a, b) in frame_skeleton(&mirrored, 0).iter().zip(rest.iter()) {
+            assert!(a.orientation.dot(b.orientation).abs() > 1.0 - 1e-5);
+        }
+        assert!(after[1].orientation.dot(rest[1].orientation).abs() > 1.0 - 1e-5);
+        let reflected = super::reflect_orientation(before[1].orientation, Vector3::unit_x());
+        assert!(after[2].orientation.dot(reflected).abs() > 1.0 - 1e-5);
+    }
+}
@@ -0,0 +1,12 @@
+pub mod skeleton;
+pub mod player;
+pub mod bounds;
+pub mod compat;
+pub mod blend;
+pub mod state_machine;
+pub mod root_motion;
+pub mod retarget;
+pub mod ik;
+pub mod mirror;
+pub mod edit;
+pub mod optimize;
\ No newline at end of file
@@ -0,0 +1,154 @@
+#![allow(dead_code)]
+use std::fmt;
+use std::f32;
+use cgmath::Vector3;
+
+use md5::md5anim::{Md5Anim, Frame};
+use md5::md5anim_writer::write_md5anim;
+use animation::skeleton::{quaternion_from_xyz, quaternion_to_xyz,
+                          POSITION_X, POSITION_Y, POSITION_Z, ORIENTATION_X, ORIENTATION_Y, ORIENTATION_Z};
+
+const FLAGS: [i32; 6] = [POSITION_X, POSITION_Y, POSITION_Z, ORIENTATION_X, ORIENTATION_Y, ORIENTATION_Z];
+
+// Size of an anim before and after `reduce_channels`
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Reduction {
+    pub components_before: usize,
+    pub components_after: usize,
+    // as written by `write_md5anim`, 0 for anims it refuses
+    pub bytes_before: usize,
+    pub bytes_after: usize,
+}
+
+impl Reduction {
+    // Share of the file left, 1 when nothing was removed
+    pub fn ratio(&self) -> f32 {
+        if self.bytes_before == 0 { 1.0 } else { self.bytes_after as f32 / self.bytes_before as f32 }
+    }
+}
+
+impl fmt::Display for Reduction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} -> {} animated components, {} -> {} bytes ({:.0}%)",
+               self.components_before, self.components_after,
+               self.bytes_before, self.bytes_after, self.ratio() * 100.0)
+    }
+}
+
+// `anim` without the animated components that stay within `tolerance` over the
+// whole clip: their flag bits are cleared and their value, the middle of their
+// range, goes to the base frame. Frames keep the other components, at new start
+// indices.
+pub fn reduce_channels(anim: &Md5Anim, tolerance: f32) -> (Md5Anim, Reduction) {
+    let mut reduced = anim.clone();
+    let mut start_index = 0;
+
+    for (i, joint) in reduced.hierarchies.iter_mut().enumerate() {
+        let base_position = anim.base_frame.position[i];
+        let base_orientation = quaternion_to_xyz(anim.base_frame.orientation[i]);
+        let mut base = [base_position.x, base_position.y, base_position.z, base_orientation.x, base_orientation.y, base_orientation.z];
+
+        let mut k = joint.start_index.max(0) as usize;
+        for c in 0..6 {
+            if joint.flag & FLAGS[c] == 0 {
+                continue;
+            }
+
+            let (min, max) = anim.frames.iter()
+                .filter_map(|frame| frame.frame_data.get(k))
+                .fold((f32::MAX, f32::MIN), |(min, max), &v| (min.min(v), max.max(v)));
+            if max - min <= tolerance {
+                joint.flag &= !FLAGS[c];
+                base[c] = if min <= max { (min + max) * 0.5 } else { base[c] };
+            }
+            k += 1;
+        }
+
+        reduced.base_frame.position[i] = Vector3::new(base[0], base[1], base[2]);
+        reduced.base_frame.orientation[i] = quaternion_from_xyz(base[3], base[4], base[5]);
+        joint.start_index = start_index;
+        start_index += (joint.flag & 63).count_ones() as i32;
+    }
+
+    // values of the components still animated, in the same order
+    reduced.frames = anim.frames.iter().map(|frame| {
+        let mut data = Vec::with_capacity(start_index as usize);
+        for (old, new) in anim.hierarchies.iter().zip(reduced.hierarchies.iter()) {
+            let mut k = old.start_index.max(0) as usize;
+            for c in 0..6 {
+                if old.flag & FLAGS[c] == 0 {
+                    continue;
+                }
+                if new.flag & FLAGS[c] != 0 {
+                    data.push(frame.frame_data.get(k).cloned().unwrap_or(0.0));
+                }
+                k += 1;
+            }
+        }
+        Frame { frame_number: frame.frame_number, frame_data: data }
+    }).collect();
+    reduced.num_animated_components = start_index;
+
+    let reduction = Reduction {
+        components_before: anim.num_animated_components.max(0) as usize,
+        components_after: start_index as usize,
+        bytes_before: written_size(anim),
+        bytes_after: written_size(&reduced),
+    };
+    (reduced, reduction)
+}
+
+fn written_size(anim: &Md5Anim) -> usize {
+    let mut out: Vec<u8> = Vec::new();
+    match write_md5anim(&mut out, anim) {
+        Ok(()) => out.len(),
+        Err(_) => 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector3, InnerSpace};
+    use md5::md5anim::{Joint, Bound};
+    use animation::skeleton::tests::anim;
+    use animation::skeleton::decode_frame;
+
+    #[test]
+    fn reduce_channels() {
+        let mut anim = anim();
+        anim.bounds = vec![Bound { bound_min: Vector3::new(0.0, 0.0, 0.0), bound_max: Vector3::new(1.0, 1.0, 1.0) }; 2];
+        let (reduced, reduction) = super::reduce_channels(&anim, 0.0);
+
+        // only the origin Z and the arm X positions move
+        assert_eq!(reduced.hierarchies, vec![
+            Joint { name: String::from("origin"), index: -1, flag: 4, start_index: 0 },
+            Joint { name: String::from("arm"), index: 0, flag: 1, start_index: 1 },
+        ]);
+        assert_eq!(reduced.num_animated_components, 2);
+        assert_eq!(reduced.frames[1].frame_data, vec![2.0, 3.0]);
+        for i in 0..2 {
+            assert_eq!(decode_frame(&reduced, i), decode_frame(&anim, i));
+        }
+        assert_eq!((reduction.components_before, reduction.components_after), (5, 2));
+        assert!(reduction.bytes_after < reduction.bytes_before);
+
+        // nothing left to remove
+        let (again, reduction) = super::reduce_channels(&reduced, 0.0);
+        assert_eq!(again, reduced);
+        assert_eq!(reduction.ratio(), 1.0);
+    }
+
+    #[test]
+    fn tolerance() {
+        let mut anim = anim();
+        anim.frames[1].frame_data[4] = 0.001;
+
+        assert_eq!(super::reduce_channels(&anim, 1e-4).0.hierarchies[1].flag, 9);
+        let (reduced, _) = super::reduce_channels(&anim, 1e-2);
+        assert_eq!(reduced.hierarchies[1].flag, 1);
+        // the middle of the range
+        let orientation = decode_frame(&reduced, 1)[1].orientation;
+        assert!((orientation.v.x - 0.0005).abs() < 1e-6);
+        assert!(orientation.magnitude() > 1.0 - 1e-6);
+    }
+}
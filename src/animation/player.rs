@@ -0,0 +1,226 @@
+#![allow(dead_code)]
+use winit;
+
+use md5::md5anim::Md5Anim;
+use md5::md5mesh::Joint;
+use animation::skeleton::{frames_at, sample_pose, to_object_space};
+
+const SPEED_STEP: f32 = 1.25;
+
+// Playback state of the viewer over the loaded clips
+pub struct AnimationPlayer {
+    pub clips: Vec<Md5Anim>,
+    pub current: usize,
+    pub time: f32,
+    pub playing: bool,
+    pub speed: f32,
+    pub looping: bool,
+}
+
+impl AnimationPlayer {
+    pub fn new(clips: Vec<Md5Anim>) -> AnimationPlayer {
+        AnimationPlayer {
+            clips: clips,
+            current: 0,
+            time: 0.0,
+            playing: true,
+            speed: 1.0,
+            looping: true,
+        }
+    }
+
+    pub fn clip(&self) -> Option<&Md5Anim> {
+        self.clips.get(self.current)
+    }
+
+    // 0 for clips without frames or frame rate
+    pub fn duration(&self) -> f32 {
+        match self.clip() {
+            Some(anim) if anim.frame_rate > 0 => anim.frames.len() as f32 / anim.frame_rate as f32,
+            _ => 0.0
+        }
+    }
+
+    // Advances the clip by `dt` seconds scaled by the speed
+    pub fn update(&mut self, dt: f32) {
+        let duration = self.duration();
+        if !self.playing || duration <= 0.0 {
+            return;
+        }
+
+        self.time += dt * self.speed;
+
+        if self.looping {
+            self.time = ((self.time % duration) + duration) % duration;
+        } else if self.at_end() {
+            self.time = self.time.max(0.0).min(duration);
+            self.playing = false;
+        }
+    }
+
+    pub fn current_frame(&self) -> usize {
+        match self.clip() {
+            Some(anim) => frames_at(anim, self.time, self.looping).0,
+            None => 0
+        }
+    }
+
+    // Pauses and moves by whole frames
+    pub fn step(&mut self, frames: i32) {
+        let (count, frame_rate) = match self.clip() {
+            Some(anim) if !anim.frames.is_empty() && anim.frame_rate > 0 => (anim.frames.len() as i32, anim.frame_rate as f32),
+            _ => return
+        };
+
+        let mut frame = self.current_frame() as i32 + frames;
+        frame = if self.looping { ((frame % count) + count) % count } else { frame.max(0).min(count - 1) };
+
+        self.playing = false;
+        self.time = frame as f32 / frame_rate;
+    }
+
+    // Whether a clip not looping can't go further in the direction of the speed
+    fn at_end(&self) -> bool {
+        if self.speed < 0.0 { self.time <= 0.0 } else { self.time >= self.duration() }
+    }
+
+    // Playing again a clip not looping that reached its end starts it over
+    pub fn toggle_play(&mut self) {
+        self.playing = !self.playing;
+
+        if self.playing && !self.looping && self.at_end() {
+            self.time = if self.speed < 0.0 { self.duration() } else { 0.0 };
+        }
+    }
+
+    pub fn toggle_loop(&mut self) {
+        self.looping = !self.looping;
+    }
+
+    pub fn scale_speed(&mut self, factor: f32) {
+        self.speed *= factor;
+    }
+
+    pub fn select_clip(&mut self, index: usize) {
+        if index < self.clips.len() {
+            self.current = index;
+            self.time = 0.0;
+        }
+    }
+
+    pub fn next_clip(&mut self) {
+        if !self.clips.is_empty() {
+            let next = (self.current + 1) % self.clips.len();
+            self.select_clip(next);
+        }
+    }
+
+    // Object space skeleton at the current time, None without a clip or frames
+    pub fn skeleton(&self) -> Option<Vec<Joint>> {
+        match self.clip() {
+            Some(anim) if !anim.frames.is_empty() => Some(to_object_space(&anim.hierarchies, &sample_pose(anim, self.time, self.looping))),
+            _ => None
+        }
+    }
+
+    pub fn status(&self) -> String {
+        match self.clip() {
+            Some(anim) => format!("clip {}/{} - frame {}/{} - x{:.2}{}{}",
+                                  self.current + 1, self.clips.len(),
+                                  self.current_frame(), anim.frames.len(),
+                                  self.speed,
+                                  if self.looping { " - loop" } else { "" },
+                                  if self.playing { "" } else { " - paused" }),
+            None => String::from("bind pose")
+        }
+    }
+
+    // Space plays or pauses, period and comma step a frame, +/- change the speed,
+    // L toggles looping, Tab or the number keys switch clips.
+    // Returns whether the event was used.
+    pub fn handle_event(&mut self, event: &winit::WindowEvent) -> bool {
+        match *event {
+            winit::WindowEvent::KeyboardInput { input: winit::KeyboardInput { state: winit::ElementState::Pressed, virtual_keycode: Some(key), .. }, .. } => {
+                match key {
+                    winit::VirtualKeyCode::Space => self.toggle_play(),
+                    winit::VirtualKeyCode::Period => self.step(1),
+                    winit::VirtualKeyCode::Comma => self.step(-1),
+                    winit::VirtualKeyCode::Add | winit::VirtualKeyCode::Equals => self.scale_speed(SPEED_STEP),
+                    winit::VirtualKeyCode::Subtract | winit::VirtualKeyCode::Minus => self.scale_speed(1.0 / SPEED_STEP),
+                    winit::VirtualKeyCode::L => self.toggle_loop(),
+                    winit::VirtualKeyCode::Tab => self.next_clip(),
+                    winit::VirtualKeyCode::Key1 => self.select_clip(0),
+                    winit::VirtualKeyCode::Key2 => self.select_clip(1),
+                    winit::VirtualKeyCode::Key3 => self.select_clip(2),
+                    winit::VirtualKeyCode::Key4 => self.select_clip(3),
+                    winit::VirtualKeyCode::Key5 => self.select_clip(4),
+                    winit::VirtualKeyCode::Key6 => self.select_clip(5),
+                    winit::VirtualKeyCode::Key7 => self.select_clip(6),
+                    winit::VirtualKeyCode::Key8 => self.select_clip(7),
+                    winit::VirtualKeyCode::Key9 => self.select_clip(8),
+                    _ => return false
+                }
+                true
+            },
+            _ => false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use animation::skeleton::tests::anim;
+
+    #[test]
+    fn update() {
+        let mut player = super::AnimationPlayer::new(vec![anim()]);
+
+        player.update(1.5 / 24.0);
+        assert_eq!(player.current_frame(), 1);
+        player.update(1.0 / 24.0);
+        assert_eq!(player.current_frame(), 0);
+
+        player.toggle_loop();
+        player.update(5.0 / 24.0);
+        assert_eq!(player.current_frame(), 1);
+        assert!(!player.playing);
+
+        // replaying from the start, without stopping on a frame of no time
+        player.toggle_play();
+        assert_eq!(player.time, 0.0);
+        player.update(0.0);
+        assert!(player.playing);
+        player.update(1.0 / 24.0);
+        assert_eq!(player.current_frame(), 1);
+    }
+
+    #[test]
+    fn step() {
+        let mut player = super::AnimationPlayer::new(vec![anim(), anim()]);
+
+        player.step(1);
+        assert_eq!(player.current_frame(), 1);
+        assert!(!player.playing);
+        player.step(1);
+        assert_eq!(player.current_frame(), 0);
+        player.step(-1);
+        assert_eq!(player.current_frame(), 1);
+
+        player.next_clip();
+        assert_eq!(player.current, 1);
+        assert_eq!(player.current_frame(), 0);
+    }
+
+    #[test]
+    fn empty_clip() {
+        let mut empty = anim();
+        empty.frames.clear();
+        let mut player = super::AnimationPlayer::new(vec![empty]);
+
+        player.update(1.0 / 24.0);
+        assert_eq!(player.time, 0.0);
+        player.step(1);
+        assert_eq!(player.current_frame(), 0);
+        assert_eq!(player.skeleton(), None);
+    }
+}
@@ -0,0 +1,196 @@
+#![allow(dead_code)]
+use std::collections::HashMap;
+use cgmath::InnerSpace;
+
+use md5::md5anim::{Md5Anim, Joint as AnimJoint, BaseFrame};
+use md5::md5mesh::{Md5Mesh, Joint};
+use animation::skeleton::{JointTransform, decode_frame, encode_frames, to_local_space, stored_orientation};
+use animation::bounds::recompute_bounds;
+use animation::compat::Mismatch;
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct RetargetOptions {
+    // target joint name to source joint name, for the joints named differently.
+    // Other joints go by their own name.
+    pub mapping: HashMap<String, String>,
+    // target joint whose bind height over the lowest joint is the leg height,
+    // the root translation being scaled by the ratio of the two leg heights
+    pub hips: Option<String>,
+}
+
+// Plays `anim`, made for the skeleton of `source`, on the skeleton of `target`.
+// Joints take the rotation of their source joint relative to its bind pose and
+// keep the bone lengths of the target. Only roots and the hips are translated.
+// Target joints without a source joint stay in their bind pose. Fails when the
+// mapping names a joint missing from `anim` or `source`.
+pub fn retarget(anim: &Md5Anim, source: &Md5Mesh, target: &Md5Mesh, options: &RetargetOptions) -> Result<Md5Anim, Vec<Mismatch>> {
+    let mut mismatches = Vec::new();
+    for name in options.mapping.values() {
+        if !anim.hierarchies.iter().any(|j| &j.name == name) {
+            mismatches.push(Mismatch::MissingInAnim(name.clone()));
+        }
+        if !source.joints.iter().any(|j| &j.name == name) {
+            mismatches.push(Mismatch::MissingInMesh(name.clone()));
+        }
+    }
+    if !mismatches.is_empty() {
+        return Err(mismatches);
+    }
+
+    let source_name = |name: &str| options.mapping.get(name).cloned().unwrap_or(String::from(name));
+    // anim and source mesh index of the source joint of each target joint
+    let sources = target.joints.iter().map(|joint| {
+        let name = source_name(&joint.name);
+        match (anim.hierarchies.iter().position(|j| j.name == name), source.joints.iter().position(|j| j.name == name)) {
+            (Some(a), Some(m)) => Some((a, m)),
+            _ => None
+        }
+    }).collect::<Vec<_>>();
+
+    let scale = match options.hips {
+        Some(ref hips) => match (leg_height(&target.joints, hips), leg_height(&source.joints, &source_name(hips))) {
+            (Some(target_height), Some(source_height)) if source_height > 0.0 => target_height / source_height,
+            _ => 1.0
+        },
+        None => 1.0
+    };
+
+    let source_bind = to_local_space(&source.joints);
+    let target_bind = to_local_space(&target.joints);
+
+    let poses = (0..anim.frames.len()).map(|frame| {
+        let local = decode_frame(anim, frame);
+        target.joints.iter().enumerate().map(|(i, joint)| {
+            let bind = target_bind[i];
+            match sources[i] {
+                Some((a, m)) => {
+                    let rotation = source_bind[m].orientation.conjugate() * local[a].orientation;
+                    let translated = joint.parent_index < 0 || options.hips.as_ref() == Some(&joint.name);
+                    JointTransform {
+                        position: if translated { bind.position + (local[a].position - source_bind[m].position) * scale } else { bind.position },
+                        orientation: (bind.orientation * rotation).normalize(),
+                    }
+                },
+                None => bind
+            }
+        }).collect::<Vec<_>>()
+    }).collect::<Vec<_>>();
+
+    let mut retargeted = bind_anim(target, anim.frame_rate);
+    retargeted.version = anim.version;
+    retargeted.command_line = anim.command_line.clone();
+    encode_frames(&mut retargeted, &poses);
+    recompute_bounds(&mut retargeted, target);
+    Ok(retargeted)
+}
+
+// Height of joint `hips` over the lowest joint of `skeleton`, up being Z
+pub fn leg_height(skeleton: &[Joint], hips: &str) -> Option<f32> {
+    let lowest = skeleton.iter().map(|j| j.position.z).fold(::std::f32::MAX, f32::min);
+    skeleton.iter().find(|j| j.name == hips).map(|j| j.position.z - lowest)
+}
+
+// Anim of the joints of `mesh`, with the bind pose as base frame and no frames
+fn bind_anim(mesh: &Md5Mesh, frame_rate: i32) -> Md5Anim {
+    let bind = to_local_space(&mesh.joints);
+
+    Md5Anim {
+        version: 10,
+        command_line: String::new(),
+        num_frames: 0,
+        num_joints: mesh.joints.len() as i32,
+        frame_rate: frame_rate,
+        num_animated_components: 0,
+        hierarchies: mesh.joints.iter().map(|j| AnimJoint { name: j.name.clone(), index: j.parent_index, flag: 0, start_index: 0 }).collect(),
+        bounds: Vec::new(),
+        base_frame: BaseFrame {
+            position: bind.iter().map(|t| t.position).collect(),
+            orientation: bind.iter().map(|t| stored_orientation(t.orientation)).collect(),
+        },
+        frames: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector3, Quaternion, Rotation3, Deg, InnerSpace};
+    use md5::md5mesh::{Md5Mesh, Joint};
+    use animation::skeleton::tests::mesh;
+    use animation::skeleton::{JointTransform, encode_frames, to_local_space, frame_skeleton};
+    use super::RetargetOptions;
+
+    // origin, hips `height` over it and a hand `reach` in front of the hips
+    fn character(hips: &str, height: f32, reach: f32) -> Md5Mesh {
+        let joint = |name: &str, parent_index: i32, position: Vector3<f32>| Joint {
+            name: String::from(name),
+            parent_index: parent_index,
+            position: position,
+            orientation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+        };
+        let mut character = mesh();
+        character.joints = vec![
+            joint("origin", -1, Vector3::new(0.0, 0.0, 0.0)),
+            joint(hips, 0, Vector3::new(0.0, 0.0, height)),
+            joint("hand", 1, Vector3::new(reach, 0.0, height)),
+        ];
+        character
+    }
+
+    #[test]
+    fn retarget() {
+        let source = character("hips", 1.0, 1.0);
+        let target = character("pelvis", 2.0, 3.0);
+
+        // walking forward one unit and turning the hips a quarter
+        let mut anim = super::bind_anim(&source, 24);
+        let mut pose = to_local_space(&source.joints);
+        pose[0].position = Vector3::new(1.0, 0.0, 0.0);
+        pose[1] = JointTransform { position: pose[1].position, orientation: Quaternion::from_angle_z(Deg(90.0)) };
+        encode_frames(&mut anim, &[to_local_space(&source.joints), pose]);
+
+        let mut options = RetargetOptions::default();
+        options.mapping.insert(String::from("pelvis"), String::from("hips"));
+        options.hips = Some(String::from("pelvis"));
+        let retargeted = super::retarget(&anim, &source, &target, &options).unwrap();
+
+        assert_eq!(retargeted.hierarchies.len(), 3);
+        assert_eq!(retargeted.bounds.len(), 2);
+        let skeleton = frame_skeleton(&retargeted, 1);
+        // the root moves twice as far, the longer arm turns with the hips
+        assert!((skeleton[0].position - Vector3::new(2.0, 0.0, 0.0)).magnitude() < 1e-5);
+        assert!((skeleton[2].position - Vector3::new(2.0, 3.0, 2.0)).magnitude() < 1e-5);
+
+        options.mapping.insert(String::from("pelvis"), String::from("spine"));
+        assert!(super::retarget(&anim, &source, &target, &options).is_err());
+    }
+
+    #[test]
+    fn bind_orientation() {
+        let source = character("hips", 1.0, 1.0);
+        // a pelvis turned a quarter, left unmapped
+        let mut target = character("pelvis", 2.0, 3.0);
+        target.joints[1].orientation = Quaternion::from_angle_z(Deg(90.0));
+        target.joints[2].orientation = Quaternion::from_angle_z(Deg(90.0));
+
+        let mut anim = super::bind_anim(&source, 24);
+        let mut pose = to_local_space(&source.joints);
+        pose[0].position = Vector3::new(1.0, 0.0, 0.0);
+        encode_frames(&mut anim, &[to_local_space(&source.joints), pose]);
+        let retargeted = super::retarget(&anim, &source, &target, &RetargetOptions::default()).unwrap();
+
+        // joints keeping their bind orientation play it, not its inverse
+        for frame in 0..2 {
+            let skeleton = frame_skeleton(&retargeted, frame);
+            for (a, b) in skeleton.iter().zip(target.joints.iter()) {
+                assert!(a.orientation.dot(b.orientation).abs() > 1.0 - 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn leg_height() {
+        let character = character("hips", 1.5, 1.0);
+        assert_eq!(super::leg_height(&character.joints, "hips"), Some(1.5));
+        assert_eq!(super::leg_height(&character.joints, "knee"), None);
+    }
+}
@@ -0,0 +1,177 @@
+#![allow(dead_code)]
+use std::f32::consts::PI;
+use cgmath::{Vector3, Matrix4, Quaternion, Rotation, Rotation3, Rad, InnerSpace, SquareMatrix};
+
+use md5::md5anim::{Md5Anim, Joint, Bound};
+use animation::skeleton::{JointTransform, decode_frame, encode_frames, to_object_space};
+use renderer::frustum::transform_aabb;
+use vertex_computation::skinning::joint_matrix;
+
+// Movement of the root joint from one frame to the next, in the space the
+// character faced on the previous frame. Yaw is around Z, the up axis of MD5.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct RootMotion {
+    pub translation: Vector3<f32>,
+    // radians, counterclockwise seen from above
+    pub yaw: f32,
+}
+
+pub struct ExtractedMotion {
+    // one per frame, the first one being zero
+    pub deltas: Vec<RootMotion>,
+    // the clip with the root held on its first frame placement, its bounds
+    // following the root
+    pub in_place: Md5Anim,
+}
+
+// Splits the translation and yaw of joint `root` out of `anim`, relative to its
+// parent, the object for `origin`. With `keep_vertical`, the height of the root
+// stays in the in place clip and out of the deltas, for jumps and crouches.
+// None when there is no such joint.
+pub fn extract_root_motion(anim: &Md5Anim, root: &str, keep_vertical: bool) -> Option<ExtractedMotion> {
+    let root = match anim.hierarchies.iter().position(|j| j.name == root) {
+        Some(root) => root,
+        None => return None
+    };
+    if anim.frames.is_empty() {
+        return Some(ExtractedMotion { deltas: Vec::new(), in_place: anim.clone() });
+    }
+
+    let original = (0..anim.frames.len()).map(|i| decode_frame(anim, i)).collect::<Vec<_>>();
+    let mut poses = original.clone();
+    let first = poses[0][root];
+    let (first_yaw, first_motion) = (yaw(first.orientation), moving_part(first.position, keep_vertical));
+
+    let mut deltas = Vec::with_capacity(poses.len());
+    let (mut last_yaw, mut last_motion) = (first_yaw, first_motion);
+    for pose in &mut poses {
+        let JointTransform { position, orientation } = pose[root];
+        let (frame_yaw, frame_motion) = (yaw(orientation), moving_part(position, keep_vertical));
+
+        let facing = Quaternion::from_angle_z(Rad(last_yaw - first_yaw));
+        deltas.push(RootMotion {
+            translation: facing.invert().rotate_vector(frame_motion - last_motion),
+            yaw: wrap_angle(frame_yaw - last_yaw),
+        });
+
+        // the motion since the first frame taken out, which leaves the position
+        // at the first one, turning doesn't move the root off its own axis
+        let turn = Quaternion::from_angle_z(Rad(first_yaw - frame_yaw));
+        pose[root] = JointTransform {
+            position: position - frame_motion + first_motion,
+            orientation: (turn * orientation).normalize(),
+        };
+
+        last_yaw = frame_yaw;
+        last_motion = frame_motion;
+    }
+
+    let mut in_place = anim.clone();
+    encode_frames(&mut in_place, &poses);
+
+    // the bounds taken back along with the root, everything under it moving with it
+    in_place.bounds = anim.bounds.iter().enumerate().map(|(i, bound)| {
+        match (original.get(i), poses.get(i)) {
+            (Some(before), Some(after)) => {
+                let back = root_matrix(&anim.hierarchies, after, root) *
+                    root_matrix(&anim.hierarchies, before, root).invert().unwrap_or(Matrix4::identity());
+                let (min, max) = transform_aabb(&back, bound.bound_min, bound.bound_max);
+                Bound { bound_min: min, bound_max: max }
+            },
+            _ => bound.clone()
+        }
+    }).collect();
+
+    Some(ExtractedMotion { deltas: deltas, in_place: in_place })
+}
+
+// Object space transform of joint `root` in the local pose `pose`
+fn root_matrix(hierarchy: &[Joint], pose: &[JointTransform], root: usize) -> Matrix4<f32> {
+    joint_matrix(&to_object_space(hierarchy, pose)[root])
+}
+
+// Sum of `deltas` from the first frame, as a translation in the space of the
+// first frame and a yaw
+pub fn accumulate(deltas: &[RootMotion]) -> RootMotion {
+    deltas.iter().fold(RootMotion { translation: Vector3::new(0.0, 0.0, 0.0), yaw: 0.0 }, |total, delta| {
+        RootMotion {
+            translation: total.translation + Quaternion::from_angle_z(Rad(total.yaw)).rotate_vector(delta.translation),
+            yaw: wrap_angle(total.yaw + delta.yaw),
+        }
+    })
+}
+
+// Angle of the twist of `q` around Z
+fn yaw(q: Quaternion<f32>) -> f32 {
+    let (s, z) = if q.s < 0.0 { (-q.s, -q.v.z) } else { (q.s, q.v.z) };
+    2.0 * z.atan2(s)
+}
+
+fn moving_part(position: Vector3<f32>, keep_vertical: bool) -> Vector3<f32> {
+    Vector3::new(position.x, position.y, if keep_vertical { 0.0 } else { position.z })
+}
+
+fn wrap_angle(angle: f32) -> f32 {
+    let angle = (angle + PI) % (2.0 * PI);
+    if angle < 0.0 { angle + PI } else { angle - PI }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+    use cgmath::{Vector3, InnerSpace};
+    use md5::md5anim::{Md5Anim, Bound};
+    use animation::skeleton::tests::anim;
+    use animation::skeleton::{decode_frame, quaternion_from_xyz, ORIENTATION_Z};
+
+    // the fixture, with the origin also moving along X and Y and turning a quarter
+    fn walk() -> Md5Anim {
+        let mut anim = anim();
+        anim.hierarchies[0].flag |= ORIENTATION_Z;
+        anim.hierarchies[1].start_index = 4;
+        anim.num_animated_components = 6;
+        anim.frames[0].frame_data = vec![0.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        anim.frames[1].frame_data = vec![1.0, 2.0, 0.5, -(0.5f32).sqrt(), 3.0, 0.0];
+        // a box along X, turned and moved with the origin
+        anim.bounds = vec![
+            Bound { bound_min: Vector3::new(-1.0, -0.5, 0.0), bound_max: Vector3::new(1.0, 0.5, 2.0) },
+            Bound { bound_min: Vector3::new(0.5, 1.0, 0.5), bound_max: Vector3::new(1.5, 3.0, 2.5) },
+        ];
+        anim
+    }
+
+    #[test]
+    fn extract_root_motion() {
+        let anim = walk();
+        let motion = super::extract_root_motion(&anim, "origin", true).unwrap();
+
+        assert_eq!(motion.deltas.len(), 2);
+        assert_eq!(motion.deltas[0].translation, Vector3::new(0.0, 0.0, 0.0));
+        assert!((motion.deltas[1].translation - Vector3::new(1.0, 2.0, 0.0)).magnitude() < 1e-5);
+        assert!((motion.deltas[1].yaw - PI / 2.0).abs() < 1e-5);
+
+        let pose = decode_frame(&motion.in_place, 1);
+        assert!((pose[0].position - Vector3::new(0.0, 0.0, 0.5)).magnitude() < 1e-5);
+        assert!(pose[0].orientation.dot(quaternion_from_xyz(0.0, 0.0, 0.0)).abs() > 1.0 - 1e-5);
+        assert_eq!(pose[1], decode_frame(&anim, 1)[1]);
+        assert_eq!(motion.in_place.bounds[0], anim.bounds[0]);
+        assert!((motion.in_place.bounds[1].bound_min - Vector3::new(-1.0, -0.5, 0.5)).magnitude() < 1e-5);
+        assert!((motion.in_place.bounds[1].bound_max - Vector3::new(1.0, 0.5, 2.5)).magnitude() < 1e-5);
+
+        let motion = super::extract_root_motion(&anim, "origin", false).unwrap();
+        assert!((motion.deltas[1].translation - Vector3::new(1.0, 2.0, 0.5)).magnitude() < 1e-5);
+        assert!(decode_frame(&motion.in_place, 1)[0].position.magnitude() < 1e-5);
+
+        assert!(super::extract_root_motion(&anim, "hips", false).is_none());
+    }
+
+    #[test]
+    fn accumulate() {
+        let step = super::RootMotion { translation: Vector3::new(1.0, 0.0, 0.0), yaw: PI / 2.0 };
+        let total = super::accumulate(&[step, step]);
+
+        // forward then left
+        assert!((total.translation - Vector3::new(1.0, 1.0, 0.0)).magnitude() < 1e-5);
+        assert!((total.yaw.abs() - PI).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,314 @@
+#![allow(dead_code)]
+use cgmath::{Vector3, Quaternion, InnerSpace};
+use md5::md5anim::{Md5Anim, Joint as AnimJoint, Frame};
+use md5::md5mesh::Joint;
+pub use md5::md5common_parser::{quaternion_from_xyz, quaternion_to_xyz};
+
+// Bits of `md5anim::Joint::flag`, one per animated component
+pub const POSITION_X: i32 = 1;
+pub const POSITION_Y: i32 = 2;
+pub const POSITION_Z: i32 = 4;
+pub const ORIENTATION_X: i32 = 8;
+pub const ORIENTATION_Y: i32 = 16;
+pub const ORIENTATION_Z: i32 = 32;
+
+// Transform of a joint relative to its parent, or to the object for the root
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct JointTransform {
+    pub position: Vector3<f32>,
+    pub orientation: Quaternion<f32>,
+}
+
+
+// Unit quaternion `q` as it is read back from a file, with a negative real part
+pub fn stored_orientation(q: Quaternion<f32>) -> Quaternion<f32> {
+    let v = quaternion_to_xyz(q);
+    quaternion_from_xyz(v.x, v.y, v.z)
+}
+
+// Local joint transforms of frame `frame`: the base frame overridden by the
+// components flagged as animated
+pub fn decode_frame(anim: &Md5Anim, frame: usize) -> Vec<JointTransform> {
+    let data = &anim.frames[frame].frame_data;
+    let mut res: Vec<JointTransform> = Vec::with_capacity(anim.hierarchies.len());
+
+    for (i, joint) in anim.hierarchies.iter().enumerate() {
+        let mut p = anim.base_frame.position[i];
+        // base orientations not read from a file may have a positive real part
+        let mut o = quaternion_to_xyz(anim.base_frame.orientation[i]);
+        let mut k = joint.start_index as usize;
+
+        {
+            let mut read = |flag: i32, value: &mut f32| {
+                if joint.flag & flag != 0 {
+                    *value = data[k];
+                    k += 1;
+                }
+            };
+
+            read(POSITION_X, &mut p.x);
+            read(POSITION_Y, &mut p.y);
+            read(POSITION_Z, &mut p.z);
+            read(ORIENTATION_X, &mut o.x);
+            read(ORIENTATION_Y, &mut o.y);
+            read(ORIENTATION_Z, &mut o.z);
+        }
+
+        res.push(JointTransform {
+            position: p,
+            orientation: quaternion_from_xyz(o.x, o.y, o.z),
+        });
+    }
+
+    res
+}
+
+// Inverse of `decode_frame` over the whole clip: replaces the frames of `anim` by
+// `poses`, one local pose per frame. Components differing from the base frame
+// get animated, the others keep their flags. Bounds are left as they are.
+pub fn encode_frames(anim: &mut Md5Anim, poses: &[Vec<JointTransform>]) {
+    let components = |position: Vector3<f32>, orientation: Quaternion<f32>| {
+        let o = quaternion_to_xyz(orientation);
+        [position.x, position.y, position.z, o.x, o.y, o.z]
+    };
+    let flags = [POSITION_X, POSITION_Y, POSITION_Z, ORIENTATION_X, ORIENTATION_Y, ORIENTATION_Z];
+
+    let mut start_index = 0;
+    for (i, joint) in anim.hierarchies.iter_mut().enumerate() {
+        let base = components(anim.base_frame.position[i], anim.base_frame.orientation[i]);
+        for pose in poses {
+            let values = components(pose[i].position, pose[i].orientation);
+            for c in 0..6 {
+                // below what the writer keeps
+                if (values[c] - base[c]).abs() > 1e-6 {
+                    joint.flag |= flags[c];
+                }
+            }
+        }
+
+        joint.start_index = start_index;
+        start_index += (joint.flag & 63).count_ones() as i32;
+    }
+
+    anim.frames = poses.iter().enumerate().map(|(n, pose)| {
+        let mut data = Vec::with_capacity(start_index as usize);
+        for (joint, transform) in anim.hierarchies.iter().zip(pose.iter()) {
+            let values = components(transform.position, transform.orientation);
+            for c in 0..6 {
+                if joint.flag & flags[c] != 0 {
+                    data.push(values[c]);
+                }
+            }
+        }
+        Frame { frame_number: n as u32, frame_data: data }
+    }).collect();
+
+    anim.num_frames = anim.frames.len() as i32;
+    anim.num_animated_components = start_index;
+}
+
+// Normalized lerp along the shortest arc
+pub fn interpolate_orientation(a: Quaternion<f32>, b: Quaternion<f32>, t: f32) -> Quaternion<f32> {
+    let b = if a.dot(b) < 0.0 { -b } else { b };
+    (a * (1.0 - t) + b * t).normalize()
+}
+
+pub fn interpolate_poses(a: &[JointTransform], b: &[JointTransform], t: f32) -> Vec<JointTransform> {
+    a.iter().zip(b.iter()).map(|(ja, jb)| {
+        JointTransform {
+            position: ja.position + (jb.position - ja.position) * t,
+            orientation: interpolate_orientation(ja.orientation, jb.orientation, t),
+        }
+    }).collect()
+}
+
+// Frames surrounding `time` (in seconds) and the blend factor between them.
+// Past the last frame, looping clips wrap around and others hold the last frame.
+// Clips without frames give frame 0, which callers must not decode.
+pub fn frames_at(anim: &Md5Anim, time: f32, looping: bool) -> (usize, usize, f32) {
+    let count = anim.frames.len();
+    if count == 0 {
+        return (0, 0, 0.0);
+    }
+
+    let mut position = time.max(0.0) * anim.frame_rate as f32;
+
+    // times set from a frame number should land exactly on it
+    if (position - position.round()).abs() < 1e-4 {
+        position = position.round();
+    }
+
+    if looping {
+        let position = position % count as f32;
+        let current = (position.floor() as usize).min(count - 1);
+        (current, (current + 1) % count, position - current as f32)
+    } else if position >= (count - 1) as f32 {
+        (count - 1, count - 1, 0.0)
+    } else {
+        let current = position.floor() as usize;
+        (current, current + 1, position - current as f32)
+    }
+}
+
+// Local pose at `time`, interpolated between the two closest frames
+pub fn sample_pose(anim: &Md5Anim, time: f32, looping: bool) -> Vec<JointTransform> {
+    let (current, next, t) = frames_at(anim, time, looping);
+
+    interpolate_poses(&decode_frame(anim, current), &decode_frame(anim, next), t)
+}
+
+// Accumulates the hierarchy, parents being listed before their children
+pub fn to_object_space(hierarchy: &[AnimJoint], local: &[JointTransform]) -> Vec<Joint> {
+    let mut res: Vec<Joint> = Vec::with_capacity(local.len());
+
+    for (joint, transform) in hierarchy.iter().zip(local.iter()) {
+        let (position, orientation) = if joint.index < 0 {
+            (transform.position, transform.orientation)
+        } else {
+            let parent: &Joint = &res[joint.index as usize];
+            (parent.position + parent.orientation * transform.position,
+             (parent.orientation * transform.orientation).normalize())
+        };
+
+        res.push(Joint {
+            name: joint.name.clone(),
+            parent_index: joint.index,
+            position: position,
+            orientation: orientation,
+        });
+    }
+
+    res
+}
+
+// Inverse of `to_object_space`
+pub fn to_local_space(joints: &[Joint]) -> Vec<JointTransform> {
+    joints.iter().map(|j| {
+        if j.parent_index < 0 {
+            JointTransform { position: j.position, orientation: j.orientation }
+        } else {
+            let parent: &Joint = &joints[j.parent_index as usize];
+            let inverse = parent.orientation.conjugate();
+            JointTransform {
+                position: inverse * (j.position - parent.position),
+                orientation: (inverse * j.orientation).normalize(),
+            }
+        }
+    }).collect()
+}
+
+// Object space skeleton of frame `frame`, ready for skinning
+pub fn frame_skeleton(anim: &Md5Anim, frame: usize) -> Vec<Joint> {
+    to_object_space(&anim.hierarchies, &decode_frame(anim, frame))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use cgmath::{Vector2, Vector3, InnerSpace};
+    use md5::md5anim::{Md5Anim, Joint, BaseFrame, Frame};
+    use md5::md5mesh::{Md5Mesh, Joint as MeshJoint, Mesh, Vertex, Triangle, Weight};
+
+    // Two joints, the child only animating its X position and X orientation
+    pub fn anim() -> Md5Anim {
+        Md5Anim {
+            version: 10,
+            command_line: String::new(),
+            num_frames: 2,
+            num_joints: 2,
+            frame_rate: 24,
+            num_animated_components: 5,
+            hierarchies: vec![
+                Joint { name: String::from("origin"), index: -1, flag: 7, start_index: 0 },
+                Joint { name: String::from("arm"), index: 0, flag: 9, start_index: 3 },
+            ],
+            bounds: vec![],
+            base_frame: BaseFrame {
+                position: vec![Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0)],
+                orientation: vec![super::quaternion_from_xyz(0.0, 0.0, 0.0), super::quaternion_from_xyz(0.0, 0.0, 0.0)],
+            },
+            frames: vec![
+                Frame { frame_number: 0, frame_data: vec![0.0, 0.0, 0.0, 1.0, 0.0] },
+                Frame { frame_number: 1, frame_data: vec![0.0, 0.0, 2.0, 3.0, 0.0] },
+            ],
+        }
+    }
+
+    // Mesh bound to `anim`, in its bind pose, with a vertex on each joint
+    pub fn mesh() -> Md5Mesh {
+        let joint = |name: &str, parent_index: i32, y: f32| MeshJoint {
+            name: String::from(name),
+            parent_index: parent_index,
+            position: Vector3::new(0.0, y, 0.0),
+            orientation: super::quaternion_from_xyz(0.0, 0.0, 0.0),
+        };
+        let vertex = |index: u32| Vertex { index: index, tex_coords: Vector2::new(0.0, 0.0), start_weight: index, weight_count: 1 };
+        let weight = |index: u32| Weight { index: index, joint_index: index, bias: 1.0, position: Vector3::new(0.0, 0.0, 0.0) };
+
+        Md5Mesh {
+            version: 10,
+            command_line: String::new(),
+            joints: vec![joint("origin", -1, 0.0), joint("arm", 0, 1.0)],
+            meshes: vec![Mesh {
+                shader: String::new(),
+                vertices: vec![vertex(0), vertex(1)],
+                triangles: vec![Triangle { index: 0, vertex_indices: (0, 1, 1) }],
+                weights: vec![weight(0), weight(1)],
+            }],
+        }
+    }
+
+    #[test]
+    fn decode_frame() {
+        let pose = super::decode_frame(&anim(), 1);
+
+        assert_eq!(pose[0].position, Vector3::new(0.0, 0.0, 2.0));
+        assert_eq!(pose[1].position, Vector3::new(3.0, 1.0, 0.0));
+        assert_eq!(pose[1].orientation, super::quaternion_from_xyz(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn frames_at() {
+        let a = anim();
+
+        assert_eq!(super::frames_at(&a, 0.5 / 24.0, false), (0, 1, 0.5));
+        assert_eq!(super::frames_at(&a, 3.0 / 24.0, false), (1, 1, 0.0));
+        assert_eq!(super::frames_at(&a, 1.5 / 24.0, true), (1, 0, 0.5));
+
+        let mut empty = anim();
+        empty.frames.clear();
+        assert_eq!(super::frames_at(&empty, 1.0, true), (0, 0, 0.0));
+    }
+
+    #[test]
+    fn encode_frames() {
+        let original = anim();
+        let poses = vec![super::decode_frame(&original, 1), super::decode_frame(&original, 0)];
+        let mut anim = anim();
+
+        super::encode_frames(&mut anim, &poses);
+        assert_eq!(anim.hierarchies, original.hierarchies);
+        assert_eq!(anim.frames[0].frame_data, original.frames[1].frame_data);
+        assert_eq!(anim.frames[1].frame_number, 1);
+
+        // the arm turning around Z now
+        let mut turned = poses.clone();
+        turned[1][1].orientation = super::quaternion_from_xyz(0.0, 0.0, 0.5);
+        super::encode_frames(&mut anim, &turned);
+        assert_eq!(anim.hierarchies[1].flag, 41);
+        assert_eq!(anim.num_animated_components, 6);
+        assert_eq!(super::decode_frame(&anim, 1), turned[1]);
+    }
+
+    #[test]
+    fn object_space_round_trip() {
+        let mut pose = super::decode_frame(&anim(), 1);
+        pose[1].orientation = super::quaternion_from_xyz(0.0, 0.0, 0.5);
+        let skeleton = super::to_object_space(&anim().hierarchies, &pose);
+
+        assert_eq!(skeleton[1].position, Vector3::new(3.0, 1.0, 2.0));
+
+        let local = super::to_local_space(&skeleton);
+        assert!((local[1].position - pose[1].position).magnitude() < 1e-5);
+        assert!((local[1].orientation - pose[1].orientation).magnitude() < 1e-5);
+    }
+}
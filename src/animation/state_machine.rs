@@ -0,0 +1,321 @@
+#![allow(dead_code)]
+use std::collections::HashMap;
+
+use md5::md5anim::Md5Anim;
+use md5::md5mesh::Joint;
+use animation::skeleton::{JointTransform, sample_pose, to_object_space};
+use animation::blend::crossfade;
+
+// One state of a `StateMachine`, playing one of its clips
+#[derive(Clone, PartialEq, Debug)]
+pub struct State {
+    pub name: String,
+    // index in `StateMachine::clips`
+    pub clip: usize,
+    pub speed: f32,
+    pub looping: bool,
+}
+
+// Test on the parameters, or on the clip of the current state
+#[derive(Clone, PartialEq, Debug)]
+pub enum Condition {
+    Greater(String, f32),
+    Less(String, f32),
+    // a parameter set to a non zero value, bools being 0 or 1
+    IsSet(String),
+    IsNotSet(String),
+    // the clip of the current state reached its end at least once
+    ClipEnded,
+}
+
+// Move to state `to` when all of `conditions` hold, crossfading over `duration`
+// seconds. Without `from`, the transition is taken from any other state.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Transition {
+    pub from: Option<usize>,
+    pub to: usize,
+    pub conditions: Vec<Condition>,
+    pub duration: f32,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Event {
+    // the clip of a state reached its end, once per loop when looping
+    ClipEnded(usize),
+    TransitionStarted { from: usize, to: usize },
+    TransitionEnded(usize),
+}
+
+// State being faded out
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct Fade {
+    from: usize,
+    from_time: f32,
+    elapsed: f32,
+    duration: f32,
+}
+
+// Graph of states over clips, driven by named parameters. Transitions are only
+// looked at once the previous one is over.
+pub struct StateMachine {
+    pub clips: Vec<Md5Anim>,
+    pub states: Vec<State>,
+    pub transitions: Vec<Transition>,
+    pub parameters: HashMap<String, f32>,
+    pub current: usize,
+    // time in the clip of the current state
+    pub time: f32,
+    ended: bool,
+    fade: Option<Fade>,
+    events: Vec<Event>,
+}
+
+impl StateMachine {
+    pub fn new(clips: Vec<Md5Anim>) -> StateMachine {
+        StateMachine {
+            clips: clips,
+            states: Vec::new(),
+            transitions: Vec::new(),
+            parameters: HashMap::new(),
+            current: 0,
+            time: 0.0,
+            ended: false,
+            fade: None,
+            events: Vec::new(),
+        }
+    }
+
+    // Adds a state playing `clip`, the first one added being the starting state
+    pub fn add_state(&mut self, name: &str, clip: usize, looping: bool) -> usize {
+        self.states.push(State { name: String::from(name), clip: clip, speed: 1.0, looping: looping });
+        self.states.len() - 1
+    }
+
+    pub fn add_transition(&mut self, from: Option<usize>, to: usize, conditions: Vec<Condition>, duration: f32) {
+        self.transitions.push(Transition { from: from, to: to, conditions: conditions, duration: duration });
+    }
+
+    pub fn state_index(&self, name: &str) -> Option<usize> {
+        self.states.iter().position(|s| s.name == name)
+    }
+
+    pub fn set_float(&mut self, name: &str, value: f32) {
+        self.parameters.insert(String::from(name), value);
+    }
+
+    pub fn set_bool(&mut self, name: &str, value: bool) {
+        self.set_float(name, if value { 1.0 } else { 0.0 });
+    }
+
+    // Unknown parameters are 0
+    pub fn parameter(&self, name: &str) -> f32 {
+        self.parameters.get(name).cloned().unwrap_or(0.0)
+    }
+
+    pub fn in_transition(&self) -> bool {
+        self.fade.is_some()
+    }
+
+    // Events since the last call, oldest first
+    pub fn take_events(&mut self) -> Vec<Event> {
+        self.events.drain(..).collect()
+    }
+
+    // Advances by `dt` seconds, takes the first transition whose conditions hold,
+    // and returns the object space skeleton to skin with. None without states.
+    pub fn tick(&mut self, dt: f32) -> Option<Vec<Joint>> {
+        if self.states.is_empty() {
+            return None;
+        }
+
+        let current = self.current;
+        let (time, ended) = self.advance(current, self.time, dt);
+        self.time = time;
+        if ended {
+            self.ended = true;
+            self.events.push(Event::ClipEnded(current));
+        }
+
+        let mut fade_over = false;
+        if let Some(mut fade) = self.fade {
+            let (from_time, _) = self.advance(fade.from, fade.from_time, dt);
+            fade.from_time = from_time;
+            fade.elapsed += dt;
+            fade_over = fade.elapsed >= fade.duration;
+            self.fade = if fade_over { None } else { Some(fade) };
+        }
+        if fade_over {
+            self.events.push(Event::TransitionEnded(current));
+        }
+
+        if self.fade.is_none() {
+            let next = self.transitions.iter()
+                .find(|t| t.to != current && t.from.map(|from| from == current).unwrap_or(true)
+                      && t.conditions.iter().all(|c| self.holds(c)))
+                .cloned();
+            if let Some(transition) = next {
+                self.start_transition(transition.to, transition.duration);
+            }
+        }
+
+        self.skeleton()
+    }
+
+    // Moves to state `to` without waiting on a transition, crossfading over
+    // `duration` seconds
+    pub fn start_transition(&mut self, to: usize, duration: f32) {
+        if to >= self.states.len() {
+            return;
+        }
+
+        let from = self.current;
+        self.events.push(Event::TransitionStarted { from: from, to: to });
+        if duration > 0.0 {
+            self.fade = Some(Fade { from: from, from_time: self.time, elapsed: 0.0, duration: duration });
+        } else {
+            self.fade = None;
+            self.events.push(Event::TransitionEnded(to));
+        }
+        self.current = to;
+        self.time = 0.0;
+        self.ended = false;
+    }
+
+    // Local pose of the current state, blended with the state faded out
+    pub fn pose(&self) -> Option<Vec<JointTransform>> {
+        let pose = match self.state_pose(self.current, self.time) {
+            Some(pose) => pose,
+            None => return None
+        };
+
+        match self.fade {
+            Some(fade) => match self.state_pose(fade.from, fade.from_time) {
+                Some(ref from) if from.len() == pose.len() => Some(crossfade(from, &pose, fade.elapsed / fade.duration)),
+                _ => Some(pose)
+            },
+            None => Some(pose)
+        }
+    }
+
+    pub fn skeleton(&self) -> Option<Vec<Joint>> {
+        let anim = match self.states.get(self.current).and_then(|s| self.clips.get(s.clip)) {
+            Some(anim) => anim,
+            None => return None
+        };
+        self.pose().map(|pose| to_object_space(&anim.hierarchies, &pose))
+    }
+
+    pub fn status(&self) -> String {
+        match self.states.get(self.current) {
+            Some(state) => match self.fade {
+                Some(fade) => format!("{} -> {}", self.states[fade.from].name, state.name),
+                None => state.name.clone()
+            },
+            None => String::from("no state")
+        }
+    }
+
+    fn holds(&self, condition: &Condition) -> bool {
+        match *condition {
+            Condition::Greater(ref name, value) => self.parameter(name) > value,
+            Condition::Less(ref name, value) => self.parameter(name) < value,
+            Condition::IsSet(ref name) => self.parameter(name) != 0.0,
+            Condition::IsNotSet(ref name) => self.parameter(name) == 0.0,
+            Condition::ClipEnded => self.ended,
+        }
+    }
+
+    // Time of `state` moved by `dt`, and whether its clip reached its end on the way
+    fn advance(&self, state: usize, time: f32, dt: f32) -> (f32, bool) {
+        let state = &self.states[state];
+        let duration = match self.clips.get(state.clip) {
+            Some(anim) if !anim.frames.is_empty() => anim.frames.len() as f32 / anim.frame_rate as f32,
+            _ => return (time, false)
+        };
+
+        let next = time + dt * state.speed;
+        if state.looping {
+            (((next % duration) + duration) % duration, next >= duration)
+        } else {
+            (next.max(0.0).min(duration), next >= duration && time < duration)
+        }
+    }
+
+    fn state_pose(&self, state: usize, time: f32) -> Option<Vec<JointTransform>> {
+        let state = &self.states[state];
+        match self.clips.get(state.clip) {
+            Some(anim) if !anim.frames.is_empty() => Some(sample_pose(anim, time, state.looping)),
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use animation::skeleton::tests::anim;
+    use animation::skeleton::{decode_frame, sample_pose};
+    use animation::blend::crossfade;
+    use super::{StateMachine, Condition, Event};
+
+    fn machine() -> StateMachine {
+        let mut still = anim();
+        still.frames[1] = still.frames[0].clone();
+
+        let mut machine = StateMachine::new(vec![still, anim()]);
+        let idle = machine.add_state("idle", 0, true);
+        let wave = machine.add_state("wave", 1, false);
+        machine.add_transition(Some(idle), wave, vec![Condition::Greater(String::from("speed"), 0.5)], 1.0 / 24.0);
+        machine.add_transition(Some(wave), idle, vec![Condition::ClipEnded], 0.0);
+        machine
+    }
+
+    #[test]
+    fn transitions() {
+        let mut machine = machine();
+
+        machine.tick(0.5 / 24.0);
+        assert_eq!(machine.current, 0);
+        assert!(machine.take_events().is_empty());
+
+        machine.set_float("speed", 1.0);
+        machine.tick(0.5 / 24.0);
+        assert_eq!(machine.current, 1);
+        assert!(machine.in_transition());
+        assert_eq!(machine.status(), "idle -> wave");
+
+        // halfway through the fade, from the still idle pose
+        machine.tick(0.5 / 24.0);
+        let (still, anim) = (machine.clips[0].clone(), anim());
+        assert_eq!(machine.pose().unwrap(),
+                   crossfade(&decode_frame(&still, 0), &sample_pose(&anim, 0.5 / 24.0, false), 0.5));
+
+        machine.tick(0.5 / 24.0);
+        assert!(!machine.in_transition());
+        assert_eq!(machine.take_events(), vec![
+            Event::TransitionStarted { from: 0, to: 1 },
+            Event::TransitionEnded(1),
+        ]);
+
+        // wave plays once then goes back to idle, even with the speed still up
+        machine.tick(1.0 / 24.0);
+        assert_eq!(machine.current, 0);
+        assert_eq!(machine.take_events(), vec![
+            Event::ClipEnded(1),
+            Event::TransitionStarted { from: 1, to: 0 },
+            Event::TransitionEnded(0),
+        ]);
+    }
+
+    #[test]
+    fn tick() {
+        let mut machine = StateMachine::new(vec![anim()]);
+        assert_eq!(machine.tick(0.1), None);
+
+        machine.add_state("idle", 0, true);
+        assert_eq!(machine.tick(1.0 / 24.0).unwrap().len(), 2);
+        assert!(machine.take_events().is_empty());
+        machine.tick(1.5 / 24.0);
+        assert_eq!(machine.take_events(), vec![Event::ClipEnded(0)]);
+        assert_eq!(machine.current, 0);
+    }
+}
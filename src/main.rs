@@ -13,18 +13,37 @@ extern crate vulkano_win;
 
 mod md5;
 use md5::md5mesh_parser::parse_md5mesh;
+use md5::md5anim_parser::parse_anim;
+use md5::md5anim::Md5Anim;
+use md5::md5anim_writer::save_md5anim;
 use nom::FileProducer;
 use std::fs::File;
 use std::io::Read;
+use std::path::Path;
+use std::env;
+use std::process;
 
 mod renderer;
-use renderer::render::render_model;
+use renderer::backend::Renderer;
+use renderer::render::{render_model, render_scene, prepare_scene};
+use renderer::capture::{save_screenshot, capture_sequence};
+use renderer::camera::OrbitCamera;
+use renderer::config::{RendererConfig, DeviceSelection, DeviceType, PresentMode, DepthFormat};
+use renderer::debug::DebugOverlay;
+use renderer::error::RendererError;
+use renderer::lighting::{Lighting, Light};
+use renderer::material::{Material, load_materials};
+use renderer::scene::Scene;
+use renderer::shadow::ShadowConfig;
+use renderer::software::SoftwareRenderer;
+use renderer::vulkan::VulkanRenderer;
 
 mod vertex_computation;
-use vertex_computation::compute::{prepare_mesh, prepare_normals, prepare_full_mesh};
-use vertex_computation::convert::posvec3_to_posvulkano;
-use vertex_computation::convert::normvec3_to_normvulkano;
-use vertex_computation::convert::generate_indices;
+
+mod animation;
+use animation::bounds::{verify_bounds, recompute_bounds};
+use animation::compat::{check_hierarchy, remap_by_name};
+use animation::player::AnimationPlayer;
 
 fn main() {
 
@@ -35,19 +54,277 @@ fn main() {
     f.read_to_end(&mut buff).unwrap();
     let (_, res) = parse_md5mesh(&buff).unwrap();
 
-    // let model_idx: usize = 0;
+    let args: Vec<String> = env::args().collect();
+
+    // `--up x|y|z` picks the camera up axis, MD5 models are usually Z up
+    let up = match args.iter().position(|a| a == "--up").and_then(|i| args.get(i + 1)) {
+        Some(axis) if axis == "x" => cgmath::Vector3::unit_x(),
+        Some(axis) if axis == "y" => cgmath::Vector3::unit_y(),
+        _ => cgmath::Vector3::unit_z()
+    };
+    let mut camera = OrbitCamera::new(up);
+
+    let lighting = parse_lighting(&args);
+    let shadows = parse_shadows(&args);
+    // textures are looked up next to the mesh, named after the shader of each mesh
+    let materials = load_materials(Path::new(path).parent().unwrap_or(Path::new(".")), &res);
+
+    // `amalia --headless out.png` renders a single frame on the CPU, no GPU needed
+    if args.len() > 2 && args[1] == "--headless" {
+        if let Err(e) = render_headless(&res, &materials, &lighting, &shadows, &mut camera, Path::new(&args[2])) {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    // every `.md5anim` given on the command line becomes a clip of the player
+    let mut anim_paths = args.iter().filter(|a| a.ends_with(".md5anim")).cloned().collect::<Vec<_>>();
+    if anim_paths.is_empty() {
+        anim_paths.push(String::from("./Resources/bob_lamp_update/bob_lamp_update_export.md5anim"));
+    }
+    // clips not matching the joints of the mesh are remapped by name, or left out
+    let (anim_paths, clips): (Vec<String>, Vec<Md5Anim>) = anim_paths.iter()
+        .filter_map(|p| fit_to_mesh(&res, p, load_md5anim(p)).map(|anim| (p.clone(), anim)))
+        .unzip();
+    let mut player = AnimationPlayer::new(clips);
+
+    // `--check-bounds` compares the bounds of each clip with the mesh skinned in
+    // every frame, `--write-bounds out.md5anim` saves the first clip with the
+    // skinned bounds
+    if args.iter().any(|a| a == "--check-bounds") || option_value(&args, "--write-bounds").is_some() {
+        check_bounds(&res, &anim_paths, &mut player.clips, option_value(&args, "--write-bounds"));
+        return;
+    }
+
+    let mut events_loop = winit::EventsLoop::new();
+    let mut overlay = DebugOverlay::new();
+
+    // the renderer is dropped, waiting for the GPU, before exiting
+    let result = VulkanRenderer::new(&events_loop, &parse_renderer_config(&args)).and_then(|mut renderer| {
+        renderer.upload_materials(&materials)?;
+        renderer.set_lighting(&lighting);
+        renderer.set_shadows(&shadows)?;
+
+        // `--capture-sequence dir [--capture-fps n]` renders every frame of the
+        // first clip to dir/frame_0000.png, ..., at the frame rate of the clip
+        // unless given
+        if let (Some(dir), Some(anim)) = (option_value(&args, "--capture-sequence"), player.clips.first()) {
+            prepare_scene(&mut renderer, &res, &mut camera)?;
+            renderer.set_camera(camera.view(), camera.projection(renderer.dimensions()));
+
+            let fps = option_value(&args, "--capture-fps").and_then(|v| v.parse::<f32>().ok())
+                .unwrap_or(anim.frame_rate as f32);
+            let count = capture_sequence(&mut renderer, anim, fps, Path::new(&dir))?;
+            println!("wrote {} images to {}", count, dir);
+            return Ok(());
+        }
+
+        // `--screenshot out.png` saves the first frame of the first clip and exits
+        if let Some(path) = option_value(&args, "--screenshot") {
+            prepare_scene(&mut renderer, &res, &mut camera)?;
+            renderer.set_camera(camera.view(), camera.projection(renderer.dimensions()));
+            if let Some(skeleton) = player.skeleton() {
+                renderer.update_pose(&skeleton)?;
+            }
+            renderer.draw_frame()?;
+            return save_screenshot(&mut renderer, Path::new(&path));
+        }
+
+        // `--crowd n [--crowd-spacing s]` draws n instances of the model on a grid,
+        // playing the clips with different start times
+        if let Some(count) = option_value(&args, "--crowd").and_then(|v| v.parse::<usize>().ok()) {
+            let spacing = option_value(&args, "--crowd-spacing").and_then(|v| v.parse::<f32>().ok())
+                .unwrap_or(crowd_spacing(&player.clips));
+            let mut scene = Scene::new(player.clips.clone());
+            scene.add_grid(count, spacing);
+
+            return render_scene(&mut renderer, &mut events_loop, &res, &mut camera, &mut scene);
+        }
+
+        render_model(&mut renderer, &mut events_loop, &res, &mut camera, &mut player, &mut overlay)
+    });
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn load_md5anim(path: &str) -> Md5Anim {
+    let mut f = File::open(path).unwrap();
+    let mut buff = vec![];
+
+    f.read_to_end(&mut buff).unwrap();
+    let (_, res) = parse_anim(&buff).unwrap();
+    res
+}
+
+fn fit_to_mesh(model: &md5::md5mesh::Md5Mesh, path: &str, anim: Md5Anim) -> Option<Md5Anim> {
+    let mismatches = check_hierarchy(&anim, model);
+    if mismatches.is_empty() {
+        return Some(anim);
+    }
+
+    println!("{} doesn't match the joints of the mesh:", path);
+    for m in &mismatches {
+        println!("  {}", m);
+    }
+
+    match remap_by_name(&anim, model) {
+        Ok(remapped) => {
+            println!("  remapped by joint name");
+            Some(remapped)
+        },
+        Err(_) => {
+            println!("  left out");
+            None
+        }
+    }
+}
+
+fn check_bounds(model: &md5::md5mesh::Md5Mesh, paths: &[String], clips: &mut [Md5Anim], output: Option<String>) {
+    for (path, anim) in paths.iter().zip(clips.iter()) {
+        let mismatches = verify_bounds(anim, model, 1e-3);
+        println!("{}: {} of {} frames with wrong bounds", path, mismatches.len(), anim.frames.len());
+
+        for m in &mismatches {
+            let (min, max) = m.computed;
+            match m.stored {
+                Some((stored_min, stored_max)) =>
+                    println!("  frame {}: {:?} {:?}, skinned {:?} {:?}, off by {}",
+                             m.frame, stored_min, stored_max, min, max, m.error),
+                None => println!("  frame {}: missing, skinned {:?} {:?}", m.frame, min, max)
+            }
+        }
+    }
+
+    if let (Some(output), Some(anim)) = (output, clips.first_mut()) {
+        recompute_bounds(anim, model);
+        match save_md5anim(Path::new(&output), anim) {
+            Ok(()) => println!("wrote {}", output),
+            Err(e) => {
+                eprintln!("failed to write {}: {}", output, e);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+// Room given to each instance of a crowd, from the bounds of the first clip
+fn crowd_spacing(clips: &[Md5Anim]) -> f32 {
+    match clips.first().and_then(|anim| anim.bounds.first()) {
+        Some(bound) => {
+            let size = bound.bound_max - bound.bound_min;
+            size.x.max(size.y).max(size.z) * 1.5
+        },
+        None => 10.0
+    }
+}
+
+fn option_value(args: &[String], option: &str) -> Option<String> {
+    args.iter().position(|a| a == option).and_then(|i| args.get(i + 1)).cloned()
+}
+
+// `--light x,y,z` adds a directional light shining along x,y,z and
+// `--point-light x,y,z[,range]` a point light, both white. Without any, the
+// default lights are used.
+fn parse_lighting(args: &[String]) -> Lighting {
+    let mut lighting = Lighting::new();
+    let mut lights: Vec<Light> = Vec::new();
+    let white = cgmath::Vector3::new(1.0, 1.0, 1.0);
+
+    for (option, value) in args.iter().zip(args.iter().skip(1)) {
+        let v = value.split(',').filter_map(|c| c.trim().parse::<f32>().ok()).collect::<Vec<_>>();
+        if v.len() < 3 {
+            continue;
+        }
+
+        if option == "--light" {
+            lights.push(Light::Directional { direction: cgmath::Vector3::new(v[0], v[1], v[2]), color: white });
+        } else if option == "--point-light" {
+            let range = if v.len() > 3 { v[3] } else { 100.0 };
+            lights.push(Light::Point { position: cgmath::Vector3::new(v[0], v[1], v[2]), color: white, range: range });
+        }
+    }
+
+    if !lights.is_empty() {
+        lighting.lights = lights;
+    }
+    lighting
+}
+
+// `--shadow-resolution n` and `--shadow-bias b` tune the shadow map of the first
+// directional light, `--no-shadows` turns it off
+fn parse_shadows(args: &[String]) -> ShadowConfig {
+    let mut shadows = ShadowConfig::new();
+
+    for (option, value) in args.iter().zip(args.iter().skip(1)) {
+        if option == "--shadow-resolution" {
+            if let Ok(resolution) = value.parse::<u32>() {
+                shadows.resolution = resolution.max(1);
+            }
+        } else if option == "--shadow-bias" {
+            if let Ok(bias) = value.parse::<f32>() {
+                shadows.bias = bias;
+            }
+        }
+    }
+
+    if args.iter().any(|a| a == "--no-shadows") {
+        shadows.enabled = false;
+    }
+    shadows
+}
+
+// `--device name|discrete|integrated|virtual|cpu` picks the GPU, `--present-mode
+// vsync|immediate|mailbox` the swapchain presentation, `--srgb` an sRGB swapchain,
+// `--depth-format d16|d24s8|d32|d32s8` the preferred depth format and `--msaa n`
+// the samples per pixel
+fn parse_renderer_config(args: &[String]) -> RendererConfig {
+    let mut config = RendererConfig::new();
+
+    for (option, value) in args.iter().zip(args.iter().skip(1)) {
+        if option == "--device" {
+            config.device = match DeviceType::from_name(value) {
+                Some(ty) => DeviceSelection::Type(ty),
+                None => DeviceSelection::Name(value.clone())
+            };
+        } else if option == "--present-mode" {
+            match PresentMode::from_name(value) {
+                Some(mode) => config.present_mode = mode,
+                None => println!("unknown present mode {}", value)
+            }
+        } else if option == "--depth-format" {
+            match DepthFormat::from_name(value) {
+                Some(format) => {
+                    config.depth_formats.retain(|&f| f != format);
+                    config.depth_formats.insert(0, format);
+                },
+                None => println!("unknown depth format {}", value)
+            }
+        } else if option == "--msaa" {
+            if let Ok(samples) = value.parse::<u32>() {
+                config.samples = samples.max(1);
+            }
+        }
+    }
 
-    // let s = prepare_mesh(&res.meshes[model_idx], &res.joints);
-    // let n = prepare_normals(&res.meshes[model_idx], &s);
-    // let idx = generate_indices(&res.meshes[model_idx]);
+    if args.iter().any(|a| a == "--srgb") {
+        config.srgb = true;
+    }
+    config
+}
 
-    let (s, n, idx) = prepare_full_mesh(&res);
+fn render_headless(model: &md5::md5mesh::Md5Mesh, materials: &[Material], lighting: &Lighting, shadows: &ShadowConfig, camera: &mut OrbitCamera, output: &Path) -> Result<(), RendererError> {
+    let mut renderer = SoftwareRenderer::new(1024, 768);
 
-    let vertices = posvec3_to_posvulkano(&s);
-    let normales = normvec3_to_normvulkano(&n);
+    prepare_scene(&mut renderer, model, camera)?;
+    renderer.upload_materials(materials)?;
+    renderer.set_lighting(lighting);
+    renderer.set_shadows(shadows)?;
+    renderer.set_camera(camera.view(), camera.projection(renderer.dimensions()));
+    renderer.draw_frame()?;
 
-    //println!("{:?}", vertices);
-    //println!("{:?}", normales);
-    //println!("{:?}", idx);
-    render_model(vertices.as_slice(), normales.as_slice(), idx.as_slice());
-}
\ No newline at end of file
+    renderer.framebuffer.save(output).map_err(|e| RendererError::Capture(format!("{}: {}", output.display(), e)))
+}
@@ -0,0 +1,138 @@
+#![allow(dead_code)]
+use std::io;
+use std::io::Write;
+use std::fs::File;
+use std::path::Path;
+use cgmath::Vector3;
+
+use md5::md5anim::Md5Anim;
+use md5::md5common_parser::quaternion_to_xyz;
+
+// Writes `anim` in the text format read by `md5anim_parser::parse_anim`. The
+// header counts are written as they are, see `Md5Anim::num_frames` and co. The
+// parser needs at least one joint, bound and frame, anims without are refused
+// with `InvalidInput`, see `bounds::recompute_bounds` for the bounds.
+pub fn write_md5anim<W: Write>(w: &mut W, anim: &Md5Anim) -> io::Result<()> {
+    let missing = if anim.hierarchies.is_empty() {
+        Some("joints")
+    } else if anim.bounds.is_empty() {
+        Some("bounds")
+    } else if anim.frames.is_empty() {
+        Some("frames")
+    } else {
+        None
+    };
+    if let Some(missing) = missing {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("anim without {}", missing)));
+    }
+
+    writeln!(w, "MD5Version {}", anim.version)?;
+    writeln!(w, "commandline \"{}\"", anim.command_line.replace('"', "'"))?;
+    writeln!(w, "")?;
+    writeln!(w, "numFrames {}", anim.num_frames)?;
+    writeln!(w, "numJoints {}", anim.num_joints)?;
+    writeln!(w, "frameRate {}", anim.frame_rate)?;
+    writeln!(w, "numAnimatedComponents {}", anim.num_animated_components)?;
+    writeln!(w, "")?;
+
+    writeln!(w, "hierarchy {{")?;
+    for joint in &anim.hierarchies {
+        let parent = if joint.index >= 0 {
+            anim.hierarchies.get(joint.index as usize).map(|p| p.name.as_str()).unwrap_or("")
+        } else {
+            ""
+        };
+        writeln!(w, "\t\"{}\"\t{} {} {}\t//{}", joint.name, joint.index, joint.flag, joint.start_index,
+                 if parent.is_empty() { String::new() } else { format!(" {}", parent) })?;
+    }
+    writeln!(w, "}}")?;
+    writeln!(w, "")?;
+
+    writeln!(w, "bounds {{")?;
+    for bound in &anim.bounds {
+        writeln!(w, "\t{} {}", vector(&bound.bound_min), vector(&bound.bound_max))?;
+    }
+    writeln!(w, "}}")?;
+    writeln!(w, "")?;
+
+    writeln!(w, "baseframe {{")?;
+    for (position, orientation) in anim.base_frame.position.iter().zip(anim.base_frame.orientation.iter()) {
+        writeln!(w, "\t{} {}", vector(position), vector(&quaternion_to_xyz(*orientation)))?;
+    }
+    writeln!(w, "}}")?;
+
+    for frame in &anim.frames {
+        writeln!(w, "")?;
+        writeln!(w, "frame {} {{", frame.frame_number)?;
+        // one line per joint, as exporters do
+        for joint in &anim.hierarchies {
+            let count = (joint.flag & 63).count_ones() as usize;
+            let start = joint.start_index.max(0) as usize;
+            let values = frame.frame_data.iter().skip(start).take(count)
+                .map(|v| number(*v))
+                .collect::<Vec<_>>();
+            if !values.is_empty() {
+                writeln!(w, "\t{}", values.join(" "))?;
+            }
+        }
+        writeln!(w, "}}")?;
+    }
+
+    Ok(())
+}
+
+pub fn save_md5anim(path: &Path, anim: &Md5Anim) -> io::Result<()> {
+    let mut f = File::create(path)?;
+    write_md5anim(&mut f, anim)
+}
+
+fn vector(v: &Vector3<f32>) -> String {
+    format!("( {} {} {} )", number(v.x), number(v.y), number(v.z))
+}
+
+// Fixed notation, the parsers don't read exponents
+fn number(v: f32) -> String {
+    let s = format!("{:.6}", v);
+    if s == "-0.000000" { String::from("0.000000") } else { s }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+    use nom::IResult::Done;
+    use cgmath::Vector3;
+    use animation::skeleton::tests::anim;
+    use md5::md5anim::Bound;
+    use md5::md5anim_parser::parse_anim;
+
+    #[test]
+    fn write_md5anim() {
+        let mut anim = anim();
+        anim.bounds = vec![Bound { bound_min: Vector3::new(-1.0, -0.5, 0.0), bound_max: Vector3::new(1.0, 0.5, 2.25) }; 2];
+        let mut out: Vec<u8> = Vec::new();
+        super::write_md5anim(&mut out, &anim).unwrap();
+
+        match parse_anim(&out) {
+            Done(_, parsed) => {
+                assert_eq!(parsed.hierarchies, anim.hierarchies);
+                assert_eq!(parsed.bounds, anim.bounds);
+                assert_eq!(parsed.base_frame.position, anim.base_frame.position);
+                assert_eq!(parsed.frames, anim.frames);
+            },
+            e => panic!("{:?}", e)
+        }
+    }
+
+    #[test]
+    fn unreadable() {
+        // the parser wants bounds and frames
+        let mut anim = anim();
+        let mut out: Vec<u8> = Vec::new();
+        assert_eq!(super::write_md5anim(&mut out, &anim).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert!(out.is_empty());
+
+        anim.bounds = vec![Bound { bound_min: Vector3::new(0.0, 0.0, 0.0), bound_max: Vector3::new(1.0, 1.0, 1.0) }; 2];
+        anim.frames.clear();
+        assert_eq!(super::write_md5anim(&mut out, &anim).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+}
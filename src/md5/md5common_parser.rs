@@ -140,15 +140,25 @@ named!(pub parse_vector3f32<&[u8], Vector3<f32>>,
     )
 );
 
+// Rebuilds a unit quaternion from the imaginary part stored in the files, the
+// real part being taken negative
+pub fn quaternion_from_xyz(x: f32, y: f32, z: f32) -> Quaternion<f32> {
+    let mut scal : f32 = 1.0 - x * x - y * y - z * z;
+    if scal < 0.0 { scal = 0.0 };
+    Quaternion::new(-scal.sqrt(), x, y, z)
+}
+
+// Imaginary part to store for the unit quaternion `q`, from the equivalent one with
+// a negative real part so that `quaternion_from_xyz` gives it back
+pub fn quaternion_to_xyz(q: Quaternion<f32>) -> Vector3<f32> {
+    if q.s > 0.0 { -q.v } else { q.v }
+}
+
 named!(pub parse_quaternionf32<&[u8], Quaternion<f32>>,
     ws!(
         map!(
             parse_tuple3f32,
-            |(x, y, z)| {
-                let mut scal : f32= 1.0 - x * x - y * y - z * z;
-                if scal < 0.0 { scal = 0.0 };
-                Quaternion::new(-scal.sqrt(), x, y, z) 
-            }
+            |(x, y, z)| quaternion_from_xyz(x, y, z)
         )
     )
 );
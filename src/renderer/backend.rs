@@ -0,0 +1,58 @@
+use cgmath::Matrix4;
+use md5::md5mesh::{Md5Mesh, Joint};
+use renderer::debug::{LinePoint, RenderMode};
+use renderer::error::RendererError;
+use renderer::lighting::Lighting;
+use renderer::material::Material;
+use renderer::scene::InstancePose;
+use renderer::shadow::{ShadowConfig, GroundPlane};
+
+// What the application loop needs from a rendering backend. Implemented by the
+// vulkano renderer and by the software rasterizer. Methods creating GPU resources
+// can fail, the backend is unusable after a `RendererError::DeviceLost`.
+pub trait Renderer {
+    // Uploads every mesh of `model`, skinned in its bind pose
+    fn upload_mesh(&mut self, model: &Md5Mesh) -> Result<(), RendererError>;
+
+    // Textures of each mesh of the model, in order. Meshes without one use
+    // `Material::new`.
+    fn upload_materials(&mut self, materials: &[Material]) -> Result<(), RendererError>;
+
+    // Skins the uploaded model against `skeleton`, given in object space and in
+    // the same order as the model joints
+    fn update_pose(&mut self, skeleton: &[Joint]) -> Result<(), RendererError>;
+
+    // Draws the model once per instance instead of once in the pose of
+    // `update_pose`, each instance transform being applied before the model
+    // transform. None goes back to the single model.
+    fn set_instances(&mut self, instances: Option<&[InstancePose]>) -> Result<(), RendererError>;
+
+    fn set_model_transform(&mut self, world: Matrix4<f32>);
+
+    fn set_camera(&mut self, view: Matrix4<f32>, proj: Matrix4<f32>);
+
+    fn set_lighting(&mut self, lighting: &Lighting);
+
+    // Shadows of the first directional light, cast on the model and on the ground
+    fn set_shadows(&mut self, config: &ShadowConfig) -> Result<(), RendererError>;
+
+    // Plane drawn under the model, in object space, or none
+    fn set_ground(&mut self, ground: Option<GroundPlane>) -> Result<(), RendererError>;
+
+    // Lines drawn over the model, in object space, until replaced
+    fn set_debug_lines(&mut self, lines: &[LinePoint]) -> Result<(), RendererError>;
+
+    fn set_render_mode(&mut self, mode: RenderMode);
+
+    fn draw_frame(&mut self) -> Result<(), RendererError>;
+
+    // RGBA8 pixels of the frame as drawn by `draw_frame`, top row first, of the
+    // current dimensions
+    fn read_pixels(&mut self) -> Result<Vec<u8>, RendererError>;
+
+    fn resize(&mut self, dimensions: [u32; 2]);
+
+    fn dimensions(&self) -> [u32; 2];
+
+    fn set_title(&mut self, title: &str);
+}
@@ -0,0 +1,205 @@
+use cgmath::{Vector3, Point3, Matrix4, Rad, InnerSpace, EuclideanSpace};
+use winit;
+use std::f32;
+
+// Orbit camera: rotates around `target` at `distance`, `yaw` turning around the
+// up axis and `pitch` tilting towards it.
+#[derive(Clone, Debug)]
+pub struct OrbitCamera {
+    pub target: Point3<f32>,
+    pub distance: f32,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub up: Vector3<f32>,
+    pub fov: Rad<f32>,
+
+    home: (Point3<f32>, f32, f32, f32),
+    rotating: bool,
+    panning: bool,
+    cursor: Option<(f64, f64)>,
+}
+
+const ORBIT_SPEED: f32 = 0.01;
+const KEY_ORBIT_STEP: f32 = 0.1;
+const ZOOM_STEP: f32 = 1.1;
+
+impl OrbitCamera {
+    pub fn new(up: Vector3<f32>) -> OrbitCamera {
+        let target = Point3::new(0.0, 0.0, 0.0);
+
+        OrbitCamera {
+            target: target,
+            distance: 1.0,
+            yaw: 0.0,
+            pitch: 0.0,
+            up: up.normalize(),
+            fov: Rad(f32::consts::FRAC_PI_2),
+
+            home: (target, 1.0, 0.0, 0.0),
+            rotating: false,
+            panning: false,
+            cursor: None,
+        }
+    }
+
+    // Centers the camera on the box and backs off until all of it is visible.
+    // The result becomes the state restored by `reset`.
+    pub fn frame_bounds(&mut self, min: Vector3<f32>, max: Vector3<f32>) {
+        let radius = ((max - min).magnitude() * 0.5).max(0.001);
+
+        self.target = Point3::from_vec((min + max) * 0.5);
+        self.distance = radius / (self.fov.0 * 0.5).sin() * 1.1;
+        self.yaw = f32::consts::FRAC_PI_4;
+        self.pitch = 0.3;
+
+        self.home = (self.target, self.distance, self.yaw, self.pitch);
+    }
+
+    pub fn reset(&mut self) {
+        let (target, distance, yaw, pitch) = self.home;
+
+        self.target = target;
+        self.distance = distance;
+        self.yaw = yaw;
+        self.pitch = pitch;
+    }
+
+    pub fn orbit(&mut self, d_yaw: f32, d_pitch: f32) {
+        let limit = f32::consts::FRAC_PI_2 - 0.01;
+
+        self.yaw += d_yaw;
+        self.pitch = (self.pitch + d_pitch).max(-limit).min(limit);
+    }
+
+    // Moves the target in the view plane, `dx` and `dy` being fractions of the distance
+    pub fn pan(&mut self, dx: f32, dy: f32) {
+        let forward = (self.target - self.eye()).normalize();
+        let right = forward.cross(self.up).normalize();
+        let up = right.cross(forward);
+
+        self.target += (right * -dx + up * dy) * self.distance;
+    }
+
+    pub fn zoom(&mut self, factor: f32) {
+        self.distance = (self.distance * factor).max(0.001);
+    }
+
+    pub fn eye(&self) -> Point3<f32> {
+        let (forward, right) = self.basis();
+        let horizontal = forward * self.yaw.cos() + right * self.yaw.sin();
+        let offset = horizontal * self.pitch.cos() + self.up * self.pitch.sin();
+
+        self.target + offset * self.distance
+    }
+
+    pub fn view(&self) -> Matrix4<f32> {
+        Matrix4::look_at(self.eye(), self.target, self.up)
+    }
+
+    // Vulkan has the origin at the upper left, so the Y axis is reversed here
+    pub fn projection(&self, dimensions: [u32; 2]) -> Matrix4<f32> {
+        let aspect = dimensions[0] as f32 / dimensions[1] as f32;
+        let near = self.distance * 0.01;
+        let far = self.distance * 100.0;
+
+        Matrix4::from_nonuniform_scale(1.0, -1.0, 1.0) * ::cgmath::perspective(self.fov, aspect, near, far)
+    }
+
+    // Left drag orbits, right or middle drag pans, the wheel zooms.
+    // Arrows orbit, page up/down zoom and R resets. Returns whether the event was used.
+    pub fn handle_event(&mut self, event: &winit::WindowEvent) -> bool {
+        match *event {
+            winit::WindowEvent::MouseInput { state, button, .. } => {
+                let pressed = state == winit::ElementState::Pressed;
+                match button {
+                    winit::MouseButton::Left => self.rotating = pressed,
+                    winit::MouseButton::Right | winit::MouseButton::Middle => self.panning = pressed,
+                    _ => return false
+                }
+                true
+            },
+            winit::WindowEvent::CursorMoved { position, .. } => {
+                let used = match self.cursor {
+                    Some((x, y)) => {
+                        let dx = (position.0 - x) as f32;
+                        let dy = (position.1 - y) as f32;
+
+                        if self.rotating {
+                            self.orbit(-dx * ORBIT_SPEED, dy * ORBIT_SPEED);
+                        } else if self.panning {
+                            self.pan(dx * ORBIT_SPEED * 0.1, dy * ORBIT_SPEED * 0.1);
+                        }
+                        self.rotating || self.panning
+                    },
+                    None => false
+                };
+                self.cursor = Some(position);
+                used
+            },
+            winit::WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    winit::MouseScrollDelta::LineDelta(_, y) => y,
+                    winit::MouseScrollDelta::PixelDelta(_, y) => y / 20.0,
+                };
+                self.zoom(ZOOM_STEP.powf(-lines));
+                true
+            },
+            winit::WindowEvent::KeyboardInput { input: winit::KeyboardInput { state: winit::ElementState::Pressed, virtual_keycode: Some(key), .. }, .. } => {
+                match key {
+                    winit::VirtualKeyCode::Left => self.orbit(KEY_ORBIT_STEP, 0.0),
+                    winit::VirtualKeyCode::Right => self.orbit(-KEY_ORBIT_STEP, 0.0),
+                    winit::VirtualKeyCode::Up => self.orbit(0.0, KEY_ORBIT_STEP),
+                    winit::VirtualKeyCode::Down => self.orbit(0.0, -KEY_ORBIT_STEP),
+                    winit::VirtualKeyCode::PageUp => self.zoom(1.0 / ZOOM_STEP),
+                    winit::VirtualKeyCode::PageDown => self.zoom(ZOOM_STEP),
+                    winit::VirtualKeyCode::R => self.reset(),
+                    _ => return false
+                }
+                true
+            },
+            _ => false
+        }
+    }
+
+    // Two axes orthogonal to `up`, yaw 0 looking back along the first one
+    fn basis(&self) -> (Vector3<f32>, Vector3<f32>) {
+        let reference = if self.up.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+        let right = self.up.cross(reference).normalize();
+        let forward = right.cross(self.up);
+
+        (forward, right)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector3, Vector4, Point3, MetricSpace};
+
+    #[test]
+    fn frame_bounds() {
+        let mut camera = super::OrbitCamera::new(Vector3::new(0.0, 0.0, 1.0));
+        camera.frame_bounds(Vector3::new(-1.0, -1.0, 0.0), Vector3::new(1.0, 1.0, 6.0));
+
+        assert_eq!(camera.target, Point3::new(0.0, 0.0, 3.0));
+        assert!((camera.eye().distance(camera.target) - camera.distance).abs() < 1e-4);
+
+        // the target ends up straight ahead of the camera
+        let p = camera.view() * Vector4::new(0.0, 0.0, 3.0, 1.0);
+        assert!(p.x.abs() < 1e-4 && p.y.abs() < 1e-4);
+        assert!((p.z + camera.distance).abs() < 1e-4);
+    }
+
+    #[test]
+    fn reset() {
+        let mut camera = super::OrbitCamera::new(Vector3::new(0.0, 1.0, 0.0));
+        camera.frame_bounds(Vector3::new(-1.0, -1.0, -1.0), Vector3::new(1.0, 1.0, 1.0));
+        let eye = camera.eye();
+
+        camera.orbit(1.0, 0.5);
+        camera.pan(0.2, 0.1);
+        camera.zoom(3.0);
+        camera.reset();
+
+        assert!(camera.eye().distance(eye) < 1e-5);
+    }
+}
@@ -0,0 +1,84 @@
+#![allow(dead_code)]
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use animation::skeleton::{sample_pose, to_object_space};
+use md5::md5anim::Md5Anim;
+use renderer::backend::Renderer;
+use renderer::error::RendererError;
+use renderer::image::save_rgba;
+
+// Saves the last frame of `renderer` to `path`, as a PNG unless the extension is .ppm
+pub fn save_screenshot<R: Renderer>(renderer: &mut R, path: &Path) -> Result<(), RendererError> {
+    let pixels = renderer.read_pixels()?;
+    let dimensions = renderer.dimensions();
+
+    save_rgba(path, dimensions[0] as usize, dimensions[1] as usize, &pixels)
+        .map_err(|e| RendererError::Capture(format!("{}: {}", path.display(), e)))
+}
+
+// Path of a screenshot taken now, in the working directory. The milliseconds
+// and a counter keep screenshots taken in a row from overwriting each other.
+pub fn screenshot_path() -> PathBuf {
+    let (secs, millis) = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| (d.as_secs(), d.subsec_nanos() / 1_000_000))
+        .unwrap_or((0, 0));
+
+    let mut path = PathBuf::from(format!("amalia-{}-{:03}.png", secs, millis));
+    let mut n = 1;
+    while path.exists() {
+        path = PathBuf::from(format!("amalia-{}-{:03}-{}.png", secs, millis, n));
+        n += 1;
+    }
+    path
+}
+
+// Times of the images of a sequence of `anim` at `fps` images per second. The
+// sequence covers one loop of the clip, the last frame blending back into the
+// first one, so that it can be played in a loop too.
+pub fn sequence_times(anim: &Md5Anim, fps: f32) -> Vec<f32> {
+    if anim.frames.is_empty() || anim.frame_rate <= 0 || fps <= 0.0 {
+        return Vec::new();
+    }
+
+    let duration = anim.frames.len() as f32 / anim.frame_rate as f32;
+    let count = (duration * fps - 1e-3).ceil().max(1.0) as usize;
+    (0..count).map(|i| i as f32 / fps).collect()
+}
+
+// Renders `anim` at a fixed timestep of 1 / `fps` to `dir/frame_0000.png`, ...,
+// with the camera and settings already given to `renderer`. Returns the number of
+// images written.
+pub fn capture_sequence<R: Renderer>(renderer: &mut R, anim: &Md5Anim, fps: f32, dir: &Path) -> Result<usize, RendererError> {
+    fs::create_dir_all(dir).map_err(|e| RendererError::Capture(format!("{}: {}", dir.display(), e)))?;
+
+    let times = sequence_times(anim, fps);
+    for (i, &time) in times.iter().enumerate() {
+        renderer.update_pose(&to_object_space(&anim.hierarchies, &sample_pose(anim, time, true)))?;
+        renderer.draw_frame()?;
+        save_screenshot(renderer, &dir.join(format!("frame_{:04}.png", i)))?;
+    }
+
+    Ok(times.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use animation::skeleton::tests::anim;
+
+    #[test]
+    fn sequence_times() {
+        let mut anim = anim();
+        anim.frame_rate = 24;
+        let frames = anim.frames.len();
+
+        // at the frame rate of the clip, one image per frame
+        let times = super::sequence_times(&anim, 24.0);
+        assert_eq!(times.len(), frames);
+        assert_eq!(times[1], 1.0 / 24.0);
+
+        assert_eq!(super::sequence_times(&anim, 48.0).len(), frames * 2);
+        assert!(super::sequence_times(&anim, 0.0).is_empty());
+    }
+}
@@ -0,0 +1,184 @@
+#![allow(dead_code)]
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum DeviceType {
+    Discrete,
+    Integrated,
+    Virtual,
+    Cpu,
+    Other,
+}
+
+impl DeviceType {
+    pub fn from_name(name: &str) -> Option<DeviceType> {
+        match name.to_lowercase().as_str() {
+            "discrete" => Some(DeviceType::Discrete),
+            "integrated" => Some(DeviceType::Integrated),
+            "virtual" => Some(DeviceType::Virtual),
+            "cpu" => Some(DeviceType::Cpu),
+            _ => None
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum DeviceSelection {
+    // first device enumerated
+    First,
+    // first device whose name contains this, ignoring case
+    Name(String),
+    // first device of this type
+    Type(DeviceType),
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PresentMode {
+    // FIFO, always supported
+    Vsync,
+    // no wait for the vertical blank, may tear
+    Immediate,
+    // no tearing, latest frame replacing the queued one
+    Mailbox,
+}
+
+impl PresentMode {
+    pub fn from_name(name: &str) -> Option<PresentMode> {
+        match name.to_lowercase().as_str() {
+            "vsync" | "fifo" | "on" => Some(PresentMode::Vsync),
+            "immediate" | "off" => Some(PresentMode::Immediate),
+            "mailbox" => Some(PresentMode::Mailbox),
+            _ => None
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum DepthFormat {
+    D16,
+    D24S8,
+    D32,
+    D32S8,
+}
+
+impl DepthFormat {
+    pub fn from_name(name: &str) -> Option<DepthFormat> {
+        match name.to_lowercase().as_str() {
+            "d16" => Some(DepthFormat::D16),
+            "d24s8" => Some(DepthFormat::D24S8),
+            "d32" => Some(DepthFormat::D32),
+            "d32s8" => Some(DepthFormat::D32S8),
+            _ => None
+        }
+    }
+}
+
+// Choices made by the vulkano renderer at startup, each falling back to something
+// the device supports
+#[derive(Clone, PartialEq, Debug)]
+pub struct RendererConfig {
+    pub device: DeviceSelection,
+    // sRGB swapchain, with the color textures decoded to linear when sampled. Off,
+    // colors are written as computed, like the software renderer does.
+    pub srgb: bool,
+    pub present_mode: PresentMode,
+    // depth formats by preference, the first supported one is used
+    pub depth_formats: Vec<DepthFormat>,
+    // MSAA samples per pixel, lowered to what the device supports
+    pub samples: u32,
+}
+
+impl RendererConfig {
+    pub fn new() -> RendererConfig {
+        RendererConfig {
+            device: DeviceSelection::First,
+            srgb: false,
+            present_mode: PresentMode::Vsync,
+            depth_formats: vec![DepthFormat::D16, DepthFormat::D32, DepthFormat::D24S8, DepthFormat::D32S8],
+            samples: 1,
+        }
+    }
+
+    // Index of the selected device among (name, type) pairs, the first one when
+    // none matches
+    pub fn pick_device(&self, devices: &[(String, DeviceType)]) -> Option<usize> {
+        let found = match self.device {
+            DeviceSelection::First => None,
+            DeviceSelection::Name(ref name) => {
+                let name = name.to_lowercase();
+                devices.iter().position(|&(ref n, _)| n.to_lowercase().contains(&name))
+            },
+            DeviceSelection::Type(ty) => devices.iter().position(|&(_, t)| t == ty),
+        };
+
+        if found.is_none() && self.device != DeviceSelection::First && !devices.is_empty() {
+            println!("no device matching {:?}, using the first one", self.device);
+        }
+        found.or(if devices.is_empty() { None } else { Some(0) })
+    }
+
+    // Index of the swapchain format to use, given whether each supported format is sRGB
+    pub fn pick_color_format(&self, srgb_formats: &[bool]) -> usize {
+        srgb_formats.iter().position(|&srgb| srgb == self.srgb).unwrap_or(0)
+    }
+
+    pub fn pick_present_mode(&self, supported: &[PresentMode]) -> PresentMode {
+        if supported.contains(&self.present_mode) {
+            self.present_mode
+        } else {
+            PresentMode::Vsync
+        }
+    }
+
+    pub fn pick_depth_format(&self, supported: &[DepthFormat]) -> Option<DepthFormat> {
+        self.depth_formats.iter().cloned().find(|f| supported.contains(f))
+    }
+
+    // Highest power of two sample count up to `samples` in the `supported` mask,
+    // bit n standing for 2^n samples as in Vulkan
+    pub fn pick_samples(&self, supported: u32) -> u32 {
+        let mut samples = 1;
+        while samples * 2 <= self.samples && supported & (samples * 2) != 0 {
+            samples *= 2;
+        }
+        samples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RendererConfig, DeviceSelection, DeviceType, PresentMode, DepthFormat};
+
+    #[test]
+    fn pick_device() {
+        let devices = vec![(String::from("Intel HD 620"), DeviceType::Integrated),
+                           (String::from("GeForce GTX 1060"), DeviceType::Discrete)];
+        let mut config = RendererConfig::new();
+
+        assert_eq!(config.pick_device(&devices), Some(0));
+        config.device = DeviceSelection::Type(DeviceType::Discrete);
+        assert_eq!(config.pick_device(&devices), Some(1));
+        config.device = DeviceSelection::Name(String::from("geforce"));
+        assert_eq!(config.pick_device(&devices), Some(1));
+        config.device = DeviceSelection::Type(DeviceType::Cpu);
+        assert_eq!(config.pick_device(&devices), Some(0));
+        assert_eq!(config.pick_device(&[]), None);
+    }
+
+    #[test]
+    fn fallbacks() {
+        let mut config = RendererConfig::new();
+        config.srgb = true;
+        config.present_mode = PresentMode::Mailbox;
+        config.depth_formats = vec![DepthFormat::D32, DepthFormat::D24S8];
+        config.samples = 8;
+
+        assert_eq!(config.pick_color_format(&[false, true]), 1);
+        assert_eq!(config.pick_color_format(&[false]), 0);
+        assert_eq!(config.pick_present_mode(&[PresentMode::Vsync, PresentMode::Immediate]), PresentMode::Vsync);
+        assert_eq!(config.pick_depth_format(&[DepthFormat::D16, DepthFormat::D24S8]), Some(DepthFormat::D24S8));
+        // 1, 2 and 4 samples
+        assert_eq!(config.pick_samples(0b111), 4);
+        config.samples = 3;
+        assert_eq!(config.pick_samples(0b111), 2);
+    }
+}
@@ -0,0 +1,314 @@
+use cgmath::{Vector3};
+use winit;
+
+use md5::md5mesh::{Md5Mesh, Joint};
+use vertex_computation::compute::{prepare_skinned_mesh, prepare_full_tangents};
+
+// End point of a debug line segment, consecutive pairs forming a line list
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct LinePoint {
+    pub position: Vector3<f32>,
+    pub color: Vector3<f32>,
+}
+
+const BONE_COLOR: [f32; 3] = [1.0, 1.0, 0.0];
+const LABEL_COLOR: [f32; 3] = [1.0, 1.0, 1.0];
+const NORMAL_COLOR: [f32; 3] = [0.0, 1.0, 1.0];
+const TANGENT_COLOR: [f32; 3] = [1.0, 0.0, 1.0];
+
+// How the backends fill the triangles of the model
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum RenderMode {
+    Shaded,
+    // triangle edges only, seen through the model
+    Wireframe,
+    // 8x8 checkerboard in texture space, to spot stretched or flipped UVs
+    UvChecker,
+    // influence of the given joint, from blue (none) to red (full)
+    WeightHeatmap(u32),
+}
+
+// Runtime toggles of the debug overlay
+#[derive(Clone, Debug)]
+pub struct DebugOverlay {
+    pub skeleton: bool,
+    pub joint_names: bool,
+    pub normals: bool,
+    pub tangents: bool,
+    pub mode: RenderMode,
+    // joint shown by the weight heatmap, wrapped to the joint count
+    pub joint: i32,
+}
+
+impl DebugOverlay {
+    pub fn new() -> DebugOverlay {
+        DebugOverlay {
+            skeleton: false,
+            joint_names: false,
+            normals: false,
+            tangents: false,
+            mode: RenderMode::Shaded,
+            joint: 0,
+        }
+    }
+
+    fn selected_joint(&self, joint_count: usize) -> usize {
+        let count = joint_count.max(1) as i32;
+        (((self.joint % count) + count) % count) as usize
+    }
+
+    // Mode to pass to `Renderer::set_render_mode`
+    pub fn render_mode(&self, joint_count: usize) -> RenderMode {
+        match self.mode {
+            RenderMode::WeightHeatmap(_) => RenderMode::WeightHeatmap(self.selected_joint(joint_count) as u32),
+            mode => mode
+        }
+    }
+
+    pub fn next_mode(&mut self) {
+        self.mode = match self.mode {
+            RenderMode::Shaded => RenderMode::Wireframe,
+            RenderMode::Wireframe => RenderMode::UvChecker,
+            RenderMode::UvChecker => RenderMode::WeightHeatmap(0),
+            RenderMode::WeightHeatmap(_) => RenderMode::Shaded,
+        };
+    }
+
+    pub fn status(&self, joints: &[Joint]) -> String {
+        match self.mode {
+            RenderMode::Shaded => String::new(),
+            RenderMode::Wireframe => String::from("wireframe"),
+            RenderMode::UvChecker => String::from("uv checker"),
+            RenderMode::WeightHeatmap(_) => match joints.get(self.selected_joint(joints.len())) {
+                Some(joint) => format!("weights of {}", joint.name),
+                None => String::from("weights")
+            }
+        }
+    }
+
+    // Lines for the enabled parts of the overlay, `model` being skinned against
+    // `joints`. `right` and `up` orient the labels towards the camera and `size`
+    // is the height of the labels, axes and vertex vectors.
+    pub fn lines(&self, model: &Md5Mesh, joints: &[Joint], right: Vector3<f32>, up: Vector3<f32>, size: f32) -> Vec<LinePoint> {
+        let mut res: Vec<LinePoint> = Vec::new();
+
+        if self.skeleton {
+            res.append(&mut skeleton_lines(joints, size));
+        }
+        if self.joint_names {
+            res.append(&mut label_lines(joints, right, up, size));
+        }
+        if self.normals || self.tangents {
+            let (positions, normals, _) = prepare_skinned_mesh(model, joints);
+
+            if self.normals {
+                res.append(&mut vector_lines(&positions, &normals, size, NORMAL_COLOR));
+            }
+            if self.tangents {
+                let tangents = prepare_full_tangents(model, &positions, &normals).iter().map(|t| t.truncate()).collect::<Vec<_>>();
+                res.append(&mut vector_lines(&positions, &tangents, size, TANGENT_COLOR));
+            }
+        }
+        res
+    }
+
+    // K toggles the bones, J the joint names, N the normals and T the tangents.
+    // M cycles through the render modes and the brackets pick the joint of the
+    // weight heatmap. Returns whether the event was used.
+    pub fn handle_event(&mut self, event: &winit::WindowEvent) -> bool {
+        match *event {
+            winit::WindowEvent::KeyboardInput { input: winit::KeyboardInput { state: winit::ElementState::Pressed, virtual_keycode: Some(key), .. }, .. } => {
+                match key {
+                    winit::VirtualKeyCode::K => self.skeleton = !self.skeleton,
+                    winit::VirtualKeyCode::J => self.joint_names = !self.joint_names,
+                    winit::VirtualKeyCode::N => self.normals = !self.normals,
+                    winit::VirtualKeyCode::T => self.tangents = !self.tangents,
+                    winit::VirtualKeyCode::M => self.next_mode(),
+                    winit::VirtualKeyCode::LBracket => self.joint -= 1,
+                    winit::VirtualKeyCode::RBracket => self.joint += 1,
+                    _ => return false
+                }
+                true
+            },
+            _ => false
+        }
+    }
+}
+
+fn push_line(res: &mut Vec<LinePoint>, a: Vector3<f32>, b: Vector3<f32>, color: [f32; 3]) {
+    let color = Vector3::new(color[0], color[1], color[2]);
+
+    res.push(LinePoint { position: a, color: color });
+    res.push(LinePoint { position: b, color: color });
+}
+
+// A line from each joint to its parent and the joint axes, X red, Y green and Z blue
+pub fn skeleton_lines(joints: &[Joint], axis_length: f32) -> Vec<LinePoint> {
+    let mut res: Vec<LinePoint> = Vec::new();
+
+    for j in joints {
+        if j.parent_index >= 0 {
+            push_line(&mut res, joints[j.parent_index as usize].position, j.position, BONE_COLOR);
+        }
+
+        push_line(&mut res, j.position, j.position + j.orientation * Vector3::unit_x() * axis_length, [1.0, 0.0, 0.0]);
+        push_line(&mut res, j.position, j.position + j.orientation * Vector3::unit_y() * axis_length, [0.0, 1.0, 0.0]);
+        push_line(&mut res, j.position, j.position + j.orientation * Vector3::unit_z() * axis_length, [0.0, 0.0, 1.0]);
+    }
+
+    res
+}
+
+// A line of `length` from each position along its direction
+pub fn vector_lines(positions: &[Vector3<f32>], directions: &[Vector3<f32>], length: f32, color: [f32; 3]) -> Vec<LinePoint> {
+    let mut res: Vec<LinePoint> = Vec::new();
+
+    for (p, d) in positions.iter().zip(directions.iter()) {
+        push_line(&mut res, *p, *p + d * length, color);
+    }
+
+    res
+}
+
+// Every edge of an indexed triangle list, shared edges being drawn twice
+pub fn wireframe_lines(positions: &[Vector3<f32>], indices: &[u16], color: [f32; 3]) -> Vec<LinePoint> {
+    let mut res: Vec<LinePoint> = Vec::new();
+
+    for t in indices.chunks(3) {
+        if t.len() < 3 {
+            break;
+        }
+
+        for k in 0..3 {
+            push_line(&mut res, positions[t[k] as usize], positions[t[(k + 1) % 3] as usize], color);
+        }
+    }
+
+    res
+}
+
+// Stroke font on a 3x5 grid. Each stroke is a polyline of "xy" digit pairs.
+fn glyph(c: char) -> &'static str {
+    match c.to_ascii_uppercase() {
+        'A' => "0003142320 0222",
+        'B' => "0004142312 0212 12211000",
+        'C' => "24040020",
+        'D' => "00041423211000",
+        'E' => "24040020 0212",
+        'F' => "240400 0212",
+        'G' => "240400202212",
+        'H' => "0004 2024 0222",
+        'I' => "0424 1410 0020",
+        'J' => "24200001",
+        'K' => "0004 240220",
+        'L' => "040020",
+        'M' => "0004122420",
+        'N' => "00042024",
+        'O' | '0' => "0004242000",
+        'P' => "0004242202",
+        'Q' => "0004242000 1120",
+        'R' => "0004242202 1220",
+        'S' | '5' => "240402222000",
+        'T' => "0424 1410",
+        'U' => "04002024",
+        'V' => "041024",
+        'W' => "0400122024",
+        'X' => "0024 0420",
+        'Y' => "0412 2412 1210",
+        'Z' => "04240020",
+        '1' => "0314 1410 0020",
+        '2' => "042422020020",
+        '3' => "04242000 0222",
+        '4' => "040222 2420",
+        '6' => "240400202202",
+        '7' => "042420",
+        '8' => "0004242000 0222",
+        '9' => "220204242000",
+        '.' => "1011",
+        '_' => "0020",
+        '-' => "0222",
+        _ => ""
+    }
+}
+
+// `text` drawn with its baseline starting at `origin`
+pub fn text_lines(text: &str, origin: Vector3<f32>, right: Vector3<f32>, up: Vector3<f32>, height: f32, color: [f32; 3]) -> Vec<LinePoint> {
+    let mut res: Vec<LinePoint> = Vec::new();
+    let unit = height / 4.0;
+
+    for (i, c) in text.chars().enumerate() {
+        let start = origin + right * (i as f32 * 3.0 * unit);
+
+        for stroke in glyph(c).split(' ') {
+            let points = stroke.as_bytes().chunks(2).map(|xy| {
+                let x = (xy[0] - b'0') as f32;
+                let y = (xy[1] - b'0') as f32;
+                start + right * (x * unit) + up * (y * unit)
+            }).collect::<Vec<_>>();
+
+            for k in 1..points.len() {
+                push_line(&mut res, points[k - 1], points[k], color);
+            }
+        }
+    }
+
+    res
+}
+
+// The name of each joint, written next to it
+pub fn label_lines(joints: &[Joint], right: Vector3<f32>, up: Vector3<f32>, height: f32) -> Vec<LinePoint> {
+    let mut res: Vec<LinePoint> = Vec::new();
+
+    for j in joints {
+        let origin = j.position + right * (height * 0.5);
+        res.append(&mut text_lines(&j.name, origin, right, up, height, LABEL_COLOR));
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector3, Quaternion};
+    use md5::md5mesh::Joint;
+
+    #[test]
+    fn skeleton_lines() {
+        let joints = vec![
+            Joint { name: String::from("origin"), parent_index: -1, position: Vector3::new(0.0, 0.0, 0.0), orientation: Quaternion::new(1.0, 0.0, 0.0, 0.0) },
+            Joint { name: String::from("spine"), parent_index: 0, position: Vector3::new(0.0, 0.0, 2.0), orientation: Quaternion::new(1.0, 0.0, 0.0, 0.0) },
+        ];
+
+        let lines = super::skeleton_lines(&joints, 0.5);
+
+        // three axes per joint and one bone
+        assert_eq!(lines.len(), 2 * 7);
+        assert_eq!(lines[6].position, Vector3::new(0.0, 0.0, 0.0));
+        assert_eq!(lines[7].position, Vector3::new(0.0, 0.0, 2.0));
+        assert_eq!(lines[9].position, Vector3::new(0.5, 0.0, 2.0));
+    }
+
+    #[test]
+    fn render_mode() {
+        let mut overlay = super::DebugOverlay::new();
+        overlay.next_mode();
+        assert_eq!(overlay.render_mode(3), super::RenderMode::Wireframe);
+
+        overlay.next_mode();
+        overlay.next_mode();
+        overlay.joint = -1;
+        assert_eq!(overlay.render_mode(3), super::RenderMode::WeightHeatmap(2));
+    }
+
+    #[test]
+    fn text_lines() {
+        let lines = super::text_lines("L.", Vector3::new(0.0, 0.0, 0.0), Vector3::unit_x(), Vector3::unit_y(), 4.0, [1.0, 1.0, 1.0]);
+        let points = lines.iter().map(|p| p.position).collect::<Vec<_>>();
+
+        assert_eq!(points, vec![
+            Vector3::new(0.0, 4.0, 0.0), Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, 0.0), Vector3::new(2.0, 0.0, 0.0),
+            Vector3::new(4.0, 0.0, 0.0), Vector3::new(4.0, 1.0, 0.0),
+        ]);
+    }
+}
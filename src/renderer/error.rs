@@ -0,0 +1,58 @@
+use std::error::Error;
+use std::fmt;
+
+// Failures of a rendering backend. The vulkano errors are kept as their debug
+// output, so that callers don't depend on vulkano.
+#[derive(Clone, PartialEq, Debug)]
+pub enum RendererError {
+    Instance(String),
+    // no physical device, or none with a queue able to draw to the window
+    NoDevice,
+    Device(String),
+    Surface(String),
+    Swapchain(String),
+    // shaders, render passes and pipelines
+    Pipeline(String),
+    // buffers, images, samplers, descriptor sets and framebuffers
+    Resource(String),
+    // recording or submission of the commands of a frame
+    Frame(String),
+    // the device was lost, nothing can be drawn with it anymore
+    DeviceLost,
+    // writing the rendered image, a screenshot or an image sequence
+    Capture(String),
+}
+
+impl fmt::Display for RendererError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RendererError::Instance(ref e) => write!(f, "failed to create the Vulkan instance: {}", e),
+            RendererError::NoDevice => write!(f, "no device able to draw to the window"),
+            RendererError::Device(ref e) => write!(f, "failed to create the device: {}", e),
+            RendererError::Surface(ref e) => write!(f, "window surface error: {}", e),
+            RendererError::Swapchain(ref e) => write!(f, "swapchain error: {}", e),
+            RendererError::Pipeline(ref e) => write!(f, "failed to create a pipeline: {}", e),
+            RendererError::Resource(ref e) => write!(f, "failed to create a GPU resource: {}", e),
+            RendererError::Frame(ref e) => write!(f, "failed to draw a frame: {}", e),
+            RendererError::DeviceLost => write!(f, "the device was lost"),
+            RendererError::Capture(ref e) => write!(f, "failed to save a capture: {}", e),
+        }
+    }
+}
+
+impl Error for RendererError {
+    fn description(&self) -> &str {
+        match *self {
+            RendererError::Instance(_) => "instance creation failed",
+            RendererError::NoDevice => "no device",
+            RendererError::Device(_) => "device creation failed",
+            RendererError::Surface(_) => "surface error",
+            RendererError::Swapchain(_) => "swapchain error",
+            RendererError::Pipeline(_) => "pipeline creation failed",
+            RendererError::Resource(_) => "resource creation failed",
+            RendererError::Frame(_) => "frame failed",
+            RendererError::DeviceLost => "device lost",
+            RendererError::Capture(_) => "capture failed",
+        }
+    }
+}
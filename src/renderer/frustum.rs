@@ -0,0 +1,135 @@
+#![allow(dead_code)]
+use cgmath::{Vector3, Vector4, Matrix4, Matrix, InnerSpace};
+
+use animation::bounds::{frame_bounds, bounds_at};
+use md5::md5anim::Md5Anim;
+
+// View frustum as six planes (a, b, c, d), a point p being inside a plane when
+// a * p.x + b * p.y + c * p.z + d >= 0. The near plane is the OpenGL one, at
+// z = -w, which keeps everything the Vulkan depth range keeps.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Frustum {
+    pub planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    // Frustum of the clip volume of `matrix`, usually proj * view. With the world
+    // matrix too, the planes are in object space.
+    pub fn from_matrix(matrix: &Matrix4<f32>) -> Frustum {
+        let (r0, r1, r2, r3) = (matrix.row(0), matrix.row(1), matrix.row(2), matrix.row(3));
+
+        Frustum {
+            planes: [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r3 + r2, r3 - r2],
+        }
+    }
+
+    pub fn contains_point(&self, p: Vector3<f32>) -> bool {
+        self.planes.iter().all(|plane| plane.truncate().dot(p) + plane.w >= 0.0)
+    }
+
+    // Whether the box from `min` to `max` may be in the frustum. Boxes near a corner
+    // of the frustum can be kept while outside, never the other way.
+    pub fn intersects_aabb(&self, min: Vector3<f32>, max: Vector3<f32>) -> bool {
+        self.planes.iter().all(|plane| {
+            // corner of the box the furthest along the plane normal
+            let corner = Vector3::new(if plane.x >= 0.0 { max.x } else { min.x },
+                                      if plane.y >= 0.0 { max.y } else { min.y },
+                                      if plane.z >= 0.0 { max.z } else { min.z });
+            plane.truncate().dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+// Axis aligned box enclosing the box from `min` to `max` transformed by `matrix`,
+// an affine transform
+pub fn transform_aabb(matrix: &Matrix4<f32>, min: Vector3<f32>, max: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let center = (min + max) * 0.5;
+    let half = (max - min) * 0.5;
+
+    let new_center = (matrix * center.extend(1.0)).truncate();
+    let new_half = Vector3::new(
+        matrix.x.x.abs() * half.x + matrix.y.x.abs() * half.y + matrix.z.x.abs() * half.z,
+        matrix.x.y.abs() * half.x + matrix.y.y.abs() * half.y + matrix.z.y.abs() * half.z,
+        matrix.x.z.abs() * half.x + matrix.y.z.abs() * half.y + matrix.z.z.abs() * half.z,
+    );
+
+    (new_center - new_half, new_center + new_half)
+}
+
+// Whether frame `frame` of `anim`, placed by `transform`, may be in `frustum`.
+// Frames without bounds are taken as visible.
+pub fn frame_visible(anim: &Md5Anim, frame: usize, transform: &Matrix4<f32>, frustum: &Frustum) -> bool {
+    match frame_bounds(anim, frame) {
+        Some((min, max)) => {
+            let (min, max) = transform_aabb(transform, min, max);
+            frustum.intersects_aabb(min, max)
+        },
+        None => true
+    }
+}
+
+// Whether `anim` at `time`, placed by `transform`, may be in `frustum`, with the
+// bounds interpolated between frames
+pub fn anim_visible(anim: &Md5Anim, time: f32, looping: bool, transform: &Matrix4<f32>, frustum: &Frustum) -> bool {
+    match bounds_at(anim, time, looping) {
+        Some((min, max)) => {
+            let (min, max) = transform_aabb(transform, min, max);
+            frustum.intersects_aabb(min, max)
+        },
+        None => true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector3, Matrix4, Point3, Deg, InnerSpace, SquareMatrix, perspective};
+    use animation::skeleton::tests::anim;
+    use md5::md5anim::Bound;
+    use super::Frustum;
+
+    #[test]
+    fn intersects_aabb() {
+        let view = Matrix4::look_at(Point3::new(0.0, 0.0, 10.0), Point3::new(0.0, 0.0, 0.0), Vector3::unit_y());
+        let frustum = Frustum::from_matrix(&(perspective(Deg(60.0), 1.0, 0.1, 100.0) * view));
+        let unit = Vector3::new(0.5, 0.5, 0.5);
+
+        assert!(frustum.contains_point(Vector3::new(0.0, 0.0, 0.0)));
+        assert!(frustum.intersects_aabb(-unit, unit));
+        // straddling the left plane
+        assert!(frustum.intersects_aabb(Vector3::new(-7.0, 0.0, 0.0) - unit, Vector3::new(-5.0, 0.0, 0.0)));
+        assert!(!frustum.intersects_aabb(Vector3::new(-20.0, 0.0, 0.0) - unit, Vector3::new(-20.0, 0.0, 0.0) + unit));
+        // behind the camera and past the far plane
+        assert!(!frustum.intersects_aabb(Vector3::new(0.0, 0.0, 11.0) - unit, Vector3::new(0.0, 0.0, 11.0) + unit));
+        assert!(!frustum.intersects_aabb(Vector3::new(0.0, 0.0, -200.0) - unit, Vector3::new(0.0, 0.0, -200.0) + unit));
+    }
+
+    #[test]
+    fn frame_visible() {
+        let mut anim = anim();
+        let view = Matrix4::look_at(Point3::new(0.0, 0.0, 10.0), Point3::new(0.0, 0.0, 0.0), Vector3::unit_y());
+        let frustum = Frustum::from_matrix(&(perspective(Deg(60.0), 1.0, 0.1, 100.0) * view));
+        let away = Matrix4::from_translation(Vector3::new(50.0, 0.0, 0.0));
+
+        // without bounds, always drawn
+        assert!(super::frame_visible(&anim, 0, &away, &frustum));
+
+        anim.bounds = vec![
+            Bound { bound_min: Vector3::new(-1.0, -1.0, -1.0), bound_max: Vector3::new(1.0, 1.0, 1.0) },
+            Bound { bound_min: Vector3::new(-60.0, -1.0, -1.0), bound_max: Vector3::new(-40.0, 1.0, 1.0) },
+        ];
+        assert!(super::frame_visible(&anim, 0, &Matrix4::identity(), &frustum));
+        assert!(!super::frame_visible(&anim, 0, &away, &frustum));
+        // the second frame reaches back to the origin once moved away
+        assert!(super::frame_visible(&anim, 1, &away, &frustum));
+        assert!(super::anim_visible(&anim, 1.0 / 24.0, false, &away, &frustum));
+    }
+
+    #[test]
+    fn transform_aabb() {
+        let m = Matrix4::from_translation(Vector3::new(1.0, 2.0, 3.0)) * Matrix4::from_angle_z(Deg(90.0));
+        let (min, max) = super::transform_aabb(&m, Vector3::new(0.0, 0.0, 0.0), Vector3::new(2.0, 1.0, 1.0));
+
+        assert!((min - Vector3::new(0.0, 2.0, 3.0)).magnitude() < 1e-5);
+        assert!((max - Vector3::new(1.0, 4.0, 4.0)).magnitude() < 1e-5);
+    }
+}
@@ -0,0 +1,299 @@
+use std::io;
+use std::io::{Read, Write};
+use std::fs::File;
+use std::path::Path;
+
+// Writes an RGBA8 buffer as a binary PPM (P6). Alpha is dropped.
+pub fn write_ppm<W: Write>(w: &mut W, width: usize, height: usize, rgba: &[u8]) -> io::Result<()> {
+    check_size(width, height, rgba)?;
+    write!(w, "P6\n{} {}\n255\n", width, height)?;
+
+    let mut rgb: Vec<u8> = Vec::with_capacity(width * height * 3);
+    for p in rgba.chunks(4) {
+        rgb.extend_from_slice(&p[0..3]);
+    }
+
+    w.write_all(&rgb)
+}
+
+// Writes an RGBA8 buffer as a PNG. The image data is stored without compression
+// so that no deflate implementation is needed. PNG images can't be empty.
+pub fn write_png<W: Write>(w: &mut W, width: usize, height: usize, rgba: &[u8]) -> io::Result<()> {
+    if width == 0 || height == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty PNG image"));
+    }
+    check_size(width, height, rgba)?;
+
+    w.write_all(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A])?;
+
+    let mut ihdr: Vec<u8> = Vec::new();
+    push_u32_be(&mut ihdr, width as u32);
+    push_u32_be(&mut ihdr, height as u32);
+    // 8 bits per channel, color type 6 (RGBA), default compression, filter and interlace
+    ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+    write_chunk(w, b"IHDR", &ihdr)?;
+
+    let mut raw: Vec<u8> = Vec::with_capacity(height * (width * 4 + 1));
+    for row in rgba.chunks(width * 4) {
+        // filter type 0 (none)
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    write_chunk(w, b"IDAT", &zlib_stored(&raw))?;
+
+    write_chunk(w, b"IEND", &[])
+}
+
+pub fn save_rgba(path: &Path, width: usize, height: usize, rgba: &[u8]) -> io::Result<()> {
+    let mut f = File::create(path)?;
+
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("ppm") => write_ppm(&mut f, width, height, rgba),
+        _ => write_png(&mut f, width, height, rgba)
+    }
+}
+
+// Decodes a TGA image to RGBA8, top row first. Handles uncompressed and RLE
+// true-color (24 or 32 bits) and grayscale (8 bits) images, the kinds written
+// for Doom 3 style assets.
+pub fn read_tga(data: &[u8]) -> io::Result<(usize, usize, Vec<u8>)> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+    if data.len() < 18 {
+        return Err(invalid("truncated TGA header"));
+    }
+
+    let id_length = data[0] as usize;
+    let color_map_type = data[1];
+    let image_type = data[2];
+    let color_map_length = data[5] as usize | (data[6] as usize) << 8;
+    let color_map_entry_size = data[7] as usize;
+    let width = data[12] as usize | (data[13] as usize) << 8;
+    let height = data[14] as usize | (data[15] as usize) << 8;
+    let pixel_size = data[16] as usize / 8;
+    let top_to_bottom = data[17] & 0x20 != 0;
+
+    let (rle, grayscale) = match image_type {
+        2 => (false, false),
+        3 => (false, true),
+        10 => (true, false),
+        11 => (true, true),
+        _ => return Err(invalid("unsupported TGA image type"))
+    };
+
+    let supported = if grayscale { pixel_size == 1 } else { pixel_size == 3 || pixel_size == 4 };
+    if !supported {
+        return Err(invalid("unsupported TGA pixel size"));
+    }
+
+    // an unused color map may still be present
+    let map_size = if color_map_type != 0 { color_map_length * ((color_map_entry_size + 7) / 8) } else { 0 };
+    let mut pos = 18 + id_length + map_size;
+
+    let count = width * height;
+    let mut pixels: Vec<u8> = Vec::with_capacity(count * pixel_size);
+
+    while pixels.len() < count * pixel_size {
+        if !rle {
+            let end = pos + count * pixel_size;
+            if end > data.len() {
+                return Err(invalid("truncated TGA data"));
+            }
+            pixels.extend_from_slice(&data[pos..end]);
+            break;
+        }
+
+        if pos >= data.len() {
+            return Err(invalid("truncated TGA data"));
+        }
+        let header = data[pos] as usize;
+        let run = (header & 0x7F) + 1;
+        pos += 1;
+
+        if header & 0x80 != 0 {
+            if pos + pixel_size > data.len() {
+                return Err(invalid("truncated TGA data"));
+            }
+            for _ in 0..run {
+                pixels.extend_from_slice(&data[pos..pos + pixel_size]);
+            }
+            pos += pixel_size;
+        } else {
+            if pos + run * pixel_size > data.len() {
+                return Err(invalid("truncated TGA data"));
+            }
+            pixels.extend_from_slice(&data[pos..pos + run * pixel_size]);
+            pos += run * pixel_size;
+        }
+    }
+    pixels.truncate(count * pixel_size);
+
+    let mut rgba: Vec<u8> = vec![0; count * 4];
+
+    for y in 0..height {
+        // rows are stored bottom to top unless the descriptor says otherwise
+        let src_row = if top_to_bottom { y } else { height - 1 - y };
+
+        for x in 0..width {
+            let src = &pixels[(src_row * width + x) * pixel_size..(src_row * width + x + 1) * pixel_size];
+            let dst = &mut rgba[(y * width + x) * 4..(y * width + x + 1) * 4];
+
+            if grayscale {
+                dst.copy_from_slice(&[src[0], src[0], src[0], 255]);
+            } else {
+                // stored as BGR(A)
+                let alpha = if pixel_size == 4 { src[3] } else { 255 };
+                dst.copy_from_slice(&[src[2], src[1], src[0], alpha]);
+            }
+        }
+    }
+
+    Ok((width, height, rgba))
+}
+
+pub fn load_tga(path: &Path) -> io::Result<(usize, usize, Vec<u8>)> {
+    let mut f = File::open(path)?;
+    let mut buff = vec![];
+
+    f.read_to_end(&mut buff)?;
+    read_tga(&buff)
+}
+
+fn check_size(width: usize, height: usize, rgba: &[u8]) -> io::Result<()> {
+    if rgba.len() != width * height * 4 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                  format!("{} bytes for a {}x{} RGBA image", rgba.len(), width, height)));
+    }
+    Ok(())
+}
+
+fn push_u32_be(v: &mut Vec<u8>, n: u32) {
+    v.push((n >> 24) as u8);
+    v.push((n >> 16) as u8);
+    v.push((n >> 8) as u8);
+    v.push(n as u8);
+}
+
+fn write_chunk<W: Write>(w: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    let mut chunk: Vec<u8> = Vec::with_capacity(data.len() + 12);
+    push_u32_be(&mut chunk, data.len() as u32);
+    chunk.extend_from_slice(kind);
+    chunk.extend_from_slice(data);
+
+    let crc = crc32(&chunk[4..]);
+    push_u32_be(&mut chunk, crc);
+
+    w.write_all(&chunk)
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // deflate, 32K window, no preset dictionary, fastest compression
+    let mut res: Vec<u8> = vec![0x78, 0x01];
+
+    if data.is_empty() {
+        res.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+
+    let blocks = data.chunks(0xFFFF).collect::<Vec<_>>();
+    for (i, block) in blocks.iter().enumerate() {
+        let last = if i + 1 == blocks.len() { 1 } else { 0 };
+        let len = block.len() as u16;
+
+        res.push(last);
+        res.push(len as u8);
+        res.push((len >> 8) as u8);
+        res.push(!len as u8);
+        res.push((!len >> 8) as u8);
+        res.extend_from_slice(block);
+    }
+
+    push_u32_be(&mut res, adler32(data));
+    res
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xFFFF_FFFF;
+
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { 0xEDB8_8320 ^ (crc >> 1) } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+
+    for &d in data {
+        a = (a + d as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    #[test]
+    fn crc32() {
+        assert_eq!(super::crc32(b"IEND"), 0xAE42_6082);
+    }
+
+    #[test]
+    fn adler32() {
+        assert_eq!(super::adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn write_ppm() {
+        let mut out: Vec<u8> = Vec::new();
+        super::write_ppm(&mut out, 2, 1, &[255, 0, 0, 255, 0, 255, 0, 255]).unwrap();
+
+        assert_eq!(out, b"P6\n2 1\n255\n\xFF\x00\x00\x00\xFF\x00".to_vec());
+    }
+
+    #[test]
+    fn read_tga() {
+        // 2x2 bottom-up BGR image
+        let mut data = vec![0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 2, 0, 24, 0];
+        data.extend_from_slice(&[0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255, 255]);
+
+        let (width, height, rgba) = super::read_tga(&data).unwrap();
+
+        assert_eq!((width, height), (2, 2));
+        assert_eq!(&rgba[0..8], &[0, 0, 255, 255, 255, 255, 255, 255]);
+        assert_eq!(&rgba[8..16], &[255, 0, 0, 255, 0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn read_tga_rle() {
+        // 3x1 grayscale, a run of two then one raw pixel
+        let mut data = vec![0, 0, 11, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0, 1, 0, 8, 0x20];
+        data.extend_from_slice(&[0x81, 10, 0x00, 20]);
+
+        let (_, _, rgba) = super::read_tga(&data).unwrap();
+
+        assert_eq!(rgba, vec![10, 10, 10, 255, 10, 10, 10, 255, 20, 20, 20, 255]);
+    }
+
+    #[test]
+    fn write_png() {
+        let mut out: Vec<u8> = Vec::new();
+        super::write_png(&mut out, 1, 1, &[1, 2, 3, 4]).unwrap();
+
+        assert_eq!(&out[0..8], &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]);
+        assert_eq!(&out[12..16], b"IHDR");
+        assert_eq!(&out[out.len() - 8..out.len() - 4], b"IEND");
+
+        // empty or short buffers
+        assert_eq!(super::write_png(&mut Vec::new(), 0, 1, &[]).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(super::write_png(&mut Vec::new(), 2, 1, &[1, 2, 3, 4]).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(super::write_ppm(&mut Vec::new(), 2, 1, &[1, 2, 3, 4]).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+}
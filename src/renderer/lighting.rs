@@ -0,0 +1,129 @@
+#![allow(dead_code)]
+use cgmath::{Vector3, Vector4, Matrix4, InnerSpace, ElementWise};
+
+// Lights the shaders can take at once
+pub const MAX_LIGHTS: usize = 8;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Light {
+    // light travelling along `direction`, in world space
+    Directional { direction: Vector3<f32>, color: Vector3<f32> },
+    // light fading out to nothing at `range` from `position`, in world space
+    Point { position: Vector3<f32>, color: Vector3<f32>, range: f32 },
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Lighting {
+    pub ambient: Vector3<f32>,
+    pub lights: Vec<Light>,
+    // Blinn-Phong exponent, the Doom 3 materials don't carry one
+    pub shininess: f32,
+}
+
+impl Lighting {
+    // A key light from above the front of Z up models facing -Y, as exported
+    // from Blender, and a dimmer fill from the side
+    pub fn new() -> Lighting {
+        Lighting {
+            ambient: Vector3::new(0.15, 0.15, 0.15),
+            lights: vec![
+                Light::Directional { direction: Vector3::new(0.5, 1.0, -0.8), color: Vector3::new(0.9, 0.9, 0.85) },
+                Light::Directional { direction: Vector3::new(-1.0, 0.5, 0.2), color: Vector3::new(0.25, 0.25, 0.3) },
+            ],
+            shininess: 32.0,
+        }
+    }
+}
+
+// Light as read by the shaders, in view space. `vector` is the direction towards
+// the light for directional lights (w = 0) and its position for point lights (w = 1).
+// `color.w` is the range of point lights.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ShaderLight {
+    pub vector: Vector4<f32>,
+    pub color: Vector4<f32>,
+}
+
+// The first `MAX_LIGHTS` lights of `lighting` moved to view space
+pub fn view_space_lights(lighting: &Lighting, view: &Matrix4<f32>) -> Vec<ShaderLight> {
+    lighting.lights.iter().take(MAX_LIGHTS).map(|light| {
+        match *light {
+            Light::Directional { direction, color } => ShaderLight {
+                vector: (view * (-direction).normalize().extend(0.0)).normalize(),
+                color: color.extend(0.0),
+            },
+            Light::Point { position, color, range } => ShaderLight {
+                vector: view * position.extend(1.0),
+                color: color.extend(range),
+            },
+        }
+    }).collect()
+}
+
+// Same computation as `blinn_phong` in the `fs` shader. `position` and `normal`
+// are in view space, the eye being at the origin. `shadow` scales the light of
+// the given index by the given visibility.
+pub fn blinn_phong(albedo: Vector3<f32>, specular: Vector3<f32>, position: Vector3<f32>, normal: Vector3<f32>,
+                   lights: &[ShaderLight], ambient: Vector3<f32>, shininess: f32, shadow: Option<(usize, f32)>) -> Vector3<f32> {
+    let n = normal.normalize();
+    let v = (-position).normalize();
+    let mut res = albedo.mul_element_wise(ambient);
+
+    for (i, light) in lights.iter().enumerate() {
+        let (l, mut attenuation) = if light.vector.w == 0.0 {
+            (light.vector.truncate(), 1.0)
+        } else {
+            let to_light = light.vector.truncate() - position;
+            let falloff = (1.0 - to_light.magnitude() / light.color.w).max(0.0).min(1.0);
+            (to_light.normalize(), falloff * falloff)
+        };
+
+        if let Some((index, visibility)) = shadow {
+            if index == i {
+                attenuation *= visibility;
+            }
+        }
+
+        let diffuse = n.dot(l);
+        if diffuse <= 0.0 {
+            continue;
+        }
+
+        let h = (l + v).normalize();
+        let highlight = n.dot(h).max(0.0).powf(shininess);
+        let radiance = light.color.truncate() * attenuation;
+
+        res += (albedo * diffuse + specular * highlight).mul_element_wise(radiance);
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector3, Matrix4, SquareMatrix};
+
+    #[test]
+    fn blinn_phong() {
+        let mut lighting = super::Lighting::new();
+        lighting.ambient = Vector3::new(0.0, 0.0, 0.0);
+        lighting.lights = vec![
+            super::Light::Directional { direction: Vector3::new(0.0, 0.0, -1.0), color: Vector3::new(1.0, 1.0, 1.0) },
+            super::Light::Point { position: Vector3::new(0.0, 0.0, 10.0), color: Vector3::new(1.0, 0.0, 0.0), range: 5.0 },
+        ];
+        let lights = super::view_space_lights(&lighting, &Matrix4::identity());
+
+        // facing the directional light and the eye, out of reach of the point light
+        let c = super::blinn_phong(Vector3::new(0.5, 0.5, 0.5), Vector3::new(0.5, 0.5, 0.5),
+                                   Vector3::new(0.0, 0.0, -1.0), Vector3::new(0.0, 0.0, 1.0),
+                                   &lights, lighting.ambient, 16.0, None);
+
+        assert_eq!(c, Vector3::new(1.0, 1.0, 1.0));
+
+        let shadowed = super::blinn_phong(Vector3::new(0.5, 0.5, 0.5), Vector3::new(0.5, 0.5, 0.5),
+                                          Vector3::new(0.0, 0.0, -1.0), Vector3::new(0.0, 0.0, 1.0),
+                                          &lights, lighting.ambient, 16.0, Some((0, 0.5)));
+
+        assert_eq!(shadowed, Vector3::new(0.5, 0.5, 0.5));
+    }
+}
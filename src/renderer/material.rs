@@ -0,0 +1,114 @@
+#![allow(dead_code)]
+use cgmath::{Vector2, Vector4};
+use std::path::Path;
+
+use md5::md5mesh::Md5Mesh;
+use renderer::image::load_tga;
+
+// RGBA8 image, top row first
+#[derive(Clone, PartialEq, Debug)]
+pub struct Texture {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+impl Texture {
+    // 1x1 texture of a single color
+    pub fn solid(rgba: [u8; 4]) -> Texture {
+        Texture { width: 1, height: 1, data: rgba.to_vec() }
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Vector4<f32> {
+        let x = ((x % self.width as i64) + self.width as i64) % self.width as i64;
+        let y = ((y % self.height as i64) + self.height as i64) % self.height as i64;
+        let i = (y as usize * self.width + x as usize) * 4;
+
+        Vector4::new(self.data[i] as f32, self.data[i + 1] as f32, self.data[i + 2] as f32, self.data[i + 3] as f32) / 255.0
+    }
+
+    // Bilinear filtering with repeat addressing, like the sampler of the vulkano renderer
+    pub fn sample(&self, tex_coords: Vector2<f32>) -> Vector4<f32> {
+        let x = tex_coords.x * self.width as f32 - 0.5;
+        let y = tex_coords.y * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = self.texel(x0, y0) * (1.0 - fx) + self.texel(x0 + 1, y0) * fx;
+        let bottom = self.texel(x0, y0 + 1) * (1.0 - fx) + self.texel(x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+// Textures of a mesh, following the Doom 3 naming: `<shader>.tga` for the diffuse
+// color, `<shader>_s.tga` for the specular color and `<shader>_local.tga` for the
+// tangent space normal map
+#[derive(Clone, PartialEq, Debug)]
+pub struct Material {
+    pub diffuse: Texture,
+    pub specular: Texture,
+    pub normal: Texture,
+}
+
+impl Material {
+    // White, without highlights and with unperturbed normals
+    pub fn new() -> Material {
+        Material::solid([255, 255, 255, 255])
+    }
+
+    // Same as `new` with another diffuse color
+    pub fn solid(rgba: [u8; 4]) -> Material {
+        Material {
+            diffuse: Texture::solid(rgba),
+            specular: Texture::solid([0, 0, 0, 255]),
+            normal: Texture::solid([128, 128, 255, 255]),
+        }
+    }
+}
+
+fn load_texture(dir: &Path, name: &str, fallback: Texture) -> Texture {
+    let path = dir.join(format!("{}.tga", name));
+
+    match load_tga(&path) {
+        Ok((width, height, data)) => Texture { width: width, height: height, data: data },
+        Err(e) => {
+            println!("{}: {}, using a default texture", path.display(), e);
+            fallback
+        }
+    }
+}
+
+// Material of the mesh using `shader`, its textures being searched in `dir`.
+// Missing textures are replaced by those of `Material::new`.
+pub fn load_material(dir: &Path, shader: &str) -> Material {
+    // shaders may be given as paths in the game tree, only the name is kept
+    let name = Path::new(shader).file_name().and_then(|n| n.to_str()).unwrap_or(shader);
+    let default = Material::new();
+
+    Material {
+        diffuse: load_texture(dir, name, default.diffuse),
+        specular: load_texture(dir, &format!("{}_s", name), default.specular),
+        normal: load_texture(dir, &format!("{}_local", name), default.normal),
+    }
+}
+
+// One material per mesh of `model`, in order
+pub fn load_materials(dir: &Path, model: &Md5Mesh) -> Vec<Material> {
+    model.meshes.iter().map(|m| load_material(dir, &m.shader)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector2, Vector4};
+
+    #[test]
+    fn sample() {
+        let texture = super::Texture { width: 2, height: 1, data: vec![0, 0, 0, 255, 255, 255, 255, 255] };
+
+        assert_eq!(texture.sample(Vector2::new(0.25, 0.5)), Vector4::new(0.0, 0.0, 0.0, 1.0));
+        assert_eq!(texture.sample(Vector2::new(0.5, 0.5)), Vector4::new(0.5, 0.5, 0.5, 1.0));
+        // wraps around
+        assert_eq!(texture.sample(Vector2::new(1.0, 0.5)), Vector4::new(0.5, 0.5, 0.5, 1.0));
+    }
+}
@@ -1 +1,15 @@
+pub mod backend;
 pub mod render;
+pub mod camera;
+pub mod debug;
+pub mod vulkan;
+pub mod software;
+pub mod image;
+pub mod lighting;
+pub mod material;
+pub mod shadow;
+pub mod config;
+pub mod error;
+pub mod capture;
+pub mod frustum;
+pub mod scene
\ No newline at end of file
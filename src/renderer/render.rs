@@ -1,295 +1,154 @@
-use vulkano_win::VkSurfaceBuild;
-use vulkano::sync::GpuFuture;
-
-use std::sync::Arc;
-use vulkano_win;
-use vulkano;
+use std::time::Instant;
 use winit;
-use cgmath;
-use std;
-
-use vertex_computation::convert::{Vertex, Normal};
-
-
-pub fn render_model(v_vertices: &[Vertex], v_normal: &[Normal], v_index: &[u16]) {
-    // The start of this example is exactly the same as `triangle`. You should read the
-    // `triangle` example if you haven't done so yet.
-
-    let extensions = vulkano_win::required_extensions();
-    let instance = vulkano::instance::Instance::new(None, &extensions, None).expect("failed to create instance");
-
-    let physical = vulkano::instance::PhysicalDevice::enumerate(&instance)
-                            .next().expect("no device available");
-    println!("Using device: {} (type: {:?})", physical.name(), physical.ty());
-
-    let mut events_loop = winit::EventsLoop::new();
-    let surface = winit::WindowBuilder::new().build_vk_surface(&events_loop, instance.clone()).unwrap();
-
-    let mut dimensions;
-
-    let queue = physical.queue_families().find(|&q| q.supports_graphics() &&
-                                                   surface.is_supported(q).unwrap_or(false))
-                                                .expect("couldn't find a graphical queue family");
-
-    let device_ext = vulkano::device::DeviceExtensions {
-        khr_swapchain: true,
-        .. vulkano::device::DeviceExtensions::none()
-    };
-
-    let (device, mut queues) = vulkano::device::Device::new(physical, physical.supported_features(),
-                                                            &device_ext, [(queue, 0.5)].iter().cloned())
-                               .expect("failed to create device");
-    let queue = queues.next().unwrap();
-
-    let (mut swapchain, mut images) = {
-        let caps = surface.capabilities(physical).expect("failed to get surface capabilities");
-
-        dimensions = caps.current_extent.unwrap_or([1024, 768]);
-
-        let usage = caps.supported_usage_flags;
-        let format = caps.supported_formats[0].0;
-        let alpha = caps.supported_composite_alpha.iter().next().unwrap();
-
-        vulkano::swapchain::Swapchain::new(device.clone(), surface.clone(), caps.min_image_count, format, dimensions, 1,
-                                           usage, &queue, vulkano::swapchain::SurfaceTransform::Identity,
-                                           alpha,
-                                           vulkano::swapchain::PresentMode::Fifo, true, None).expect("failed to create swapchain")
-    };
-
-
-    let mut depth_buffer = vulkano::image::attachment::AttachmentImage::transient(device.clone(), dimensions, vulkano::format::D16Unorm).unwrap();
-
-    let vertex_buffer = vulkano::buffer::cpu_access::CpuAccessibleBuffer
-                                ::from_iter(device.clone(), vulkano::buffer::BufferUsage::all(), v_vertices.iter().cloned())
-                                .expect("failed to create buffer");
-
-    let normals_buffer = vulkano::buffer::cpu_access::CpuAccessibleBuffer
-                                ::from_iter(device.clone(), vulkano::buffer::BufferUsage::all(), v_normal.iter().cloned())
-                                .expect("failed to create buffer");
-
-    let index_buffer = vulkano::buffer::cpu_access::CpuAccessibleBuffer
-                                ::from_iter(device.clone(), vulkano::buffer::BufferUsage::all(), v_index.iter().cloned())
-                                .expect("failed to create buffer");
-
-    // note: this teapot was meant for OpenGL where the origin is at the lower left
-    //       instead the origin is at the upper left in vulkan, so we reverse the Y axis
-    let mut proj = cgmath::perspective(cgmath::Rad(std::f32::consts::FRAC_PI_2), { dimensions[0] as f32 / dimensions[1] as f32 }, 0.01, 100.0);
-    let view = cgmath::Matrix4::look_at(cgmath::Point3::new(0.3, 0.3, 1.0), cgmath::Point3::new(0.0, 0.0, 0.0), cgmath::Vector3::new(0.0, -1.0, 0.0));
-    let scale = cgmath::Matrix4::from_scale(0.1);
-
-    let uniform_buffer = vulkano::buffer::cpu_pool::CpuBufferPool::<vs::ty::Data>
-                               ::new(device.clone(), vulkano::buffer::BufferUsage::all());
-
-    let vs = vs::Shader::load(device.clone()).expect("failed to create shader module");
-    let fs = fs::Shader::load(device.clone()).expect("failed to create shader module");
-
-    let renderpass = Arc::new(
-        single_pass_renderpass!(device.clone(),
-            attachments: {
-                color: {
-                    load: Clear,
-                    store: Store,
-                    format: swapchain.format(),
-                    samples: 1,
-                },
-                depth: {
-                    load: Clear,
-                    store: DontCare,
-                    format: vulkano::format::Format::D16Unorm,
-                    samples: 1,
-                }
-            },
-            pass: {
-                color: [color],
-                depth_stencil: {depth}
-            }
-        ).unwrap()
-    );
+use cgmath::{Vector3, Matrix4, SquareMatrix};
+
+use animation::player::AnimationPlayer;
+
+use md5::md5mesh::Md5Mesh;
+use renderer::backend::Renderer;
+use renderer::camera::OrbitCamera;
+use renderer::capture::{save_screenshot, screenshot_path};
+use renderer::debug::DebugOverlay;
+use renderer::error::RendererError;
+use renderer::frustum::{Frustum, anim_visible};
+use renderer::scene::Scene;
+use renderer::shadow::GroundPlane;
+use vertex_computation::compute::{prepare_skinned_mesh, compute_bounds};
+
+// Frames `camera` on the bind pose of `model`, returning the bounds of the pose
+pub fn frame_model(camera: &mut OrbitCamera, model: &Md5Mesh) -> (Vector3<f32>, Vector3<f32>) {
+    let (vertices, _, _) = prepare_skinned_mesh(model, &model.joints);
+    let (min, max) = compute_bounds(&vertices);
+
+    camera.frame_bounds(min, max);
+    (min, max)
+}
 
-    let pipeline = Arc::new(vulkano::pipeline::GraphicsPipeline::start()
-        .vertex_input(vulkano::pipeline::vertex::TwoBuffersDefinition::new())
-        .vertex_shader(vs.main_entry_point(), ())
-        .triangle_list()
-        .viewports_dynamic_scissors_irrelevant(1)
-        .fragment_shader(fs.main_entry_point(), ())
-        .depth_stencil_simple_depth()
-        .render_pass(vulkano::framebuffer::Subpass::from(renderpass.clone(), 0).unwrap())
-        .build(device.clone())
-                            .unwrap());
-    let mut framebuffers: Option<Vec<Arc<vulkano::framebuffer::Framebuffer<_,_>>>> = None;
+// Uploads `model`, frames `camera` on it and sets the ground plane under it
+pub fn prepare_scene<R: Renderer>(renderer: &mut R, model: &Md5Mesh, camera: &mut OrbitCamera) -> Result<(), RendererError> {
+    renderer.upload_mesh(model)?;
+    let (min, max) = frame_model(camera, model);
+    renderer.set_ground(Some(GroundPlane::below(min, max, camera.up)))
+}
 
-    let mut recreate_swapchain = false;
+// Application loop, independent of the backend doing the drawing. Returns when
+// the window is closed, or on the first error of the renderer.
+pub fn render_model<R: Renderer>(renderer: &mut R, events_loop: &mut winit::EventsLoop, model: &Md5Mesh, camera: &mut OrbitCamera, player: &mut AnimationPlayer, overlay: &mut DebugOverlay) -> Result<(), RendererError> {
+    prepare_scene(renderer, model, camera)?;
 
-    let mut previous_frame = Box::new(vulkano::sync::now(device.clone())) as Box<GpuFuture>;
-    let rotation_start = std::time::Instant::now();
+    let mut last_frame = Instant::now();
+    let mut title = String::new();
 
     loop {
-        previous_frame.cleanup_finished();
-
-        if recreate_swapchain {
-
-        dimensions = surface.capabilities(physical)
-            .expect("failed to get surface capabilities")
-            .current_extent.unwrap_or([1024, 768]);
-            
-            let (new_swapchain, new_images) = match swapchain.recreate_with_dimension(dimensions) {
-                Ok(r) => r,
-                Err(vulkano::swapchain::SwapchainCreationError::UnsupportedDimensions) => {
-                    continue;
-                },
-                Err(err) => panic!("{:?}", err)
-            };
-
-            std::mem::replace(&mut swapchain, new_swapchain);
-            std::mem::replace(&mut images, new_images);
-
-            let new_depth_buffer = vulkano::image::attachment::AttachmentImage::transient(device.clone(), dimensions, vulkano::format::D16Unorm).unwrap();
-            std::mem::replace(&mut depth_buffer, new_depth_buffer);
-
-            framebuffers = None;
+        let elapsed = last_frame.elapsed();
+        last_frame = Instant::now();
+        player.update(elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 / 1_000_000_000.0);
+
+        let skeleton = match player.skeleton() {
+            Some(skeleton) => {
+                renderer.update_pose(&skeleton)?;
+                skeleton
+            },
+            None => model.joints.clone()
+        };
 
-            proj = cgmath::perspective(cgmath::Rad(std::f32::consts::FRAC_PI_2), { dimensions[0] as f32 / dimensions[1] as f32 }, 0.01, 100.0);
+        let view = camera.view();
+        let right = Vector3::new(view.x.x, view.y.x, view.z.x);
+        let up = Vector3::new(view.x.y, view.y.y, view.z.y);
+        renderer.set_debug_lines(&overlay.lines(model, &skeleton, right, up, camera.distance * 0.02))?;
+        renderer.set_render_mode(overlay.render_mode(model.joints.len()));
 
-            recreate_swapchain = false;
+        let mut status = format!("Amalia - {}", player.status());
+        let view_status = overlay.status(&model.joints);
+        if !view_status.is_empty() {
+            status = format!("{} - {}", status, view_status);
         }
-
-        if framebuffers.is_none() {
-            let new_framebuffers = Some(images.iter().map(|image| {
-                Arc::new(vulkano::framebuffer::Framebuffer::start(renderpass.clone())
-                         .add(image.clone()).unwrap()
-                         .add(depth_buffer.clone()).unwrap()
-                         .build().unwrap())
-            }).collect::<Vec<_>>());
-            std::mem::replace(&mut framebuffers, new_framebuffers);
+        if status != title {
+            renderer.set_title(&status);
+            title = status;
         }
 
-        let uniform_buffer_subbuffer = {
-            let elapsed = rotation_start.elapsed();
-            let rotation = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1_000_000_000.0;
-            let rotation = cgmath::Matrix3::from_angle_y(cgmath::Rad(rotation as f32));
-
-            let uniform_data = vs::ty::Data {
-                world : cgmath::Matrix4::from(rotation).into(),
-                view : (view * scale).into(),
-                proj : proj.into(),
-            };
-
-            uniform_buffer.next(uniform_data).unwrap()
+        // the model is skipped while the bounds of the clip are out of view
+        let proj = camera.projection(renderer.dimensions());
+        let in_view = match player.clip() {
+            Some(anim) => anim_visible(anim, player.time, player.looping, &Matrix4::identity(), &Frustum::from_matrix(&(proj * view))),
+            None => true
         };
+        renderer.set_instances(if in_view { None } else { Some(&[]) })?;
 
-        let set = Arc::new(vulkano::descriptor::descriptor_set::PersistentDescriptorSet::start(pipeline.clone(), 0)
-            .add_buffer(uniform_buffer_subbuffer).unwrap()
-            .build().unwrap()
-        );
-
-        let (image_num, acquire_future) = match vulkano::swapchain::acquire_next_image(swapchain.clone(),
-                                                                                       None) {
-            Ok(r) => r,
-            Err(vulkano::swapchain::AcquireError::OutOfDate) => {
-                recreate_swapchain = true;
-                continue;
-            },
-            Err(err) => panic!("{:?}", err)
-        };
-
-        let command_buffer = vulkano::command_buffer::AutoCommandBufferBuilder::primary_one_time_submit(device.clone(), queue.family()).unwrap()
-            .begin_render_pass(
-                framebuffers.as_ref().unwrap()[image_num].clone(), false,
-                vec![
-                    [0.0, 0.0, 1.0, 1.0].into(),
-                    1f32.into()
-                ]).unwrap()
-            .draw_indexed(
-                pipeline.clone(),
-                vulkano::command_buffer::DynamicState {
-                      line_width: None,
-                      viewports: Some(vec![vulkano::pipeline::viewport::Viewport {
-                          origin: [0.0, 0.0],
-                          dimensions: [dimensions[0] as f32, dimensions[1] as f32],
-                          depth_range: 0.0 .. 1.0,
-                      }]),
-                      scissors: None,
-                },
-                (vertex_buffer.clone(), normals_buffer.clone()), 
-                index_buffer.clone(), set.clone(), ()).unwrap()
-            .end_render_pass().unwrap()
-            .build().unwrap();
-        
-        let future = previous_frame.join(acquire_future)
-            .then_execute(queue.clone(), command_buffer).unwrap()
-            .then_swapchain_present(queue.clone(), swapchain.clone(), image_num)
-            .then_signal_fence_and_flush();
-
-        match future {
-            Ok(future) => {
-                previous_frame = Box::new(future) as Box<_>;
-            }
-            Err(vulkano::sync::FlushError::OutOfDate) => {
-                recreate_swapchain = true;
-                previous_frame = Box::new(vulkano::sync::now(device.clone())) as Box<_>;
-            }
-            Err(e) => {
-                println!("{:?}", e);
-                previous_frame = Box::new(vulkano::sync::now(device.clone())) as Box<_>;
-            }
-        }
+        renderer.set_camera(view, proj);
+        renderer.draw_frame()?;
 
-        let mut done = false;
-        events_loop.poll_events(|ev| {
-            match ev {
-                winit::Event::WindowEvent { event: winit::WindowEvent::Closed, .. } => done = true,
-                _ => ()
+        let closed = poll_events(renderer, events_loop, |event| {
+            if !player.handle_event(event) && !overlay.handle_event(event) {
+                camera.handle_event(event);
             }
-        });
-        if done { return; }
+        })?;
+        if closed { return Ok(()); }
     }
 }
 
-mod vs {
-    #[derive(VulkanoShader)]
-    #[ty = "vertex"]
-    #[src = "
-#version 450
+// Application loop drawing the instances of `scene` in view, with the model
+// transform left as is, the identity unless set
+pub fn render_scene<R: Renderer>(renderer: &mut R, events_loop: &mut winit::EventsLoop, model: &Md5Mesh, camera: &mut OrbitCamera, scene: &mut Scene) -> Result<(), RendererError> {
+    prepare_scene(renderer, model, camera)?;
+    if let Some((min, max)) = scene.bounds() {
+        camera.frame_bounds(min, max);
+        renderer.set_ground(Some(GroundPlane::below(min, max, camera.up)))?;
+    }
 
-layout(location = 0) in vec3 position;
-layout(location = 1) in vec3 normal;
+    let mut last_frame = Instant::now();
+    let mut title = String::new();
 
-layout(location = 0) out vec3 v_normal;
+    loop {
+        let elapsed = last_frame.elapsed();
+        last_frame = Instant::now();
+        scene.update(elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 / 1_000_000_000.0);
+
+        let view = camera.view();
+        let proj = camera.projection(renderer.dimensions());
+        let visible = scene.visible(&Frustum::from_matrix(&(proj * view)));
+        renderer.set_instances(Some(&scene.poses(&visible)))?;
+
+        let status = format!("Amalia - {} of {} instances in view", visible.len(), scene.instances.len());
+        if status != title {
+            renderer.set_title(&status);
+            title = status;
+        }
 
-layout(set = 0, binding = 0) uniform Data {
-    mat4 world;
-    mat4 view;
-    mat4 proj;
-} uniforms;
+        renderer.set_camera(view, proj);
+        renderer.draw_frame()?;
 
-void main() {
-    mat4 worldview = uniforms.view * uniforms.world;
-    v_normal = transpose(inverse(mat3(worldview))) * normal;
-    gl_Position = uniforms.proj * worldview * vec4(position, 1.0);
-}
-"]
-    struct Dummy;
+        let closed = poll_events(renderer, events_loop, |event| { camera.handle_event(event); })?;
+        if closed { return Ok(()); }
+    }
 }
 
-mod fs {
-    #[derive(VulkanoShader)]
-    #[ty = "fragment"]
-    #[src = "
-#version 450
-
-layout(location = 0) in vec3 v_normal;
-layout(location = 0) out vec4 f_color;
-
-const vec3 LIGHT = vec3(0.0, 0.0, 1.0);
-
-void main() {
-    float brightness = dot(normalize(v_normal), normalize(LIGHT));
-    vec3 dark_color = vec3(0.6, 0.0, 0.0);
-    vec3 regular_color = vec3(1.0, 0.0, 0.0);
-
-    f_color = vec4(mix(dark_color, regular_color, brightness), 1.0);
-}
-"]
-    struct Dummy;
+// Handles the window events common to the loops, closing, resizing and F12
+// saving the frame to the working directory, and passes the others to `handle`.
+// Returns whether the window was closed.
+fn poll_events<R: Renderer, F: FnMut(&winit::WindowEvent)>(renderer: &mut R, events_loop: &mut winit::EventsLoop, mut handle: F) -> Result<bool, RendererError> {
+    let mut closed = false;
+    let mut screenshot = false;
+
+    events_loop.poll_events(|ev| {
+        match ev {
+            winit::Event::WindowEvent { event: winit::WindowEvent::Closed, .. } => closed = true,
+            winit::Event::WindowEvent { event: winit::WindowEvent::Resized(w, h), .. } => renderer.resize([w, h]),
+            winit::Event::WindowEvent { event: winit::WindowEvent::KeyboardInput { input: winit::KeyboardInput { state: winit::ElementState::Pressed, virtual_keycode: Some(winit::VirtualKeyCode::F12), .. }, .. }, .. } => screenshot = true,
+            winit::Event::WindowEvent { event, .. } => handle(&event),
+            _ => ()
+        }
+    });
+
+    if screenshot {
+        let path = screenshot_path();
+        match save_screenshot(renderer, &path) {
+            Ok(()) => println!("saved {}", path.display()),
+            // a file that can't be written doesn't end the viewer
+            Err(ref e @ RendererError::Capture(_)) => eprintln!("{}", e),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(closed)
 }
-
@@ -0,0 +1,159 @@
+#![allow(dead_code)]
+use cgmath::{Vector3, Matrix4};
+
+use animation::bounds::{frame_bounds, bounds_at};
+use animation::skeleton::{sample_pose, to_object_space};
+use md5::md5anim::Md5Anim;
+use md5::md5mesh::Joint;
+use renderer::frustum::{Frustum, transform_aabb, anim_visible};
+
+// One character of a `Scene`
+#[derive(Clone, PartialEq, Debug)]
+pub struct Instance {
+    // model to scene space
+    pub transform: Matrix4<f32>,
+    // index in `Scene::clips`
+    pub clip: usize,
+    // added to the scene time, so that instances of a clip don't all move together
+    pub time_offset: f32,
+}
+
+// Placement and skeleton of an instance, as drawn by `Renderer::set_instances`
+#[derive(Clone, PartialEq, Debug)]
+pub struct InstancePose {
+    pub transform: Matrix4<f32>,
+    pub skeleton: Vec<Joint>,
+}
+
+// Many instances of one model, each playing one of `clips` in a loop
+pub struct Scene {
+    pub clips: Vec<Md5Anim>,
+    pub instances: Vec<Instance>,
+    pub time: f32,
+}
+
+impl Scene {
+    pub fn new(clips: Vec<Md5Anim>) -> Scene {
+        Scene {
+            clips: clips,
+            instances: Vec::new(),
+            time: 0.0,
+        }
+    }
+
+    pub fn add_instance(&mut self, transform: Matrix4<f32>, clip: usize, time_offset: f32) -> usize {
+        self.instances.push(Instance { transform: transform, clip: clip, time_offset: time_offset });
+        self.instances.len() - 1
+    }
+
+    // Adds `count` instances on a grid of the X/Y plane, `spacing` apart and centered
+    // on the origin, going through the clips and spreading their start times
+    pub fn add_grid(&mut self, count: usize, spacing: f32) {
+        if self.clips.is_empty() {
+            return;
+        }
+
+        let columns = (count as f32).sqrt().ceil().max(1.0) as usize;
+        let rows = (count + columns - 1) / columns;
+        let origin = Vector3::new(columns as f32 - 1.0, rows as f32 - 1.0, 0.0) * (spacing * 0.5);
+
+        for i in 0..count {
+            let position = Vector3::new((i % columns) as f32, (i / columns) as f32, 0.0) * spacing - origin;
+            let clip = i % self.clips.len();
+            let anim = &self.clips[clip];
+            let duration = anim.frames.len() as f32 / anim.frame_rate as f32;
+            // golden ratio steps, so that neighbours are far apart in the clip
+            let time_offset = (i as f32 * 0.618034) % 1.0 * duration;
+
+            self.add_instance(Matrix4::from_translation(position), clip, time_offset);
+        }
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.time += dt;
+    }
+
+    // Time of `instance` in its clip
+    pub fn instance_time(&self, instance: &Instance) -> f32 {
+        self.time + instance.time_offset
+    }
+
+    // Bounds of instance `index` in scene space at the scene time, interpolated
+    // from the bounds of its clip. None when the clip has no bounds.
+    pub fn instance_bounds(&self, index: usize) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        let instance = &self.instances[index];
+
+        self.clips.get(instance.clip)
+            .and_then(|anim| bounds_at(anim, self.instance_time(instance), true))
+            .map(|(min, max)| transform_aabb(&instance.transform, min, max))
+    }
+
+    // Indices of the instances in `frustum`, given in scene space. Instances
+    // without bounds are always kept.
+    pub fn visible(&self, frustum: &Frustum) -> Vec<usize> {
+        (0..self.instances.len()).filter(|&i| {
+            let instance = &self.instances[i];
+            match self.clips.get(instance.clip) {
+                Some(anim) => anim_visible(anim, self.instance_time(instance), true, &instance.transform, frustum),
+                None => true
+            }
+        }).collect()
+    }
+
+    // Poses of the instances of `indices` at the scene time
+    pub fn poses(&self, indices: &[usize]) -> Vec<InstancePose> {
+        indices.iter().filter_map(|&i| {
+            let instance = &self.instances[i];
+            match self.clips.get(instance.clip) {
+                Some(anim) if !anim.frames.is_empty() => {
+                    let local = sample_pose(anim, self.instance_time(instance), true);
+                    Some(InstancePose { transform: instance.transform, skeleton: to_object_space(&anim.hierarchies, &local) })
+                },
+                _ => None
+            }
+        }).collect()
+    }
+
+    // Bounds of the whole scene, from the first frame of the clip of each instance
+    pub fn bounds(&self) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        self.instances.iter().filter_map(|instance| {
+            self.clips.get(instance.clip).and_then(|anim| frame_bounds(anim, 0))
+                .map(|(min, max)| transform_aabb(&instance.transform, min, max))
+        }).fold(None, |acc, (min, max)| match acc {
+            Some((acc_min, acc_max)) => Some((
+                Vector3::new(min.x.min(acc_min.x), min.y.min(acc_min.y), min.z.min(acc_min.z)),
+                Vector3::new(max.x.max(acc_max.x), max.y.max(acc_max.y), max.z.max(acc_max.z)))),
+            None => Some((min, max))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector3, Matrix4, Point3, Deg, perspective};
+    use animation::skeleton::tests::anim;
+    use md5::md5anim::Bound;
+    use renderer::frustum::Frustum;
+    use super::Scene;
+
+    #[test]
+    fn visible() {
+        let mut clip = anim();
+        clip.bounds = vec![Bound { bound_min: Vector3::new(-1.0, -1.0, 0.0), bound_max: Vector3::new(1.0, 1.0, 2.0) }; 2];
+        let mut scene = Scene::new(vec![clip]);
+        scene.add_grid(4, 10.0);
+
+        assert_eq!(scene.instances.len(), 4);
+        assert_eq!(scene.instances[0].transform, Matrix4::from_translation(Vector3::new(-5.0, -5.0, 0.0)));
+        assert_eq!(scene.bounds(), Some((Vector3::new(-6.0, -6.0, 0.0), Vector3::new(6.0, 6.0, 2.0))));
+
+        // looking down at the instance at (5, 5) from close
+        let view = Matrix4::look_at(Point3::new(5.0, 5.0, 10.0), Point3::new(5.0, 5.0, 0.0), Vector3::unit_y());
+        let frustum = Frustum::from_matrix(&(perspective(Deg(30.0), 1.0, 0.1, 100.0) * view));
+        assert_eq!(scene.visible(&frustum), vec![3]);
+
+        let poses = scene.poses(&[3]);
+        assert_eq!(poses.len(), 1);
+        assert_eq!(poses[0].skeleton.len(), 2);
+    }
+}
@@ -0,0 +1,197 @@
+#![allow(dead_code)]
+use cgmath::{Vector2, Vector3, Vector4, Matrix4, Point3, InnerSpace, EuclideanSpace};
+
+use renderer::lighting::{Lighting, Light};
+use renderer::material::Material;
+
+const GROUND_COLOR: [u8; 4] = [140, 140, 140, 255];
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ShadowConfig {
+    pub enabled: bool,
+    // width and height of the shadow map
+    pub resolution: u32,
+    // depth offset, in shadow map depth units, avoiding self-shadowing acne
+    pub bias: f32,
+    // PCF samples (2 * radius + 1)^2 texels around the lookup
+    pub pcf_radius: u32,
+}
+
+impl ShadowConfig {
+    pub fn new() -> ShadowConfig {
+        ShadowConfig {
+            enabled: true,
+            resolution: 2048,
+            bias: 0.002,
+            pcf_radius: 1,
+        }
+    }
+}
+
+// Square under the model, in object space, receiving its shadow
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct GroundPlane {
+    pub center: Vector3<f32>,
+    pub normal: Vector3<f32>,
+    pub size: f32,
+}
+
+// Triangles of a mesh not skinned by any joint
+#[derive(Clone, PartialEq, Debug)]
+pub struct StaticMesh {
+    pub positions: Vec<Vector3<f32>>,
+    pub normals: Vec<Vector3<f32>>,
+    pub tangents: Vec<Vector4<f32>>,
+    pub tex_coords: Vec<Vector2<f32>>,
+    pub indices: Vec<u16>,
+}
+
+impl GroundPlane {
+    // Plane touching the bottom of the (min, max) box along `up`, twice as wide as the box
+    pub fn below(min: Vector3<f32>, max: Vector3<f32>, up: Vector3<f32>) -> GroundPlane {
+        let up = up.normalize();
+        let center = (min + max) * 0.5;
+        let half = (max - min) * 0.5;
+        // lowest extent of the box along `up`
+        let depth = half.x * up.x.abs() + half.y * up.y.abs() + half.z * up.z.abs();
+
+        GroundPlane {
+            center: center - up * depth,
+            normal: up,
+            size: 2.0 * (max - min).magnitude(),
+        }
+    }
+
+    // Two triangles facing `normal`
+    pub fn mesh(&self) -> StaticMesh {
+        let (u, v) = plane_axes(self.normal);
+        let h = self.size * 0.5;
+        let corners = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)];
+
+        StaticMesh {
+            positions: corners.iter().map(|&(a, b)| self.center + u * (a * h) + v * (b * h)).collect(),
+            normals: vec![self.normal; 4],
+            tangents: vec![u.extend(1.0); 4],
+            tex_coords: corners.iter().map(|&(a, b)| Vector2::new(a, b) * 0.5 + Vector2::new(0.5, 0.5)).collect(),
+            // same winding as the md5 triangles, see `prepare_normals`
+            indices: vec![0, 2, 1, 0, 3, 2],
+        }
+    }
+
+    pub fn material(&self) -> Material {
+        Material::solid(GROUND_COLOR)
+    }
+
+    // Sphere holding the shadow casters: the volume above the plane up to half its size
+    pub fn shadow_bounds(&self) -> (Vector3<f32>, f32) {
+        let h = self.size * 0.5;
+        (self.center + self.normal * (h * 0.5), h * 1.5)
+    }
+}
+
+// Two unit vectors completing `normal` to a right-handed basis
+fn plane_axes(normal: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let n = normal.normalize();
+    let helper = if n.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+    let u = (helper - n * n.dot(helper)).normalize();
+    (u, n.cross(u))
+}
+
+// The light casting shadows, the first directional one, with its index and direction
+pub fn shadow_caster(lighting: &Lighting) -> Option<(usize, Vector3<f32>)> {
+    lighting.lights.iter().enumerate().filter_map(|(i, light)| {
+        match *light {
+            Light::Directional { direction, .. } => Some((i, direction)),
+            _ => None
+        }
+    }).next()
+}
+
+// Orthographic projection along `direction` covering the sphere (center, radius),
+// with the same clip conventions as the camera: depth from 0 to 1 and y = -1 on the
+// first row of the map. Points beyond the sphere along the light up to its radius
+// again are kept, so that receivers behind the casters get a depth.
+pub fn light_matrix(direction: Vector3<f32>, center: Vector3<f32>, radius: f32) -> Matrix4<f32> {
+    let d = direction.normalize();
+    let up = if d.z.abs() < 0.9 { Vector3::unit_z() } else { Vector3::unit_y() };
+    let eye = center - d * (2.0 * radius);
+    let view = Matrix4::look_at(Point3::from_vec(eye), Point3::from_vec(center), up);
+
+    let (near, far) = (radius, 4.0 * radius);
+    let proj = Matrix4::new(
+        1.0 / radius, 0.0, 0.0, 0.0,
+        0.0, 1.0 / radius, 0.0, 0.0,
+        0.0, 0.0, -1.0 / (far - near), 0.0,
+        0.0, 0.0, -near / (far - near), 1.0,
+    );
+
+    proj * view
+}
+
+// Fraction of the texels around `coords` (in light clip space) that are not closer
+// to the light, the same filter as `shadow_factor` in the `fs` shader. Points
+// outside of the map are lit.
+pub fn pcf(depth_map: &[f32], resolution: usize, coords: Vector3<f32>, bias: f32, radius: u32) -> f32 {
+    if coords.x < -1.0 || coords.x > 1.0 || coords.y < -1.0 || coords.y > 1.0 || coords.z > 1.0 {
+        return 1.0;
+    }
+
+    let size = resolution as i64;
+    let x = ((coords.x * 0.5 + 0.5) * resolution as f32).floor() as i64;
+    let y = ((coords.y * 0.5 + 0.5) * resolution as f32).floor() as i64;
+    let r = radius as i64;
+
+    let mut lit = 0.0;
+    for dy in -r..r + 1 {
+        for dx in -r..r + 1 {
+            let sx = (x + dx).max(0).min(size - 1) as usize;
+            let sy = (y + dy).max(0).min(size - 1) as usize;
+
+            if coords.z - bias <= depth_map[sy * resolution + sx] {
+                lit += 1.0;
+            }
+        }
+    }
+
+    lit / ((2 * r + 1) * (2 * r + 1)) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector3, InnerSpace};
+
+    #[test]
+    fn ground_plane() {
+        let ground = super::GroundPlane::below(Vector3::new(-1.0, -1.0, 0.0), Vector3::new(1.0, 1.0, 4.0), Vector3::unit_z());
+
+        assert_eq!(ground.center, Vector3::new(0.0, 0.0, 0.0));
+
+        let mesh = ground.mesh();
+        // first triangle, with the cross product of `prepare_normals`
+        let n = (mesh.positions[1] - mesh.positions[0]).cross(mesh.positions[2] - mesh.positions[0]);
+        assert!(n.normalize().dot(Vector3::unit_z()) > 0.99);
+    }
+
+    #[test]
+    fn light_matrix() {
+        let m = super::light_matrix(Vector3::new(0.0, 0.0, -1.0), Vector3::new(0.0, 0.0, 0.0), 2.0);
+
+        // the sphere takes the first two thirds of the depth range
+        let top = m * Vector3::new(0.0, 0.0, 2.0).extend(1.0);
+        let bottom = m * Vector3::new(0.0, 0.0, -2.0).extend(1.0);
+        assert!(top.z.abs() < 1e-5);
+        assert!((bottom.z - 2.0 / 3.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn pcf() {
+        // left half of a 4x4 map occluded at depth 0.5
+        let map = (0..16).map(|i| if i % 4 < 2 { 0.5 } else { 1.0 }).collect::<Vec<f32>>();
+
+        assert_eq!(super::pcf(&map, 4, Vector3::new(-0.75, 0.0, 0.8), 0.0, 0), 0.0);
+        assert_eq!(super::pcf(&map, 4, Vector3::new(0.75, 0.0, 0.8), 0.0, 0), 1.0);
+        // 3x3 across the edge: one lit column out of three
+        assert!((super::pcf(&map, 4, Vector3::new(-0.25, 0.0, 0.8), 0.0, 1) - 1.0 / 3.0).abs() < 1e-6);
+        assert_eq!(super::pcf(&map, 4, Vector3::new(2.0, 0.0, 0.8), 0.0, 1), 1.0);
+    }
+}
@@ -0,0 +1,798 @@
+use cgmath::{Vector2, Vector3, Vector4, Matrix3, Matrix4, InnerSpace, SquareMatrix, Matrix};
+use std::io;
+use std::path::Path;
+use std::ops::Range;
+use std::f32;
+
+use md5::md5mesh::{Md5Mesh, Joint};
+use renderer::backend::Renderer;
+use renderer::debug::{LinePoint, RenderMode, wireframe_lines};
+use renderer::error::RendererError;
+use renderer::image::save_rgba;
+use renderer::scene::InstancePose;
+use renderer::lighting::{Lighting, ShaderLight, view_space_lights, blinn_phong};
+use renderer::material::Material;
+use renderer::shadow::{ShadowConfig, GroundPlane, StaticMesh, shadow_caster, light_matrix, pcf};
+use vertex_computation::compute::{prepare_skinned_mesh, prepare_full_tex_coords, prepare_full_tangents, prepare_index_ranges};
+use vertex_computation::skinning::{Influences, prepare_full_influences, joint_weights};
+
+// CPU counterpart of the vulkano pipeline used by `render_model`: same uniforms,
+// same shading as the `fs` shader and the same clip and depth conventions, so
+// that images rendered here match the ones on screen.
+
+#[derive(Copy, Clone, Debug)]
+pub struct Uniforms {
+    pub world: Matrix4<f32>,
+    pub view: Matrix4<f32>,
+    pub proj: Matrix4<f32>,
+}
+
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub color: Vec<u8>,
+    pub depth: Vec<f32>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        Framebuffer {
+            width: width,
+            height: height,
+            color: vec![0; width * height * 4],
+            depth: vec![1.0; width * height],
+        }
+    }
+
+    pub fn clear(&mut self, color: [f32; 4]) {
+        let c = to_rgba8(color);
+
+        for p in self.color.chunks_mut(4) {
+            p.copy_from_slice(&c);
+        }
+        for d in &mut self.depth {
+            *d = 1.0;
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        let i = (y * self.width + x) * 4;
+        [self.color[i], self.color[i + 1], self.color[i + 2], self.color[i + 3]]
+    }
+
+    // Format is picked from the extension: `.ppm` or PNG otherwise
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        save_rgba(path, self.width, self.height, &self.color)
+    }
+}
+
+// Instance of the model skinned in its own pose
+struct SkinnedInstance {
+    transform: Matrix4<f32>,
+    vertices: Vec<Vector3<f32>>,
+    normals: Vec<Vector3<f32>>,
+    tangents: Vec<Vector4<f32>>,
+}
+
+// `Renderer` drawing into a `Framebuffer`, skinning on the CPU
+pub struct SoftwareRenderer {
+    pub framebuffer: Framebuffer,
+    uniforms: Uniforms,
+    model: Option<Md5Mesh>,
+    vertices: Vec<Vector3<f32>>,
+    normals: Vec<Vector3<f32>>,
+    tangents: Vec<Vector4<f32>>,
+    indices: Vec<u16>,
+    ranges: Vec<Range<usize>>,
+    tex_coords: Vec<Vector2<f32>>,
+    influences: Vec<Influences>,
+    instances: Option<Vec<SkinnedInstance>>,
+    materials: Vec<Material>,
+    lighting: Lighting,
+    shadows: ShadowConfig,
+    shadow_map: Vec<f32>,
+    ground: Option<(GroundPlane, StaticMesh, Material)>,
+    lines: Vec<LinePoint>,
+    mode: RenderMode,
+}
+
+impl SoftwareRenderer {
+    pub fn new(width: usize, height: usize) -> SoftwareRenderer {
+        SoftwareRenderer {
+            framebuffer: Framebuffer::new(width, height),
+            uniforms: Uniforms {
+                world: Matrix4::identity(),
+                view: Matrix4::identity(),
+                proj: Matrix4::identity(),
+            },
+            model: None,
+            vertices: Vec::new(),
+            normals: Vec::new(),
+            tangents: Vec::new(),
+            indices: Vec::new(),
+            ranges: Vec::new(),
+            tex_coords: Vec::new(),
+            influences: Vec::new(),
+            instances: None,
+            materials: Vec::new(),
+            lighting: Lighting::new(),
+            shadows: ShadowConfig::new(),
+            shadow_map: Vec::new(),
+            ground: None,
+            lines: Vec::new(),
+            mode: RenderMode::Shaded,
+        }
+    }
+}
+
+impl Renderer for SoftwareRenderer {
+    fn upload_mesh(&mut self, model: &Md5Mesh) -> Result<(), RendererError> {
+        self.model = Some(model.clone());
+        self.ranges = prepare_index_ranges(model);
+        self.tex_coords = prepare_full_tex_coords(model);
+        self.influences = prepare_full_influences(model);
+        self.update_pose(&model.joints)
+    }
+
+    fn upload_materials(&mut self, materials: &[Material]) -> Result<(), RendererError> {
+        self.materials = materials.to_vec();
+        Ok(())
+    }
+
+    fn update_pose(&mut self, skeleton: &[Joint]) -> Result<(), RendererError> {
+        if let Some(ref model) = self.model {
+            let (s, n, idx) = prepare_skinned_mesh(model, skeleton);
+            self.tangents = prepare_full_tangents(model, &s, &n);
+            self.vertices = s;
+            self.normals = n;
+            self.indices = idx;
+        }
+        Ok(())
+    }
+
+    fn set_instances(&mut self, instances: Option<&[InstancePose]>) -> Result<(), RendererError> {
+        self.instances = match (instances, &self.model) {
+            (Some(instances), &Some(ref model)) => Some(instances.iter().map(|instance| {
+                let (vertices, normals, _) = prepare_skinned_mesh(model, &instance.skeleton);
+                SkinnedInstance {
+                    transform: instance.transform,
+                    tangents: prepare_full_tangents(model, &vertices, &normals),
+                    vertices: vertices,
+                    normals: normals,
+                }
+            }).collect()),
+            (Some(_), &None) => Some(Vec::new()),
+            (None, _) => None
+        };
+        Ok(())
+    }
+
+    fn set_model_transform(&mut self, world: Matrix4<f32>) {
+        self.uniforms.world = world;
+    }
+
+    fn set_camera(&mut self, view: Matrix4<f32>, proj: Matrix4<f32>) {
+        self.uniforms.view = view;
+        self.uniforms.proj = proj;
+    }
+
+    fn set_lighting(&mut self, lighting: &Lighting) {
+        self.lighting = lighting.clone();
+    }
+
+    fn set_shadows(&mut self, config: &ShadowConfig) -> Result<(), RendererError> {
+        self.shadows = *config;
+        Ok(())
+    }
+
+    fn set_ground(&mut self, ground: Option<GroundPlane>) -> Result<(), RendererError> {
+        self.ground = ground.map(|g| (g, g.mesh(), g.material()));
+        Ok(())
+    }
+
+    fn set_debug_lines(&mut self, lines: &[LinePoint]) -> Result<(), RendererError> {
+        self.lines = lines.to_vec();
+        Ok(())
+    }
+
+    fn set_render_mode(&mut self, mode: RenderMode) {
+        self.mode = mode;
+    }
+
+    fn draw_frame(&mut self) -> Result<(), RendererError> {
+        self.framebuffer.clear([0.0, 0.0, 1.0, 1.0]);
+
+        // the single model, or every instance, with its transform
+        let draws: Vec<(Matrix4<f32>, &[Vector3<f32>], &[Vector3<f32>], &[Vector4<f32>])> = match self.instances {
+            Some(ref instances) => instances.iter()
+                .map(|i| (i.transform, &i.vertices[..], &i.normals[..], &i.tangents[..]))
+                .collect(),
+            None => vec![(Matrix4::identity(), &self.vertices[..], &self.normals[..], &self.tangents[..])]
+        };
+
+        if self.mode == RenderMode::Wireframe {
+            for &(transform, positions, _, _) in &draws {
+                let uniforms = Uniforms { world: self.uniforms.world * transform, .. self.uniforms };
+                draw_lines(&mut self.framebuffer, &wireframe_lines(positions, &self.indices, [1.0, 1.0, 1.0]), &uniforms);
+            }
+        } else {
+            let weights = match self.mode {
+                RenderMode::WeightHeatmap(joint) => joint_weights(&self.influences, joint),
+                _ => Vec::new()
+            };
+            let lights = view_space_lights(&self.lighting, &self.uniforms.view);
+            let default_material = Material::new();
+
+            // light matrix of the shadow caster, fitted on the space above the ground
+            let caster = match (self.shadows.enabled, &self.ground, shadow_caster(&self.lighting)) {
+                (true, &Some((ref ground, _, _)), Some((index, direction))) => {
+                    let (center, radius) = ground.shadow_bounds();
+                    Some((index, light_matrix(direction, (self.uniforms.world * center.extend(1.0)).truncate(), radius)))
+                },
+                _ => None
+            };
+
+            let resolution = self.shadows.resolution as usize;
+            if let Some((_, light)) = caster {
+                self.shadow_map.clear();
+                self.shadow_map.resize(resolution * resolution, 1.0);
+                for &(transform, positions, _, _) in &draws {
+                    draw_depth(&mut self.shadow_map, resolution, positions, &self.indices, &(light * self.uniforms.world * transform));
+                }
+            }
+
+            let view_inverse = self.uniforms.view.invert().unwrap_or(Matrix4::identity());
+            let (depth_map, config) = (&self.shadow_map, self.shadows);
+            let shadow = caster.map(|(index, light)| ShadowLookup {
+                light: index,
+                matrix: light * view_inverse,
+                depth_map: depth_map,
+                resolution: resolution,
+                bias: config.bias,
+                pcf_radius: config.pcf_radius,
+            });
+
+            for &(transform, positions, normals, tangents) in &draws {
+                let attributes = Attributes {
+                    positions: positions,
+                    normals: normals,
+                    tangents: tangents,
+                    tex_coords: &self.tex_coords,
+                    weights: &weights,
+                };
+                let uniforms = Uniforms { world: self.uniforms.world * transform, .. self.uniforms };
+
+                for (i, range) in self.ranges.iter().enumerate() {
+                    let shading = Shading {
+                        mode: self.mode,
+                        material: self.materials.get(i).unwrap_or(&default_material),
+                        lights: &lights,
+                        ambient: self.lighting.ambient,
+                        shininess: self.lighting.shininess,
+                        shadow: shadow,
+                    };
+                    draw_mesh(&mut self.framebuffer, &attributes, &self.indices[range.clone()], &uniforms, &shading);
+                }
+            }
+
+            if let (RenderMode::Shaded, &Some((_, ref ground, ref material))) = (self.mode, &self.ground) {
+                let ground_attributes = Attributes {
+                    positions: &ground.positions,
+                    normals: &ground.normals,
+                    tangents: &ground.tangents,
+                    tex_coords: &ground.tex_coords,
+                    weights: &[],
+                };
+                let shading = Shading {
+                    mode: self.mode,
+                    material: material,
+                    lights: &lights,
+                    ambient: self.lighting.ambient,
+                    shininess: self.lighting.shininess,
+                    shadow: shadow,
+                };
+                draw_mesh(&mut self.framebuffer, &ground_attributes, &ground.indices, &self.uniforms, &shading);
+            }
+        }
+
+        draw_lines(&mut self.framebuffer, &self.lines, &self.uniforms);
+        Ok(())
+    }
+
+    fn read_pixels(&mut self) -> Result<Vec<u8>, RendererError> {
+        Ok(self.framebuffer.color.clone())
+    }
+
+    fn resize(&mut self, dimensions: [u32; 2]) {
+        self.framebuffer = Framebuffer::new(dimensions[0] as usize, dimensions[1] as usize);
+    }
+
+    fn dimensions(&self) -> [u32; 2] {
+        [self.framebuffer.width as u32, self.framebuffer.height as u32]
+    }
+
+    fn set_title(&mut self, _title: &str) {
+    }
+}
+
+// Per-vertex inputs of `draw_mesh`. Attributes missing from the slices are taken as zero.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Attributes<'a> {
+    pub positions: &'a [Vector3<f32>],
+    pub normals: &'a [Vector3<f32>],
+    pub tangents: &'a [Vector4<f32>],
+    pub tex_coords: &'a [Vector2<f32>],
+    pub weights: &'a [f32],
+}
+
+// What the `fs` shader reads besides the interpolated vertex attributes
+#[derive(Copy, Clone, Debug)]
+pub struct Shading<'a> {
+    pub mode: RenderMode,
+    pub material: &'a Material,
+    pub lights: &'a [ShaderLight],
+    pub ambient: Vector3<f32>,
+    pub shininess: f32,
+    pub shadow: Option<ShadowLookup<'a>>,
+}
+
+// Shadow map of the light of index `light`, `matrix` taking view space
+// positions to its clip space
+#[derive(Copy, Clone, Debug)]
+pub struct ShadowLookup<'a> {
+    pub light: usize,
+    pub matrix: Matrix4<f32>,
+    pub depth_map: &'a [f32],
+    pub resolution: usize,
+    pub bias: f32,
+    pub pcf_radius: u32,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct ClipVertex {
+    position: Vector4<f32>,
+    view_position: Vector3<f32>,
+    normal: Vector3<f32>,
+    tangent: Vector4<f32>,
+    tex_coords: Vector2<f32>,
+    weight: f32,
+}
+
+pub fn draw_mesh(fb: &mut Framebuffer, attributes: &Attributes, v_index: &[u16], uniforms: &Uniforms, shading: &Shading) {
+    let worldview = uniforms.view * uniforms.world;
+    let normal_matrix = mat3(&worldview).invert().unwrap_or(Matrix3::identity()).transpose();
+    let mvp = uniforms.proj * worldview;
+
+    let transformed = attributes.positions.iter().enumerate().map(|(i, p)| {
+        let tangent = attributes.tangents.get(i).cloned().unwrap_or(Vector4::new(0.0, 0.0, 0.0, 0.0));
+
+        ClipVertex {
+            position: mvp * p.extend(1.0),
+            view_position: (worldview * p.extend(1.0)).truncate(),
+            normal: normal_matrix * attributes.normals.get(i).cloned().unwrap_or(Vector3::new(0.0, 0.0, 0.0)),
+            tangent: (mat3(&worldview) * tangent.truncate()).extend(tangent.w),
+            tex_coords: attributes.tex_coords.get(i).cloned().unwrap_or(Vector2::new(0.0, 0.0)),
+            weight: attributes.weights.get(i).cloned().unwrap_or(0.0),
+        }
+    }).collect::<Vec<_>>();
+
+    for t in v_index.chunks(3) {
+        if t.len() < 3 {
+            break;
+        }
+
+        let polygon = clip_polygon(vec![transformed[t[0] as usize], transformed[t[1] as usize], transformed[t[2] as usize]]);
+
+        // fan triangulation of the clipped polygon
+        for i in 1..polygon.len().saturating_sub(1) {
+            rasterize_triangle(fb, &polygon[0], &polygon[i], &polygon[i + 1], shading);
+        }
+    }
+}
+
+// Depth only rendering into a square `resolution` map, for shadow mapping
+pub fn draw_depth(depth: &mut [f32], resolution: usize, v_vertices: &[Vector3<f32>], v_index: &[u16], mvp: &Matrix4<f32>) {
+    let transformed = v_vertices.iter().map(|p| {
+        ClipVertex {
+            position: mvp * p.extend(1.0),
+            view_position: Vector3::new(0.0, 0.0, 0.0),
+            normal: Vector3::new(0.0, 0.0, 0.0),
+            tangent: Vector4::new(0.0, 0.0, 0.0, 0.0),
+            tex_coords: Vector2::new(0.0, 0.0),
+            weight: 0.0,
+        }
+    }).collect::<Vec<_>>();
+
+    for t in v_index.chunks(3) {
+        if t.len() < 3 {
+            break;
+        }
+
+        let polygon = clip_polygon(vec![transformed[t[0] as usize], transformed[t[1] as usize], transformed[t[2] as usize]]);
+
+        for i in 1..polygon.len().saturating_sub(1) {
+            rasterize_depth(depth, resolution, &polygon[0], &polygon[i], &polygon[i + 1]);
+        }
+    }
+}
+
+// Line list drawn over the framebuffer, without depth test
+pub fn draw_lines(fb: &mut Framebuffer, lines: &[LinePoint], uniforms: &Uniforms) {
+    let mvp = uniforms.proj * uniforms.view * uniforms.world;
+    let (width, height) = (fb.width as f32, fb.height as f32);
+
+    for l in lines.chunks(2) {
+        if l.len() < 2 {
+            break;
+        }
+
+        let mut a = mvp * l[0].position.extend(1.0);
+        let mut b = mvp * l[1].position.extend(1.0);
+
+        // keep the part in front of the near plane
+        if a.z < 0.0 && b.z < 0.0 {
+            continue;
+        } else if a.z < 0.0 {
+            a = a + (b - a) * (a.z / (a.z - b.z));
+        } else if b.z < 0.0 {
+            b = b + (a - b) * (b.z / (b.z - a.z));
+        }
+
+        let to_screen = |v: Vector4<f32>| ((v.x / v.w + 1.0) * 0.5 * width, (v.y / v.w + 1.0) * 0.5 * height);
+        let (ax, ay) = to_screen(a);
+        let (bx, by) = to_screen(b);
+
+        let steps = (bx - ax).abs().max((by - ay).abs()).ceil().max(1.0).min(4.0 * (width + height));
+        let c = to_rgba8([l[0].color.x, l[0].color.y, l[0].color.z, 1.0]);
+
+        for i in 0..(steps as usize + 1) {
+            let t = i as f32 / steps;
+            let x = (ax + (bx - ax) * t).floor();
+            let y = (ay + (by - ay) * t).floor();
+
+            if x >= 0.0 && y >= 0.0 && x < width && y < height {
+                let idx = (y as usize * fb.width + x as usize) * 4;
+                fb.color[idx..idx + 4].copy_from_slice(&c);
+            }
+        }
+    }
+}
+
+// Color of a fragment, matching the `fs` shader. `position`, `normal` and
+// `tangent` are in view space.
+pub fn shade(shading: &Shading, position: Vector3<f32>, normal: Vector3<f32>, tangent: Vector4<f32>, tex_coords: Vector2<f32>, weight: f32) -> [f32; 4] {
+    let n = normal.normalize();
+
+    let regular_color = match shading.mode {
+        RenderMode::Shaded => {
+            let material = shading.material;
+            let t = tangent.truncate() - n * n.dot(tangent.truncate());
+
+            // tangent space normal from the `_local` map
+            let n = if t.magnitude2() > 1e-12 {
+                let t = t.normalize();
+                let b = n.cross(t) * tangent.w;
+                let m = material.normal.sample(tex_coords).truncate() * 2.0 - Vector3::new(1.0, 1.0, 1.0);
+                (t * m.x + b * m.y + n * m.z).normalize()
+            } else {
+                n
+            };
+
+            let shadow = shading.shadow.map(|s| {
+                let coords = s.matrix * position.extend(1.0);
+                (s.light, pcf(s.depth_map, s.resolution, coords.truncate() / coords.w, s.bias, s.pcf_radius))
+            });
+
+            let c = blinn_phong(material.diffuse.sample(tex_coords).truncate(), material.specular.sample(tex_coords).truncate(),
+                                position, n, shading.lights, shading.ambient, shading.shininess, shadow);
+            return [c.x, c.y, c.z, 1.0];
+        },
+        RenderMode::Wireframe => return [1.0, 1.0, 1.0, 1.0],
+        RenderMode::UvChecker => checker(tex_coords),
+        RenderMode::WeightHeatmap(_) => heat(weight),
+    };
+
+    // debug views are lit from the eye
+    let brightness = n.dot(Vector3::new(0.0, 0.0, 1.0));
+    let dark_color = regular_color * 0.6;
+
+    let c = dark_color + (regular_color - dark_color) * brightness;
+    [c.x, c.y, c.z, 1.0]
+}
+
+// 8x8 checkerboard over the unit square of texture space
+pub fn checker(tex_coords: Vector2<f32>) -> Vector3<f32> {
+    let cell = (tex_coords.x * 8.0).floor() + (tex_coords.y * 8.0).floor();
+
+    // like GLSL mod, positive for negative cells
+    if cell - 2.0 * (cell / 2.0).floor() == 0.0 { Vector3::new(0.9, 0.9, 0.9) } else { Vector3::new(0.2, 0.2, 0.2) }
+}
+
+// Blue for 0 through green to red for 1
+pub fn heat(weight: f32) -> Vector3<f32> {
+    let w = weight.max(0.0).min(1.0) * 2.0 - 1.0;
+    Vector3::new(w.max(0.0), 1.0 - w.abs(), (-w).max(0.0))
+}
+
+fn mat3(m: &Matrix4<f32>) -> Matrix3<f32> {
+    Matrix3::from_cols(m.x.truncate(), m.y.truncate(), m.z.truncate())
+}
+
+fn to_rgba8(c: [f32; 4]) -> [u8; 4] {
+    let conv = |v: f32| (v.max(0.0).min(1.0) * 255.0 + 0.5) as u8;
+    [conv(c[0]), conv(c[1]), conv(c[2]), conv(c[3])]
+}
+
+fn lerp_vertex(a: &ClipVertex, b: &ClipVertex, t: f32) -> ClipVertex {
+    ClipVertex {
+        position: a.position + (b.position - a.position) * t,
+        view_position: a.view_position + (b.view_position - a.view_position) * t,
+        normal: a.normal + (b.normal - a.normal) * t,
+        tangent: a.tangent + (b.tangent - a.tangent) * t,
+        tex_coords: a.tex_coords + (b.tex_coords - a.tex_coords) * t,
+        weight: a.weight + (b.weight - a.weight) * t,
+    }
+}
+
+// Sutherland-Hodgman against the Vulkan depth range, 0 <= z <= w
+fn clip_polygon(polygon: Vec<ClipVertex>) -> Vec<ClipVertex> {
+    let near = |v: &ClipVertex| v.position.z;
+    let far = |v: &ClipVertex| v.position.w - v.position.z;
+
+    let clipped = clip_against(polygon, &near);
+    clip_against(clipped, &far)
+}
+
+fn clip_against<F: Fn(&ClipVertex) -> f32>(polygon: Vec<ClipVertex>, distance: &F) -> Vec<ClipVertex> {
+    let mut res: Vec<ClipVertex> = Vec::new();
+
+    for i in 0..polygon.len() {
+        let a = &polygon[i];
+        let b = &polygon[(i + 1) % polygon.len()];
+        let da = distance(a);
+        let db = distance(b);
+
+        if da >= 0.0 {
+            res.push(*a);
+        }
+        if (da >= 0.0) != (db >= 0.0) {
+            res.push(lerp_vertex(a, b, da / (da - db)));
+        }
+    }
+
+    res
+}
+
+fn edge(a: (f32, f32), b: (f32, f32), p: (f32, f32)) -> f32 {
+    (b.0 - a.0) * (p.1 - a.1) - (b.1 - a.1) * (p.0 - a.0)
+}
+
+fn rasterize_depth(depth: &mut [f32], resolution: usize, v0: &ClipVertex, v1: &ClipVertex, v2: &ClipVertex) {
+    let size = resolution as f32;
+    let to_screen = |v: &ClipVertex| {
+        let inv_w = 1.0 / v.position.w;
+        ((v.position.x * inv_w + 1.0) * 0.5 * size, (v.position.y * inv_w + 1.0) * 0.5 * size, v.position.z * inv_w)
+    };
+
+    let s0 = to_screen(v0);
+    let s1 = to_screen(v1);
+    let s2 = to_screen(v2);
+
+    let area = edge((s0.0, s0.1), (s1.0, s1.1), (s2.0, s2.1));
+    if area == 0.0 || !area.is_finite() {
+        return;
+    }
+
+    let min_x = s0.0.min(s1.0).min(s2.0).floor().max(0.0) as usize;
+    let min_y = s0.1.min(s1.1).min(s2.1).floor().max(0.0) as usize;
+    let max_x = s0.0.max(s1.0).max(s2.0).ceil().min(size) as usize;
+    let max_y = s0.1.max(s1.1).max(s2.1).ceil().min(size) as usize;
+
+    for y in min_y..max_y {
+        for x in min_x..max_x {
+            let p = (x as f32 + 0.5, y as f32 + 0.5);
+
+            let b0 = edge((s1.0, s1.1), (s2.0, s2.1), p) / area;
+            let b1 = edge((s2.0, s2.1), (s0.0, s0.1), p) / area;
+            let b2 = edge((s0.0, s0.1), (s1.0, s1.1), p) / area;
+
+            if b0 < 0.0 || b1 < 0.0 || b2 < 0.0 {
+                continue;
+            }
+
+            let z = b0 * s0.2 + b1 * s1.2 + b2 * s2.2;
+            let idx = y * resolution + x;
+            if z < depth[idx] {
+                depth[idx] = z;
+            }
+        }
+    }
+}
+
+fn rasterize_triangle(fb: &mut Framebuffer, v0: &ClipVertex, v1: &ClipVertex, v2: &ClipVertex, shading: &Shading) {
+    let (width, height) = (fb.width as f32, fb.height as f32);
+
+    // viewport transform, origin at the upper left like the vulkano viewport
+    let to_screen = |v: &ClipVertex| {
+        let inv_w = 1.0 / v.position.w;
+        (
+            (v.position.x * inv_w + 1.0) * 0.5 * width,
+            (v.position.y * inv_w + 1.0) * 0.5 * height,
+            v.position.z * inv_w,
+            inv_w
+        )
+    };
+
+    let s0 = to_screen(v0);
+    let s1 = to_screen(v1);
+    let s2 = to_screen(v2);
+
+    let area = edge((s0.0, s0.1), (s1.0, s1.1), (s2.0, s2.1));
+    if area == 0.0 || !area.is_finite() {
+        return;
+    }
+
+    let min_x = s0.0.min(s1.0).min(s2.0).floor().max(0.0) as usize;
+    let min_y = s0.1.min(s1.1).min(s2.1).floor().max(0.0) as usize;
+    let max_x = s0.0.max(s1.0).max(s2.0).ceil().min(width) as usize;
+    let max_y = s0.1.max(s1.1).max(s2.1).ceil().min(height) as usize;
+
+    for y in min_y..max_y {
+        for x in min_x..max_x {
+            let p = (x as f32 + 0.5, y as f32 + 0.5);
+
+            let b0 = edge((s1.0, s1.1), (s2.0, s2.1), p) / area;
+            let b1 = edge((s2.0, s2.1), (s0.0, s0.1), p) / area;
+            let b2 = edge((s0.0, s0.1), (s1.0, s1.1), p) / area;
+
+            if b0 < 0.0 || b1 < 0.0 || b2 < 0.0 {
+                continue;
+            }
+
+            // z / w is affine in screen space
+            let depth = b0 * s0.2 + b1 * s1.2 + b2 * s2.2;
+            let idx = y * fb.width + x;
+            if depth >= fb.depth[idx] {
+                continue;
+            }
+
+            // perspective-correct interpolation of the attributes
+            let (w0, w1, w2) = (b0 * s0.3, b1 * s1.3, b2 * s2.3);
+            let sum = w0 + w1 + w2;
+            let position = (v0.view_position * w0 + v1.view_position * w1 + v2.view_position * w2) / sum;
+            let normal = (v0.normal * w0 + v1.normal * w1 + v2.normal * w2) / sum;
+            let tangent = (v0.tangent * w0 + v1.tangent * w1 + v2.tangent * w2) / sum;
+            let tex_coords = (v0.tex_coords * w0 + v1.tex_coords * w1 + v2.tex_coords * w2) / sum;
+            let weight = (v0.weight * w0 + v1.weight * w1 + v2.weight * w2) / sum;
+
+            let c = to_rgba8(shade(shading, position, normal, tangent, tex_coords, weight));
+            fb.depth[idx] = depth;
+            fb.color[idx * 4..idx * 4 + 4].copy_from_slice(&c);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector2, Vector3, Vector4, Matrix4, SquareMatrix};
+    use renderer::lighting::ShaderLight;
+    use renderer::material::Material;
+
+    fn uniforms() -> super::Uniforms {
+        super::Uniforms {
+            world: Matrix4::identity(),
+            view: Matrix4::identity(),
+            proj: Matrix4::identity(),
+        }
+    }
+
+    // Red light shining along -Z, no ambient
+    fn light() -> Vec<ShaderLight> {
+        vec![ShaderLight { vector: Vector4::new(0.0, 0.0, 1.0, 0.0), color: Vector4::new(1.0, 0.0, 0.0, 0.0) }]
+    }
+
+    fn shading<'a>(material: &'a Material, lights: &'a [ShaderLight]) -> super::Shading<'a> {
+        super::Shading {
+            mode: super::RenderMode::Shaded,
+            material: material,
+            lights: lights,
+            ambient: Vector3::new(0.0, 0.0, 0.0),
+            shininess: 32.0,
+            shadow: None,
+        }
+    }
+
+    #[test]
+    fn draw_mesh() {
+        let mut fb = super::Framebuffer::new(8, 8);
+        fb.clear([0.0, 0.0, 1.0, 1.0]);
+
+        let vertices = vec![Vector3::new(-0.5, -0.5, 0.5), Vector3::new(0.5, -0.5, 0.5), Vector3::new(0.0, 0.5, 0.5)];
+        let normals = vec![Vector3::new(0.0, 0.0, 1.0); 3];
+        let attributes = super::Attributes { positions: &vertices, normals: &normals, .. Default::default() };
+        let (material, lights) = (Material::new(), light());
+
+        super::draw_mesh(&mut fb, &attributes, &[0, 1, 2], &uniforms(), &shading(&material, &lights));
+
+        assert_eq!(fb.pixel(4, 4), [255, 0, 0, 255]);
+        assert_eq!(fb.pixel(0, 0), [0, 0, 255, 255]);
+        assert_eq!(fb.pixel(4, 7), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn normal_map() {
+        let mut material = Material::new();
+        let lights = light();
+        let facing = Vector3::new(0.0, 0.0, 1.0);
+        let tangent = Vector4::new(1.0, 0.0, 0.0, 1.0);
+
+        // normal bent towards the tangent by 60 degrees
+        material.normal.data = vec![238, 128, 192, 255];
+        let c = super::shade(&shading(&material, &lights), Vector3::new(0.0, 0.0, -1.0), facing, tangent, Vector2::new(0.0, 0.0), 0.0);
+
+        assert!((c[0] - 0.5).abs() < 0.02);
+    }
+
+    #[test]
+    fn render_modes() {
+        let material = Material::new();
+        let mut heatmap = shading(&material, &[]);
+        heatmap.mode = super::RenderMode::WeightHeatmap(0);
+        let facing = Vector3::new(0.0, 0.0, 1.0);
+        let tangent = Vector4::new(1.0, 0.0, 0.0, 1.0);
+
+        assert_eq!(super::shade(&heatmap, facing, facing, tangent, Vector2::new(0.0, 0.0), 1.0), [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(super::shade(&heatmap, facing, facing, tangent, Vector2::new(0.0, 0.0), 0.0), [0.0, 0.0, 1.0, 1.0]);
+        assert_eq!(super::checker(Vector2::new(0.05, 0.05)), super::checker(Vector2::new(0.2, 0.2)));
+        assert!(super::checker(Vector2::new(0.05, 0.05)) != super::checker(Vector2::new(0.2, 0.05)));
+    }
+
+    #[test]
+    fn depth_test() {
+        let mut fb = super::Framebuffer::new(4, 4);
+        fb.clear([0.0, 0.0, 0.0, 1.0]);
+
+        let quad = |z: f32| vec![Vector3::new(-1.0, -1.0, z), Vector3::new(3.0, -1.0, z), Vector3::new(-1.0, 3.0, z)];
+        let facing = vec![Vector3::new(0.0, 0.0, 1.0); 3];
+        let away = vec![Vector3::new(0.0, 0.0, -1.0); 3];
+
+        let (near, far) = (quad(0.2), quad(0.8));
+        let (material, lights) = (Material::new(), light());
+
+        super::draw_mesh(&mut fb, &super::Attributes { positions: &near, normals: &facing, .. Default::default() },
+                         &[0, 1, 2], &uniforms(), &shading(&material, &lights));
+        super::draw_mesh(&mut fb, &super::Attributes { positions: &far, normals: &away, .. Default::default() },
+                         &[0, 1, 2], &uniforms(), &shading(&material, &lights));
+
+        assert_eq!(fb.pixel(1, 1), [255, 0, 0, 255]);
+        assert!(fb.depth[5] < 0.3);
+    }
+
+    #[test]
+    fn shadow() {
+        // occluder covering the left half of the map at depth 0.25
+        let mut depth_map = vec![1.0; 16];
+        let occluder = vec![Vector3::new(-1.0, -1.0, 0.25), Vector3::new(0.0, -1.0, 0.25), Vector3::new(0.0, 3.0, 0.25),
+                            Vector3::new(-1.0, 3.0, 0.25)];
+        super::draw_depth(&mut depth_map, 4, &occluder, &[0, 1, 2, 0, 2, 3], &Matrix4::identity());
+
+        assert_eq!(depth_map[4], 0.25);
+        assert_eq!(depth_map[7], 1.0);
+
+        let (material, lights) = (Material::new(), light());
+        let mut shadowed = shading(&material, &lights);
+        shadowed.shadow = Some(super::ShadowLookup {
+            light: 0,
+            matrix: Matrix4::identity(),
+            depth_map: &depth_map,
+            resolution: 4,
+            bias: 0.01,
+            pcf_radius: 0,
+        });
+        let facing = Vector3::new(0.0, 0.0, 1.0);
+        let tangent = Vector4::new(1.0, 0.0, 0.0, 1.0);
+
+        assert_eq!(super::shade(&shadowed, Vector3::new(-0.5, 0.0, 0.5), facing, tangent, Vector2::new(0.0, 0.0), 0.0)[0], 0.0);
+        assert!(super::shade(&shadowed, Vector3::new(0.5, 0.0, 0.5), facing, tangent, Vector2::new(0.0, 0.0), 0.0)[0] > 0.99);
+    }
+}
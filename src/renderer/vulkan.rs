@@ -0,0 +1,1320 @@
+use vulkano_win::VkSurfaceBuild;
+use vulkano::sync::GpuFuture;
+
+use std::sync::Arc;
+use std::ops::Range;
+use std::fmt;
+use vulkano_win;
+use vulkano;
+use winit;
+use cgmath::{Matrix4, SquareMatrix};
+
+use md5::md5mesh::{Md5Mesh, Joint};
+use renderer::backend::Renderer;
+use renderer::config::{RendererConfig, DeviceType, PresentMode, DepthFormat};
+use renderer::debug::{LinePoint, RenderMode};
+use renderer::error::RendererError;
+use renderer::lighting::{Lighting, MAX_LIGHTS, view_space_lights};
+use renderer::material::{Material, Texture};
+use renderer::scene::InstancePose;
+use renderer::shadow::{ShadowConfig, GroundPlane, shadow_caster, light_matrix};
+use vertex_computation::compute::{prepare_full_mesh, prepare_full_tex_coords, prepare_full_tangents, prepare_index_ranges};
+use vertex_computation::convert::{SkinnedVertex, DebugVertex, InstanceData, skinned_to_vulkano, palette_to_vulkano, lines_to_vulkano, instance_to_vulkano};
+use vertex_computation::skinning::{Influences, MAX_INFLUENCES, prepare_full_influences, joint_palette};
+
+// Bind pose geometry, skinned in the vertex shader with `palette_buffer`
+struct GpuMesh {
+    vertex_buffer: Arc<vulkano::buffer::cpu_access::CpuAccessibleBuffer<[SkinnedVertex]>>,
+    index_buffer: Arc<vulkano::buffer::cpu_access::CpuAccessibleBuffer<[u16]>>,
+    palette_buffer: Arc<vulkano::buffer::cpu_access::CpuAccessibleBuffer<[[[f32; 4]; 4]]>>,
+    // indices of each mesh, drawn with the material of the same rank
+    ranges: Vec<Range<usize>>,
+}
+
+// Transforms of the instances and their joint palettes, one after the other
+struct GpuInstances {
+    instance_buffer: Arc<vulkano::buffer::cpu_access::CpuAccessibleBuffer<[InstanceData]>>,
+    palette_buffer: Arc<vulkano::buffer::cpu_access::CpuAccessibleBuffer<[[[f32; 4]; 4]]>>,
+}
+
+// Ground geometry, with no joint influence so the vertex shader leaves it in place
+struct GpuGround {
+    vertex_buffer: Arc<vulkano::buffer::cpu_access::CpuAccessibleBuffer<[SkinnedVertex]>>,
+    index_buffer: Arc<vulkano::buffer::cpu_access::CpuAccessibleBuffer<[u16]>>,
+    material: MaterialSet,
+}
+
+// Depth image the model is rendered to from the shadow casting light
+type ShadowMap = Arc<vulkano::image::AttachmentImage<vulkano::format::D16Unorm>>;
+
+type MaterialSet = Arc<vulkano::descriptor::descriptor_set::DescriptorSet + Send + Sync>;
+
+// Depth buffer, and the multisampled color image resolved to the swapchain image with MSAA
+type Attachments = (Arc<vulkano::image::AttachmentImage<vulkano::format::Format>>,
+                    Option<Arc<vulkano::image::AttachmentImage<vulkano::format::Format>>>);
+
+pub struct VulkanRenderer {
+    instance: Arc<vulkano::instance::Instance>,
+    physical_index: usize,
+    surface: Arc<vulkano::swapchain::Surface<winit::Window>>,
+    device: Arc<vulkano::device::Device>,
+    queue: Arc<vulkano::device::Queue>,
+    swapchain: Arc<vulkano::swapchain::Swapchain<winit::Window>>,
+    images: Vec<Arc<vulkano::image::SwapchainImage<winit::Window>>>,
+    depth_buffer: Arc<vulkano::image::AttachmentImage<vulkano::format::Format>>,
+    msaa_buffer: Option<Arc<vulkano::image::AttachmentImage<vulkano::format::Format>>>,
+    depth_format: vulkano::format::Format,
+    samples: u32,
+    // whether the swapchain is sRGB, color textures being then sampled as sRGB too
+    srgb: bool,
+    renderpass: Arc<vulkano::framebuffer::RenderPassAbstract + Send + Sync>,
+    pipeline: Arc<vulkano::pipeline::GraphicsPipelineAbstract + Send + Sync>,
+    wireframe_pipeline: Arc<vulkano::pipeline::GraphicsPipelineAbstract + Send + Sync>,
+    debug_pipeline: Arc<vulkano::pipeline::GraphicsPipelineAbstract + Send + Sync>,
+    framebuffers: Option<Vec<Arc<vulkano::framebuffer::FramebufferAbstract + Send + Sync>>>,
+    uniform_buffer: vulkano::buffer::cpu_pool::CpuBufferPool<vs::ty::Data>,
+    lights_buffer: vulkano::buffer::cpu_pool::CpuBufferPool<fs::ty::Lights>,
+    sampler: Arc<vulkano::sampler::Sampler>,
+    shadow_renderpass: Arc<vulkano::framebuffer::RenderPassAbstract + Send + Sync>,
+    shadow_pipeline: Arc<vulkano::pipeline::GraphicsPipelineAbstract + Send + Sync>,
+    shadow_uniform_buffer: vulkano::buffer::cpu_pool::CpuBufferPool<shadow_vs::ty::Data>,
+    shadow_map: ShadowMap,
+    shadow_framebuffer: Arc<vulkano::framebuffer::FramebufferAbstract + Send + Sync>,
+    shadow_sampler: Arc<vulkano::sampler::Sampler>,
+    default_material: MaterialSet,
+    // identity transform, for the single model and the ground
+    single_instance: Arc<vulkano::buffer::cpu_access::CpuAccessibleBuffer<[InstanceData]>>,
+    previous_frame: Option<Box<GpuFuture>>,
+    recreate_swapchain: bool,
+    dimensions: [u32; 2],
+
+    model: Option<Md5Mesh>,
+    mesh: Option<GpuMesh>,
+    // None draws the single model, Some(None) nothing, no instance being visible
+    instances: Option<Option<GpuInstances>>,
+    debug_lines: Option<Arc<vulkano::buffer::cpu_access::CpuAccessibleBuffer<[DebugVertex]>>>,
+    materials: Vec<MaterialSet>,
+    lighting: Lighting,
+    shadows: ShadowConfig,
+    ground: Option<(GroundPlane, GpuGround)>,
+    mode: RenderMode,
+    world: Matrix4<f32>,
+    view: Matrix4<f32>,
+    proj: Matrix4<f32>,
+}
+
+impl VulkanRenderer {
+    pub fn new(events_loop: &winit::EventsLoop, config: &RendererConfig) -> Result<VulkanRenderer, RendererError> {
+        let extensions = vulkano_win::required_extensions();
+        let instance = check(vulkano::instance::Instance::new(None, &extensions, None), RendererError::Instance)?;
+
+        let physical = {
+            let devices = vulkano::instance::PhysicalDevice::enumerate(&instance).collect::<Vec<_>>();
+            let index = config.pick_device(&devices.iter().map(|d| (d.name().to_string(), device_type(d.ty()))).collect::<Vec<_>>())
+                              .ok_or(RendererError::NoDevice)?;
+            devices[index]
+        };
+        println!("Using device: {} (type: {:?})", physical.name(), physical.ty());
+
+        let surface = check(winit::WindowBuilder::new().build_vk_surface(events_loop, instance.clone()), RendererError::Surface)?;
+
+        let queue = physical.queue_families().find(|&q| q.supports_graphics() &&
+                                                       surface.is_supported(q).unwrap_or(false))
+                                                    .ok_or(RendererError::NoDevice)?;
+
+        let device_ext = vulkano::device::DeviceExtensions {
+            khr_swapchain: true,
+            .. vulkano::device::DeviceExtensions::none()
+        };
+
+        let (device, mut queues) = check(vulkano::device::Device::new(physical, physical.supported_features(),
+                                                                      &device_ext, [(queue, 0.5)].iter().cloned()),
+                                         RendererError::Device)?;
+        let queue = queues.next().ok_or(RendererError::Device(String::from("no queue created")))?;
+
+        let dimensions;
+        let (swapchain, images) = {
+            let caps = check(surface.capabilities(physical), RendererError::Surface)?;
+
+            dimensions = caps.current_extent.unwrap_or([1024, 768]);
+
+            let usage = caps.supported_usage_flags;
+            let srgb_formats = caps.supported_formats.iter().map(|&(f, _)| is_srgb(f)).collect::<Vec<_>>();
+            let format = caps.supported_formats[config.pick_color_format(&srgb_formats)].0;
+            let alpha = caps.supported_composite_alpha.iter().next()
+                            .ok_or(RendererError::Swapchain(String::from("no composite alpha mode supported")))?;
+
+            let present_modes = [PresentMode::Vsync, PresentMode::Immediate, PresentMode::Mailbox].iter().cloned()
+                .filter(|&m| caps.present_modes.supports(present_mode(m)))
+                .collect::<Vec<_>>();
+            let present = config.pick_present_mode(&present_modes);
+            println!("Swapchain: {:?}, {:?}", format, present);
+
+            check(vulkano::swapchain::Swapchain::new(device.clone(), surface.clone(), caps.min_image_count, format, dimensions, 1,
+                                                     usage, &queue, vulkano::swapchain::SurfaceTransform::Identity,
+                                                     alpha,
+                                                     present_mode(present), true, None), RendererError::Swapchain)?
+        };
+        let srgb = is_srgb(swapchain.format());
+
+        // formats the device can't use as depth attachment fail to create
+        let depth_formats = [DepthFormat::D16, DepthFormat::D24S8, DepthFormat::D32, DepthFormat::D32S8].iter().cloned()
+            .filter(|&f| vulkano::image::attachment::AttachmentImage::transient(device.clone(), [1, 1], depth_format(f)).is_ok())
+            .collect::<Vec<_>>();
+        let depth = depth_format(config.pick_depth_format(&depth_formats)
+                                 .ok_or(RendererError::Resource(String::from("no supported depth format")))?);
+
+        let samples = {
+            let limits = physical.limits();
+            config.pick_samples(limits.framebuffer_color_sample_counts() & limits.framebuffer_depth_sample_counts())
+        };
+        println!("Depth buffer: {:?}, {} sample(s)", depth, samples);
+
+        let (depth_buffer, msaa_buffer) = attachments(&device, dimensions, swapchain.format(), depth, samples)?;
+
+        let uniform_buffer = vulkano::buffer::cpu_pool::CpuBufferPool::<vs::ty::Data>
+                                   ::new(device.clone(), vulkano::buffer::BufferUsage::all());
+        let lights_buffer = vulkano::buffer::cpu_pool::CpuBufferPool::<fs::ty::Lights>
+                                   ::new(device.clone(), vulkano::buffer::BufferUsage::all());
+
+        let sampler = check(vulkano::sampler::Sampler::new(device.clone(), vulkano::sampler::Filter::Linear,
+                                                           vulkano::sampler::Filter::Linear, vulkano::sampler::MipmapMode::Nearest,
+                                                           vulkano::sampler::SamplerAddressMode::Repeat,
+                                                           vulkano::sampler::SamplerAddressMode::Repeat,
+                                                           vulkano::sampler::SamplerAddressMode::Repeat,
+                                                           0.0, 1.0, 0.0, 0.0), RendererError::Resource)?;
+
+        // depth comparisons are done in the shader, on unfiltered texels
+        let shadow_sampler = check(vulkano::sampler::Sampler::new(device.clone(), vulkano::sampler::Filter::Nearest,
+                                                                  vulkano::sampler::Filter::Nearest, vulkano::sampler::MipmapMode::Nearest,
+                                                                  vulkano::sampler::SamplerAddressMode::ClampToEdge,
+                                                                  vulkano::sampler::SamplerAddressMode::ClampToEdge,
+                                                                  vulkano::sampler::SamplerAddressMode::ClampToEdge,
+                                                                  0.0, 1.0, 0.0, 0.0), RendererError::Resource)?;
+
+        let vs = check(vs::Shader::load(device.clone()), RendererError::Pipeline)?;
+        let fs = check(fs::Shader::load(device.clone()), RendererError::Pipeline)?;
+        let debug_vs = check(debug_vs::Shader::load(device.clone()), RendererError::Pipeline)?;
+        let debug_fs = check(debug_fs::Shader::load(device.clone()), RendererError::Pipeline)?;
+        let shadow_vs = check(shadow_vs::Shader::load(device.clone()), RendererError::Pipeline)?;
+        let shadow_fs = check(shadow_fs::Shader::load(device.clone()), RendererError::Pipeline)?;
+
+        let renderpass = main_renderpass(&device, swapchain.format(), depth, samples)?;
+
+        let pipeline: Arc<vulkano::pipeline::GraphicsPipelineAbstract + Send + Sync> = Arc::new(check(vulkano::pipeline::GraphicsPipeline::start()
+            .vertex_input(vulkano::pipeline::vertex::OneVertexOneInstanceDefinition::<SkinnedVertex, InstanceData>::new())
+            .vertex_shader(vs.main_entry_point(), ())
+            .triangle_list()
+            .viewports_dynamic_scissors_irrelevant(1)
+            .fragment_shader(fs.main_entry_point(), ())
+            .depth_stencil_simple_depth()
+            .render_pass(subpass(&renderpass)?)
+            .build(device.clone()), RendererError::Pipeline)?);
+
+        // same shaders over the triangle edges, without depth test so hidden edges show too
+        let wireframe_pipeline = Arc::new(check(vulkano::pipeline::GraphicsPipeline::start()
+            .vertex_input(vulkano::pipeline::vertex::OneVertexOneInstanceDefinition::<SkinnedVertex, InstanceData>::new())
+            .vertex_shader(vs.main_entry_point(), ())
+            .triangle_list()
+            .polygon_mode_line()
+            .viewports_dynamic_scissors_irrelevant(1)
+            .fragment_shader(fs.main_entry_point(), ())
+            .render_pass(subpass(&renderpass)?)
+            .build(device.clone()), RendererError::Pipeline)?);
+
+        // overlay lines, drawn without depth test so they show through the model
+        let debug_pipeline = Arc::new(check(vulkano::pipeline::GraphicsPipeline::start()
+            .vertex_input_single_buffer::<DebugVertex>()
+            .vertex_shader(debug_vs.main_entry_point(), ())
+            .line_list()
+            .viewports_dynamic_scissors_irrelevant(1)
+            .fragment_shader(debug_fs.main_entry_point(), ())
+            .render_pass(subpass(&renderpass)?)
+            .build(device.clone()), RendererError::Pipeline)?);
+
+        // depth only pass of the model seen from the light, kept for the main pass to sample
+        let shadow_renderpass = Arc::new(check(
+            single_pass_renderpass!(device.clone(),
+                attachments: {
+                    depth: {
+                        load: Clear,
+                        store: Store,
+                        format: vulkano::format::Format::D16Unorm,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [],
+                    depth_stencil: {depth}
+                }
+            ), RendererError::Pipeline)?
+        ) as Arc<vulkano::framebuffer::RenderPassAbstract + Send + Sync>;
+
+        let shadow_pipeline = Arc::new(check(vulkano::pipeline::GraphicsPipeline::start()
+            .vertex_input(vulkano::pipeline::vertex::OneVertexOneInstanceDefinition::<SkinnedVertex, InstanceData>::new())
+            .vertex_shader(shadow_vs.main_entry_point(), ())
+            .triangle_list()
+            .viewports_dynamic_scissors_irrelevant(1)
+            .fragment_shader(shadow_fs.main_entry_point(), ())
+            .depth_stencil_simple_depth()
+            .render_pass(subpass(&shadow_renderpass)?)
+            .build(device.clone()), RendererError::Pipeline)?);
+
+        let shadow_uniform_buffer = vulkano::buffer::cpu_pool::CpuBufferPool::<shadow_vs::ty::Data>
+                                   ::new(device.clone(), vulkano::buffer::BufferUsage::all());
+
+        let shadows = ShadowConfig::new();
+        let (shadow_map, shadow_framebuffer) = shadow_target(&device, &shadow_renderpass, shadows.resolution)?;
+
+        let (default_material, upload) = material_set(&queue, &pipeline, &sampler, &Material::new(), srgb)?;
+        let single_instance = check(vulkano::buffer::cpu_access::CpuAccessibleBuffer
+                                        ::from_iter(device.clone(), vulkano::buffer::BufferUsage::all(),
+                                                    Some(instance_to_vulkano(&Matrix4::identity(), 0)).into_iter()),
+                                    RendererError::Resource)?;
+
+        Ok(VulkanRenderer {
+            previous_frame: Some(Box::new(vulkano::sync::now(device.clone()).join(upload)) as Box<GpuFuture>),
+            instance: instance.clone(),
+            physical_index: physical.index(),
+            surface: surface,
+            device: device,
+            queue: queue,
+            swapchain: swapchain,
+            images: images,
+            depth_buffer: depth_buffer,
+            msaa_buffer: msaa_buffer,
+            depth_format: depth,
+            samples: samples,
+            srgb: srgb,
+            renderpass: renderpass,
+            pipeline: pipeline,
+            wireframe_pipeline: wireframe_pipeline,
+            debug_pipeline: debug_pipeline,
+            framebuffers: None,
+            uniform_buffer: uniform_buffer,
+            lights_buffer: lights_buffer,
+            sampler: sampler,
+            shadow_renderpass: shadow_renderpass,
+            shadow_pipeline: shadow_pipeline,
+            shadow_uniform_buffer: shadow_uniform_buffer,
+            shadow_map: shadow_map,
+            shadow_framebuffer: shadow_framebuffer,
+            shadow_sampler: shadow_sampler,
+            default_material: default_material,
+            single_instance: single_instance,
+            recreate_swapchain: false,
+            dimensions: dimensions,
+
+            model: None,
+            mesh: None,
+            instances: None,
+            debug_lines: None,
+            materials: Vec::new(),
+            lighting: Lighting::new(),
+            shadows: shadows,
+            ground: None,
+            mode: RenderMode::Shaded,
+            world: Matrix4::identity(),
+            view: Matrix4::identity(),
+            proj: Matrix4::identity(),
+        })
+    }
+
+    fn upload_palette(&self, skeleton: &[Joint]) -> Result<Option<Arc<vulkano::buffer::cpu_access::CpuAccessibleBuffer<[[[f32; 4]; 4]]>>>, RendererError> {
+        let palette = match self.model {
+            Some(ref model) => palette_to_vulkano(&joint_palette(&model.joints, skeleton)),
+            None => return Ok(None)
+        };
+
+        Ok(Some(check(vulkano::buffer::cpu_access::CpuAccessibleBuffer
+                          ::from_iter(self.device.clone(), vulkano::buffer::BufferUsage::all(), palette.iter().cloned()),
+                      RendererError::Resource)?))
+    }
+
+    // The future of the last submitted frame, or of nothing
+    fn take_previous_frame(&mut self) -> Box<GpuFuture> {
+        match self.previous_frame.take() {
+            Some(future) => future,
+            None => Box::new(vulkano::sync::now(self.device.clone())) as Box<GpuFuture>
+        }
+    }
+
+    // false when the window can't be drawn to at the moment, while minimized for instance
+    fn rebuild_swapchain(&mut self) -> Result<bool, RendererError> {
+        let physical = vulkano::instance::PhysicalDevice::from_index(&self.instance, self.physical_index)
+                           .ok_or(RendererError::NoDevice)?;
+
+        self.dimensions = check(self.surface.capabilities(physical), RendererError::Surface)?
+            .current_extent.unwrap_or(self.dimensions);
+
+        let (new_swapchain, new_images) = match self.swapchain.recreate_with_dimension(self.dimensions) {
+            Ok(r) => r,
+            Err(vulkano::swapchain::SwapchainCreationError::UnsupportedDimensions) => {
+                return Ok(false);
+            },
+            Err(err) => return Err(RendererError::Swapchain(format!("{:?}", err)))
+        };
+
+        self.swapchain = new_swapchain;
+        self.images = new_images;
+
+        let (depth_buffer, msaa_buffer) = attachments(&self.device, self.dimensions, self.swapchain.format(), self.depth_format, self.samples)?;
+        self.depth_buffer = depth_buffer;
+        self.msaa_buffer = msaa_buffer;
+
+        self.framebuffers = None;
+        self.recreate_swapchain = false;
+        Ok(true)
+    }
+
+    // Records the shadow pass and the main pass, drawing to `framebuffer`
+    fn record_frame(&self, framebuffer: Arc<vulkano::framebuffer::FramebufferAbstract + Send + Sync>)
+                    -> Result<vulkano::command_buffer::AutoCommandBufferBuilder, RendererError> {
+        // light matrix of the shadow caster, fitted on the space above the ground
+        let caster = match (self.shadows.enabled, &self.ground, shadow_caster(&self.lighting)) {
+            (true, &Some((ref ground, _)), Some((index, direction))) => {
+                let (center, radius) = ground.shadow_bounds();
+                Some((index, light_matrix(direction, (self.world * center.extend(1.0)).truncate(), radius)))
+            },
+            _ => None
+        };
+        let light = caster.map(|(_, light)| light).unwrap_or(Matrix4::identity());
+
+        let uniform_buffer_subbuffer = {
+            let (mode, joint) = shader_mode(self.mode);
+            let uniform_data = vs::ty::Data {
+                world : self.world.into(),
+                view : self.view.into(),
+                proj : self.proj.into(),
+                shadow : light.into(),
+                mode : mode,
+                joint : joint,
+            };
+
+            check(self.uniform_buffer.next(uniform_data), RendererError::Resource)?
+        };
+
+        let lights_buffer_subbuffer = {
+            let lights = view_space_lights(&self.lighting, &self.view);
+            let mut lights_data = fs::ty::Lights {
+                ambient: self.lighting.ambient.extend(0.0).into(),
+                vectors: [[0.0; 4]; MAX_LIGHTS],
+                colors: [[0.0; 4]; MAX_LIGHTS],
+                count: lights.len() as u32,
+                shininess: self.lighting.shininess,
+                shadow_light: caster.map(|(index, _)| index as i32).unwrap_or(-1),
+                shadow_bias: self.shadows.bias,
+                shadow_pcf: self.shadows.pcf_radius as i32,
+            };
+            for (i, light) in lights.iter().enumerate() {
+                lights_data.vectors[i] = light.vector.into();
+                lights_data.colors[i] = light.color.into();
+            }
+
+            check(self.lights_buffer.next(lights_data), RendererError::Resource)?
+        };
+
+        let dimensions = self.dimensions;
+        let dynamic_state = || vulkano::command_buffer::DynamicState {
+              line_width: None,
+              viewports: Some(vec![vulkano::pipeline::viewport::Viewport {
+                  origin: [0.0, 0.0],
+                  dimensions: [dimensions[0] as f32, dimensions[1] as f32],
+                  depth_range: 0.0 .. 1.0,
+              }]),
+              scissors: None,
+        };
+
+        // the shadow map is cleared even without caster, the main pass samples it anyway
+        let builder = check(vulkano::command_buffer::AutoCommandBufferBuilder::primary_one_time_submit(self.device.clone(), self.queue.family()),
+                            RendererError::Frame)?;
+        let mut builder = check(builder.begin_render_pass(self.shadow_framebuffer.clone(), false, vec![1f32.into()]), RendererError::Frame)?;
+
+        // instances of the model and their palettes, none when no instance is visible
+        let drawn = match (self.mesh.as_ref(), &self.instances) {
+            (Some(mesh), &None) => Some((self.single_instance.clone(), mesh.palette_buffer.clone())),
+            (Some(_), &Some(Some(ref instances))) => Some((instances.instance_buffer.clone(), instances.palette_buffer.clone())),
+            _ => None
+        };
+
+        if let (Some(_), Some(mesh), Some((ref instance_buffer, ref palette_buffer))) = (caster, self.mesh.as_ref(), drawn.clone()) {
+            let shadow_data = check(self.shadow_uniform_buffer.next(shadow_vs::ty::Data {
+                world: self.world.into(),
+                light: light.into(),
+            }), RendererError::Resource)?;
+
+            let set = vulkano::descriptor::descriptor_set::PersistentDescriptorSet::start(self.shadow_pipeline.clone(), 0);
+            let set = check(set.add_buffer(shadow_data), RendererError::Resource)?;
+            let set = check(set.add_buffer(palette_buffer.clone()), RendererError::Resource)?;
+            let set = Arc::new(check(set.build(), RendererError::Resource)?);
+
+            let resolution = self.shadows.resolution as f32;
+            let shadow_state = vulkano::command_buffer::DynamicState {
+                line_width: None,
+                viewports: Some(vec![vulkano::pipeline::viewport::Viewport {
+                    origin: [0.0, 0.0],
+                    dimensions: [resolution, resolution],
+                    depth_range: 0.0 .. 1.0,
+                }]),
+                scissors: None,
+            };
+
+            builder = check(builder.draw_indexed(
+                self.shadow_pipeline.clone(),
+                shadow_state,
+                vec![mesh.vertex_buffer.clone() as Arc<vulkano::buffer::BufferAccess + Send + Sync>,
+                     instance_buffer.clone() as Arc<vulkano::buffer::BufferAccess + Send + Sync>],
+                mesh.index_buffer.clone(), set, ()), RendererError::Frame)?;
+        }
+
+        let mut clear_values = vec![[0.0, 0.0, 1.0, 1.0].into(), 1f32.into()];
+        if self.msaa_buffer.is_some() {
+            // the resolved swapchain image
+            clear_values.push(vulkano::format::ClearValue::None);
+        }
+
+        builder = check(builder.end_render_pass(), RendererError::Frame)?;
+        builder = check(builder.begin_render_pass(framebuffer, false, clear_values), RendererError::Frame)?;
+
+        if let Some(ref mesh) = self.mesh {
+            let pipeline = match self.mode {
+                RenderMode::Wireframe => self.wireframe_pipeline.clone(),
+                _ => self.pipeline.clone()
+            };
+
+            let set = vulkano::descriptor::descriptor_set::PersistentDescriptorSet::start(pipeline.clone(), 0);
+            let set = check(set.add_buffer(uniform_buffer_subbuffer.clone()), RendererError::Resource)?;
+            let palette_buffer = drawn.as_ref().map(|&(_, ref palette)| palette.clone()).unwrap_or(mesh.palette_buffer.clone());
+            let set = check(set.add_buffer(palette_buffer), RendererError::Resource)?;
+            let set = check(set.add_buffer(lights_buffer_subbuffer.clone()), RendererError::Resource)?;
+            let set = check(set.add_sampled_image(self.shadow_map.clone(), self.shadow_sampler.clone()), RendererError::Resource)?;
+            let set = Arc::new(check(set.build(), RendererError::Resource)?);
+
+            if let Some((ref instance_buffer, _)) = drawn {
+                for (i, range) in mesh.ranges.iter().enumerate() {
+                    let material = self.materials.get(i).unwrap_or(&self.default_material).clone();
+                    let indices = vulkano::buffer::BufferSlice::from_typed_buffer_access(mesh.index_buffer.clone())
+                        .slice(range.clone())
+                        .ok_or(RendererError::Frame(format!("index range {:?} out of the index buffer", range)))?;
+
+                    builder = check(builder.draw_indexed(
+                        pipeline.clone(),
+                        dynamic_state(),
+                        vec![mesh.vertex_buffer.clone() as Arc<vulkano::buffer::BufferAccess + Send + Sync>,
+                             instance_buffer.clone() as Arc<vulkano::buffer::BufferAccess + Send + Sync>],
+                        indices, (set.clone(), material), ()), RendererError::Frame)?;
+                }
+            }
+
+            if let (RenderMode::Shaded, &Some((_, ref ground))) = (self.mode, &self.ground) {
+                builder = check(builder.draw_indexed(
+                    pipeline.clone(),
+                    dynamic_state(),
+                    vec![ground.vertex_buffer.clone() as Arc<vulkano::buffer::BufferAccess + Send + Sync>,
+                         self.single_instance.clone() as Arc<vulkano::buffer::BufferAccess + Send + Sync>],
+                    ground.index_buffer.clone(), (set.clone(), ground.material.clone()), ()), RendererError::Frame)?;
+            }
+        }
+
+        if let Some(ref lines) = self.debug_lines {
+            let set = vulkano::descriptor::descriptor_set::PersistentDescriptorSet::start(self.debug_pipeline.clone(), 0);
+            let set = check(set.add_buffer(uniform_buffer_subbuffer.clone()), RendererError::Resource)?;
+            let set = Arc::new(check(set.build(), RendererError::Resource)?);
+
+            builder = check(builder.draw(
+                self.debug_pipeline.clone(),
+                dynamic_state(),
+                vec![lines.clone() as Arc<vulkano::buffer::BufferAccess + Send + Sync>],
+                set, ()), RendererError::Frame)?;
+        }
+
+        check(builder.end_render_pass(), RendererError::Frame)
+    }
+
+    fn create_framebuffers(&self) -> Result<Vec<Arc<vulkano::framebuffer::FramebufferAbstract + Send + Sync>>, RendererError> {
+        self.images.iter().map(|image| self.framebuffer(image.clone())).collect()
+    }
+
+    // Framebuffer of the main render pass drawing to `image`, of the swapchain format and dimensions
+    fn framebuffer<I>(&self, image: I) -> Result<Arc<vulkano::framebuffer::FramebufferAbstract + Send + Sync>, RendererError>
+        where I: vulkano::image::ImageViewAccess + Send + Sync + 'static {
+        let framebuffer = vulkano::framebuffer::Framebuffer::start(self.renderpass.clone());
+
+        Ok(match self.msaa_buffer {
+            Some(ref msaa_buffer) => {
+                let framebuffer = check(framebuffer.add(msaa_buffer.clone()), RendererError::Resource)?;
+                let framebuffer = check(framebuffer.add(self.depth_buffer.clone()), RendererError::Resource)?;
+                let framebuffer = check(framebuffer.add(image), RendererError::Resource)?;
+                Arc::new(check(framebuffer.build(), RendererError::Resource)?) as Arc<vulkano::framebuffer::FramebufferAbstract + Send + Sync>
+            },
+            None => {
+                let framebuffer = check(framebuffer.add(image), RendererError::Resource)?;
+                let framebuffer = check(framebuffer.add(self.depth_buffer.clone()), RendererError::Resource)?;
+                Arc::new(check(framebuffer.build(), RendererError::Resource)?) as Arc<vulkano::framebuffer::FramebufferAbstract + Send + Sync>
+            }
+        })
+    }
+}
+
+impl Drop for VulkanRenderer {
+    // lets the GPU finish with the resources of the last frames before they are freed
+    fn drop(&mut self) {
+        if let Some(ref mut previous_frame) = self.previous_frame {
+            previous_frame.cleanup_finished();
+        }
+        if let Err(e) = self.device.wait() {
+            println!("failed to wait for the device: {:?}", e);
+        }
+    }
+}
+
+impl Renderer for VulkanRenderer {
+    fn upload_mesh(&mut self, model: &Md5Mesh) -> Result<(), RendererError> {
+        self.model = Some(model.clone());
+
+        let (s, n, idx) = prepare_full_mesh(model);
+        let tangents = prepare_full_tangents(model, &s, &n);
+        let vertices = skinned_to_vulkano(&s, &n, &tangents, &prepare_full_tex_coords(model), &prepare_full_influences(model));
+
+        let vertex_buffer = check(vulkano::buffer::cpu_access::CpuAccessibleBuffer
+                                      ::from_iter(self.device.clone(), vulkano::buffer::BufferUsage::all(), vertices.iter().cloned()),
+                                  RendererError::Resource)?;
+
+        let index_buffer = check(vulkano::buffer::cpu_access::CpuAccessibleBuffer
+                                     ::from_iter(self.device.clone(), vulkano::buffer::BufferUsage::all(), idx.iter().cloned()),
+                                 RendererError::Resource)?;
+
+        let palette_buffer = match self.upload_palette(&model.joints)? {
+            Some(palette_buffer) => palette_buffer,
+            None => return Ok(())
+        };
+
+        self.mesh = Some(GpuMesh {
+            vertex_buffer: vertex_buffer,
+            index_buffer: index_buffer,
+            palette_buffer: palette_buffer,
+            ranges: prepare_index_ranges(model),
+        });
+        Ok(())
+    }
+
+    fn upload_materials(&mut self, materials: &[Material]) -> Result<(), RendererError> {
+        let mut sets: Vec<MaterialSet> = Vec::with_capacity(materials.len());
+        let mut future = self.take_previous_frame();
+
+        for material in materials {
+            let (set, upload) = match material_set(&self.queue, &self.pipeline, &self.sampler, material, self.srgb) {
+                Ok(r) => r,
+                Err(e) => {
+                    self.previous_frame = Some(future);
+                    return Err(e);
+                }
+            };
+            sets.push(set);
+            future = Box::new(future.join(upload)) as Box<GpuFuture>;
+        }
+
+        self.materials = sets;
+        self.previous_frame = Some(future);
+        Ok(())
+    }
+
+    fn update_pose(&mut self, skeleton: &[Joint]) -> Result<(), RendererError> {
+        if let Some(palette_buffer) = self.upload_palette(skeleton)? {
+            if let Some(ref mut mesh) = self.mesh {
+                mesh.palette_buffer = palette_buffer;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_instances(&mut self, instances: Option<&[InstancePose]>) -> Result<(), RendererError> {
+        let (instances, model) = match (instances, &self.model) {
+            (None, _) => {
+                self.instances = None;
+                return Ok(());
+            },
+            (Some(instances), &Some(ref model)) if !instances.is_empty() => (instances, model),
+            _ => {
+                self.instances = Some(None);
+                return Ok(());
+            }
+        };
+
+        let joints = model.joints.len();
+        let palette = instances.iter()
+            .flat_map(|instance| palette_to_vulkano(&joint_palette(&model.joints, &instance.skeleton)))
+            .collect::<Vec<_>>();
+        let data = instances.iter().enumerate()
+            .map(|(i, instance)| instance_to_vulkano(&instance.transform, (i * joints) as u32))
+            .collect::<Vec<_>>();
+
+        let palette_buffer = check(vulkano::buffer::cpu_access::CpuAccessibleBuffer
+                                       ::from_iter(self.device.clone(), vulkano::buffer::BufferUsage::all(), palette.into_iter()),
+                                   RendererError::Resource)?;
+        let instance_buffer = check(vulkano::buffer::cpu_access::CpuAccessibleBuffer
+                                        ::from_iter(self.device.clone(), vulkano::buffer::BufferUsage::all(), data.into_iter()),
+                                    RendererError::Resource)?;
+
+        self.instances = Some(Some(GpuInstances { instance_buffer: instance_buffer, palette_buffer: palette_buffer }));
+        Ok(())
+    }
+
+    fn set_model_transform(&mut self, world: Matrix4<f32>) {
+        self.world = world;
+    }
+
+    fn set_camera(&mut self, view: Matrix4<f32>, proj: Matrix4<f32>) {
+        self.view = view;
+        self.proj = proj;
+    }
+
+    fn set_lighting(&mut self, lighting: &Lighting) {
+        self.lighting = lighting.clone();
+    }
+
+    fn set_shadows(&mut self, config: &ShadowConfig) -> Result<(), RendererError> {
+        if config.resolution != self.shadows.resolution {
+            let (shadow_map, shadow_framebuffer) = shadow_target(&self.device, &self.shadow_renderpass, config.resolution)?;
+            self.shadow_map = shadow_map;
+            self.shadow_framebuffer = shadow_framebuffer;
+        }
+        self.shadows = *config;
+        Ok(())
+    }
+
+    fn set_ground(&mut self, ground: Option<GroundPlane>) -> Result<(), RendererError> {
+        let plane = match ground {
+            Some(plane) => plane,
+            None => {
+                self.ground = None;
+                return Ok(());
+            }
+        };
+
+        let mesh = plane.mesh();
+        let influences = vec![Influences { joints: [0; MAX_INFLUENCES], weights: [0.0; MAX_INFLUENCES] }; mesh.positions.len()];
+        let vertices = skinned_to_vulkano(&mesh.positions, &mesh.normals, &mesh.tangents, &mesh.tex_coords, &influences);
+
+        let vertex_buffer = check(vulkano::buffer::cpu_access::CpuAccessibleBuffer
+                                      ::from_iter(self.device.clone(), vulkano::buffer::BufferUsage::all(), vertices.iter().cloned()),
+                                  RendererError::Resource)?;
+
+        let index_buffer = check(vulkano::buffer::cpu_access::CpuAccessibleBuffer
+                                     ::from_iter(self.device.clone(), vulkano::buffer::BufferUsage::all(), mesh.indices.iter().cloned()),
+                                 RendererError::Resource)?;
+
+        let (material, upload) = material_set(&self.queue, &self.pipeline, &self.sampler, &plane.material(), self.srgb)?;
+        let future = self.take_previous_frame();
+        self.previous_frame = Some(Box::new(future.join(upload)) as Box<GpuFuture>);
+
+        self.ground = Some((plane, GpuGround {
+            vertex_buffer: vertex_buffer,
+            index_buffer: index_buffer,
+            material: material,
+        }));
+        Ok(())
+    }
+
+    fn set_debug_lines(&mut self, lines: &[LinePoint]) -> Result<(), RendererError> {
+        if lines.is_empty() {
+            self.debug_lines = None;
+            return Ok(());
+        }
+
+        self.debug_lines = Some(check(vulkano::buffer::cpu_access::CpuAccessibleBuffer
+                                          ::from_iter(self.device.clone(), vulkano::buffer::BufferUsage::all(), lines_to_vulkano(lines).into_iter()),
+                                      RendererError::Resource)?);
+        Ok(())
+    }
+
+    fn set_render_mode(&mut self, mode: RenderMode) {
+        self.mode = mode;
+    }
+
+    fn draw_frame(&mut self) -> Result<(), RendererError> {
+        if let Some(ref mut previous_frame) = self.previous_frame {
+            previous_frame.cleanup_finished();
+        }
+
+        if self.recreate_swapchain && !self.rebuild_swapchain()? {
+            return Ok(());
+        }
+
+        if self.framebuffers.is_none() {
+            self.framebuffers = Some(self.create_framebuffers()?);
+        }
+
+        let (image_num, acquire_future) = match vulkano::swapchain::acquire_next_image(self.swapchain.clone(),
+                                                                                       None) {
+            Ok(r) => r,
+            Err(vulkano::swapchain::AcquireError::OutOfDate) => {
+                self.recreate_swapchain = true;
+                return Ok(());
+            },
+            Err(vulkano::swapchain::AcquireError::DeviceLost) => return Err(RendererError::DeviceLost),
+            Err(err) => return Err(RendererError::Swapchain(format!("{:?}", err)))
+        };
+
+        let framebuffer = match self.framebuffers {
+            Some(ref framebuffers) => framebuffers[image_num].clone(),
+            None => return Ok(())
+        };
+        let command_buffer = check(self.record_frame(framebuffer)?.build(), RendererError::Frame)?;
+
+        let previous_frame = self.take_previous_frame();
+        let future = match previous_frame.join(acquire_future).then_execute(self.queue.clone(), command_buffer) {
+            Ok(future) => future
+                .then_swapchain_present(self.queue.clone(), self.swapchain.clone(), image_num)
+                .then_signal_fence_and_flush(),
+            Err(e) => {
+                self.previous_frame = Some(Box::new(vulkano::sync::now(self.device.clone())) as Box<_>);
+                return Err(RendererError::Frame(format!("{:?}", e)));
+            }
+        };
+
+        match future {
+            Ok(future) => {
+                self.previous_frame = Some(Box::new(future) as Box<_>);
+                Ok(())
+            }
+            Err(vulkano::sync::FlushError::OutOfDate) => {
+                self.recreate_swapchain = true;
+                self.previous_frame = Some(Box::new(vulkano::sync::now(self.device.clone())) as Box<_>);
+                Ok(())
+            }
+            Err(vulkano::sync::FlushError::DeviceLost) => Err(RendererError::DeviceLost),
+            Err(e) => {
+                self.previous_frame = Some(Box::new(vulkano::sync::now(self.device.clone())) as Box<_>);
+                Err(RendererError::Frame(format!("{:?}", e)))
+            }
+        }
+    }
+
+    // Draws the frame again into an offscreen image, the swapchain images being
+    // owned by the presentation engine once presented
+    fn read_pixels(&mut self) -> Result<Vec<u8>, RendererError> {
+        let dimensions = self.dimensions;
+        let format = self.swapchain.format();
+        let usage = vulkano::image::ImageUsage {
+            transfer_source: true,
+            color_attachment: true,
+            .. vulkano::image::ImageUsage::none()
+        };
+
+        let target = check(vulkano::image::attachment::AttachmentImage::with_usage(self.device.clone(), dimensions, format, usage),
+                           RendererError::Resource)?;
+        let framebuffer = self.framebuffer(target.clone())?;
+        let buffer = check(vulkano::buffer::cpu_access::CpuAccessibleBuffer
+                               ::from_iter(self.device.clone(), vulkano::buffer::BufferUsage::all(),
+                                           (0 .. dimensions[0] * dimensions[1] * 4).map(|_| 0u8)),
+                           RendererError::Resource)?;
+
+        let builder = self.record_frame(framebuffer)?;
+        let command_buffer = check(check(builder.copy_image_to_buffer(target.clone(), buffer.clone()), RendererError::Frame)?.build(),
+                                   RendererError::Frame)?;
+
+        let previous_frame = self.take_previous_frame();
+        self.previous_frame = Some(Box::new(vulkano::sync::now(self.device.clone())) as Box<GpuFuture>);
+        let future = check(previous_frame.then_execute(self.queue.clone(), command_buffer), RendererError::Frame)?;
+        match future.then_signal_fence_and_flush().and_then(|f| f.wait(None)) {
+            Ok(()) => (),
+            Err(vulkano::sync::FlushError::DeviceLost) => return Err(RendererError::DeviceLost),
+            Err(e) => return Err(RendererError::Frame(format!("{:?}", e)))
+        }
+
+        let mut pixels = check(buffer.read(), RendererError::Resource)?.to_vec();
+        if is_bgra(format) {
+            for p in pixels.chunks_mut(4) {
+                p.swap(0, 2);
+            }
+        }
+        Ok(pixels)
+    }
+
+    fn resize(&mut self, _dimensions: [u32; 2]) {
+        // the swapchain extent comes from the surface capabilities
+        self.recreate_swapchain = true;
+    }
+
+    fn dimensions(&self) -> [u32; 2] {
+        self.dimensions
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.surface.window().set_title(title);
+    }
+}
+
+// Uploads the textures of `material` and binds them as set 1 of `pipeline`, the
+// color textures as sRGB with `srgb`. The returned future must complete before
+// drawing with the set.
+fn material_set(queue: &Arc<vulkano::device::Queue>, pipeline: &Arc<vulkano::pipeline::GraphicsPipelineAbstract + Send + Sync>,
+                sampler: &Arc<vulkano::sampler::Sampler>, material: &Material, srgb: bool) -> Result<(MaterialSet, Box<GpuFuture>), RendererError> {
+    let upload = |texture: &Texture, format: vulkano::format::Format| {
+        check(vulkano::image::immutable::ImmutableImage::from_iter(
+            texture.data.iter().cloned(),
+            vulkano::image::Dimensions::Dim2d { width: texture.width as u32, height: texture.height as u32 },
+            format,
+            queue.clone()), RendererError::Resource)
+    };
+    let color_format = if srgb { vulkano::format::Format::R8G8B8A8Srgb } else { vulkano::format::Format::R8G8B8A8Unorm };
+
+    let (diffuse, diffuse_future) = upload(&material.diffuse, color_format)?;
+    let (specular, specular_future) = upload(&material.specular, color_format)?;
+    let (normal, normal_future) = upload(&material.normal, vulkano::format::Format::R8G8B8A8Unorm)?;
+
+    let set = vulkano::descriptor::descriptor_set::PersistentDescriptorSet::start(pipeline.clone(), 1);
+    let set = check(set.add_sampled_image(diffuse, sampler.clone()), RendererError::Resource)?;
+    let set = check(set.add_sampled_image(specular, sampler.clone()), RendererError::Resource)?;
+    let set = check(set.add_sampled_image(normal, sampler.clone()), RendererError::Resource)?;
+    let set = Arc::new(check(set.build(), RendererError::Resource)?);
+
+    Ok((set, Box::new(diffuse_future.join(specular_future).join(normal_future)) as Box<GpuFuture>))
+}
+
+// Single subpass drawing to the swapchain image, through a multisampled image
+// resolved at the end of the pass when `samples` > 1
+fn main_renderpass(device: &Arc<vulkano::device::Device>, color_format: vulkano::format::Format, depth_format: vulkano::format::Format,
+                   samples: u32) -> Result<Arc<vulkano::framebuffer::RenderPassAbstract + Send + Sync>, RendererError> {
+    if samples > 1 {
+        Ok(Arc::new(check(
+            single_pass_renderpass!(device.clone(),
+                attachments: {
+                    color: {
+                        load: Clear,
+                        store: DontCare,
+                        format: color_format,
+                        samples: samples,
+                    },
+                    depth: {
+                        load: Clear,
+                        store: DontCare,
+                        format: depth_format,
+                        samples: samples,
+                    },
+                    resolved: {
+                        load: DontCare,
+                        store: Store,
+                        format: color_format,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {depth},
+                    resolve: [resolved],
+                }
+            ), RendererError::Pipeline)?
+        ))
+    } else {
+        Ok(Arc::new(check(
+            single_pass_renderpass!(device.clone(),
+                attachments: {
+                    color: {
+                        load: Clear,
+                        store: Store,
+                        format: color_format,
+                        samples: 1,
+                    },
+                    depth: {
+                        load: Clear,
+                        store: DontCare,
+                        format: depth_format,
+                        samples: 1,
+                    }
+                },
+                pass: {
+                    color: [color],
+                    depth_stencil: {depth}
+                }
+            ), RendererError::Pipeline)?
+        ))
+    }
+}
+
+fn attachments(device: &Arc<vulkano::device::Device>, dimensions: [u32; 2], color_format: vulkano::format::Format,
+               depth_format: vulkano::format::Format, samples: u32) -> Result<Attachments, RendererError> {
+    if samples > 1 {
+        Ok((check(vulkano::image::attachment::AttachmentImage::transient_multisampled(device.clone(), dimensions, samples, depth_format),
+                  RendererError::Resource)?,
+            Some(check(vulkano::image::attachment::AttachmentImage::transient_multisampled(device.clone(), dimensions, samples, color_format),
+                       RendererError::Resource)?)))
+    } else {
+        Ok((check(vulkano::image::attachment::AttachmentImage::transient(device.clone(), dimensions, depth_format), RendererError::Resource)?,
+            None))
+    }
+}
+
+fn device_type(ty: vulkano::instance::PhysicalDeviceType) -> DeviceType {
+    match ty {
+        vulkano::instance::PhysicalDeviceType::DiscreteGpu => DeviceType::Discrete,
+        vulkano::instance::PhysicalDeviceType::IntegratedGpu => DeviceType::Integrated,
+        vulkano::instance::PhysicalDeviceType::VirtualGpu => DeviceType::Virtual,
+        vulkano::instance::PhysicalDeviceType::Cpu => DeviceType::Cpu,
+        vulkano::instance::PhysicalDeviceType::Other => DeviceType::Other,
+    }
+}
+
+fn present_mode(mode: PresentMode) -> vulkano::swapchain::PresentMode {
+    match mode {
+        PresentMode::Vsync => vulkano::swapchain::PresentMode::Fifo,
+        PresentMode::Immediate => vulkano::swapchain::PresentMode::Immediate,
+        PresentMode::Mailbox => vulkano::swapchain::PresentMode::Mailbox,
+    }
+}
+
+fn depth_format(format: DepthFormat) -> vulkano::format::Format {
+    match format {
+        DepthFormat::D16 => vulkano::format::Format::D16Unorm,
+        DepthFormat::D24S8 => vulkano::format::Format::D24Unorm_S8Uint,
+        DepthFormat::D32 => vulkano::format::Format::D32Sfloat,
+        DepthFormat::D32S8 => vulkano::format::Format::D32Sfloat_S8Uint,
+    }
+}
+
+fn is_bgra(format: vulkano::format::Format) -> bool {
+    match format {
+        vulkano::format::Format::B8G8R8A8Unorm | vulkano::format::Format::B8G8R8A8Srgb => true,
+        _ => false
+    }
+}
+
+fn is_srgb(format: vulkano::format::Format) -> bool {
+    match format {
+        vulkano::format::Format::B8G8R8A8Srgb | vulkano::format::Format::R8G8B8A8Srgb |
+        vulkano::format::Format::A8B8G8R8SrgbPack32 => true,
+        _ => false
+    }
+}
+
+// Shadow map of `resolution` texels a side and the framebuffer rendering to it
+fn shadow_target(device: &Arc<vulkano::device::Device>, renderpass: &Arc<vulkano::framebuffer::RenderPassAbstract + Send + Sync>,
+                 resolution: u32) -> Result<(ShadowMap, Arc<vulkano::framebuffer::FramebufferAbstract + Send + Sync>), RendererError> {
+    let image = check(vulkano::image::attachment::AttachmentImage::sampled(device.clone(), [resolution, resolution],
+                                                                           vulkano::format::D16Unorm), RendererError::Resource)?;
+
+    let framebuffer = check(vulkano::framebuffer::Framebuffer::start(renderpass.clone()).add(image.clone()), RendererError::Resource)?;
+    let framebuffer = Arc::new(check(framebuffer.build(), RendererError::Resource)?) as Arc<vulkano::framebuffer::FramebufferAbstract + Send + Sync>;
+
+    Ok((image, framebuffer))
+}
+
+// First subpass of `renderpass`
+fn subpass(renderpass: &Arc<vulkano::framebuffer::RenderPassAbstract + Send + Sync>)
+           -> Result<vulkano::framebuffer::Subpass<Arc<vulkano::framebuffer::RenderPassAbstract + Send + Sync>>, RendererError> {
+    vulkano::framebuffer::Subpass::from(renderpass.clone(), 0).ok_or(RendererError::Pipeline(String::from("render pass without subpass")))
+}
+
+// Turns the error of a vulkano call into a `RendererError` of the given kind
+fn check<T, E: fmt::Debug>(result: Result<T, E>, kind: fn(String) -> RendererError) -> Result<T, RendererError> {
+    result.map_err(|e| kind(format!("{:?}", e)))
+}
+
+// `mode` and `joint` of the shader uniforms
+fn shader_mode(mode: RenderMode) -> (u32, u32) {
+    match mode {
+        RenderMode::Shaded => (0, 0),
+        RenderMode::Wireframe => (1, 0),
+        RenderMode::UvChecker => (2, 0),
+        RenderMode::WeightHeatmap(joint) => (3, joint),
+    }
+}
+
+mod vs {
+    #[derive(VulkanoShader)]
+    #[ty = "vertex"]
+    #[src = "
+#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec4 tangent;
+layout(location = 3) in vec2 tex_coords;
+layout(location = 4) in uvec4 joint_indices;
+layout(location = 5) in vec4 joint_weights;
+
+// per instance, see `convert::InstanceData`
+layout(location = 6) in vec4 instance_world_0;
+layout(location = 7) in vec4 instance_world_1;
+layout(location = 8) in vec4 instance_world_2;
+layout(location = 9) in vec4 instance_world_3;
+layout(location = 10) in uint palette_offset;
+
+layout(location = 0) out vec3 v_normal;
+layout(location = 1) out vec2 v_tex_coords;
+layout(location = 2) out float v_weight;
+layout(location = 3) flat out uint v_mode;
+layout(location = 4) out vec3 v_position;
+layout(location = 5) out vec4 v_tangent;
+layout(location = 6) out vec4 v_shadow_coords;
+
+layout(set = 0, binding = 0) uniform Data {
+    mat4 world;
+    mat4 view;
+    mat4 proj;
+    // world to light clip space, see `shadow::light_matrix`
+    mat4 shadow;
+    uint mode;
+    uint joint;
+} uniforms;
+
+layout(set = 0, binding = 1) readonly buffer Palette {
+    mat4 joints[];
+} palette;
+
+void main() {
+    uvec4 joints = joint_indices + palette_offset;
+    mat4 skin = joint_weights.x * palette.joints[joints.x]
+              + joint_weights.y * palette.joints[joints.y]
+              + joint_weights.z * palette.joints[joints.z]
+              + joint_weights.w * palette.joints[joints.w];
+    mat4 world = uniforms.world * mat4(instance_world_0, instance_world_1, instance_world_2, instance_world_3);
+
+    // vertices without influence, like the ground, are not skinned
+    if (joint_weights == vec4(0.0)) {
+        skin = mat4(1.0);
+    }
+
+    v_weight = dot(joint_weights, vec4(equal(joint_indices, uvec4(uniforms.joint))));
+    v_tex_coords = tex_coords;
+    v_mode = uniforms.mode;
+
+    mat4 worldview = uniforms.view * world;
+    vec4 view_position = worldview * skin * vec4(position, 1.0);
+
+    v_position = view_position.xyz;
+    v_normal = transpose(inverse(mat3(worldview))) * mat3(skin) * normal;
+    v_tangent = vec4(mat3(worldview) * mat3(skin) * tangent.xyz, tangent.w);
+    v_shadow_coords = uniforms.shadow * world * skin * vec4(position, 1.0);
+    gl_Position = uniforms.proj * view_position;
+}
+"]
+    struct Dummy;
+}
+
+mod fs {
+    #[derive(VulkanoShader)]
+    #[ty = "fragment"]
+    #[src = "
+#version 450
+
+layout(location = 0) in vec3 v_normal;
+layout(location = 1) in vec2 v_tex_coords;
+layout(location = 2) in float v_weight;
+layout(location = 3) flat in uint v_mode;
+layout(location = 4) in vec3 v_position;
+layout(location = 5) in vec4 v_tangent;
+layout(location = 6) in vec4 v_shadow_coords;
+layout(location = 0) out vec4 f_color;
+
+// in view space, see `lighting::ShaderLight`
+layout(set = 0, binding = 2) uniform Lights {
+    vec4 ambient;
+    vec4 vectors[8];
+    vec4 colors[8];
+    uint count;
+    float shininess;
+    // index of the light casting shadows, -1 for none, see `shadow::ShadowConfig`
+    int shadow_light;
+    float shadow_bias;
+    int shadow_pcf;
+} lights;
+
+layout(set = 0, binding = 3) uniform sampler2D shadow_map;
+
+layout(set = 1, binding = 0) uniform sampler2D diffuse_map;
+layout(set = 1, binding = 1) uniform sampler2D specular_map;
+layout(set = 1, binding = 2) uniform sampler2D normal_map;
+
+const uint MODE_SHADED = 0;
+const uint MODE_WIREFRAME = 1;
+const uint MODE_UV_CHECKER = 2;
+const uint MODE_WEIGHT_HEATMAP = 3;
+
+// Same filter as `shadow::pcf`
+float shadow_factor(vec4 coords) {
+    vec3 c = coords.xyz / coords.w;
+    if (lights.shadow_light < 0 || any(greaterThan(abs(c.xy), vec2(1.0))) || c.z > 1.0) {
+        return 1.0;
+    }
+
+    ivec2 size = textureSize(shadow_map, 0);
+    ivec2 texel = ivec2(floor((c.xy * 0.5 + 0.5) * vec2(size)));
+    int r = lights.shadow_pcf;
+
+    float lit = 0.0;
+    for (int dy = -r; dy <= r; dy++) {
+        for (int dx = -r; dx <= r; dx++) {
+            ivec2 s = clamp(texel + ivec2(dx, dy), ivec2(0), size - 1);
+            if (c.z - lights.shadow_bias <= texelFetch(shadow_map, s, 0).r) {
+                lit += 1.0;
+            }
+        }
+    }
+
+    return lit / float((2 * r + 1) * (2 * r + 1));
+}
+
+vec3 blinn_phong(vec3 albedo, vec3 specular, vec3 position, vec3 n, float visibility) {
+    vec3 v = normalize(-position);
+    vec3 res = albedo * lights.ambient.rgb;
+
+    for (uint i = 0; i < lights.count; i++) {
+        vec3 l = lights.vectors[i].xyz;
+        float attenuation = 1.0;
+
+        if (lights.vectors[i].w != 0.0) {
+            vec3 to_light = lights.vectors[i].xyz - position;
+            float falloff = clamp(1.0 - length(to_light) / lights.colors[i].w, 0.0, 1.0);
+            l = normalize(to_light);
+            attenuation = falloff * falloff;
+        }
+
+        if (int(i) == lights.shadow_light) {
+            attenuation *= visibility;
+        }
+
+        float diffuse = dot(n, l);
+        if (diffuse <= 0.0) {
+            continue;
+        }
+
+        vec3 h = normalize(l + v);
+        float highlight = pow(max(dot(n, h), 0.0), lights.shininess);
+        res += (albedo * diffuse + specular * highlight) * lights.colors[i].rgb * attenuation;
+    }
+
+    return res;
+}
+
+void main() {
+    vec3 n = normalize(v_normal);
+
+    if (v_mode == MODE_SHADED) {
+        // tangent space normal from the `_local` map
+        vec3 t = normalize(v_tangent.xyz - n * dot(n, v_tangent.xyz));
+        vec3 b = cross(n, t) * v_tangent.w;
+        vec3 m = texture(normal_map, v_tex_coords).xyz * 2.0 - 1.0;
+        n = normalize(t * m.x + b * m.y + n * m.z);
+
+        vec3 albedo = texture(diffuse_map, v_tex_coords).rgb;
+        vec3 specular = texture(specular_map, v_tex_coords).rgb;
+        f_color = vec4(blinn_phong(albedo, specular, v_position, n, shadow_factor(v_shadow_coords)), 1.0);
+        return;
+    }
+
+    // debug views are lit from the eye
+    float brightness = n.z;
+    vec3 regular_color = vec3(1.0);
+
+    if (v_mode == MODE_WIREFRAME) {
+        f_color = vec4(1.0);
+        return;
+    } else if (v_mode == MODE_UV_CHECKER) {
+        vec2 cell = floor(v_tex_coords * 8.0);
+        regular_color = mod(cell.x + cell.y, 2.0) == 0.0 ? vec3(0.9) : vec3(0.2);
+    } else if (v_mode == MODE_WEIGHT_HEATMAP) {
+        float w = clamp(v_weight, 0.0, 1.0) * 2.0 - 1.0;
+        regular_color = vec3(max(w, 0.0), 1.0 - abs(w), max(-w, 0.0));
+    }
+    vec3 dark_color = regular_color * 0.6;
+
+    f_color = vec4(mix(dark_color, regular_color, brightness), 1.0);
+}
+"]
+    struct Dummy;
+}
+
+mod shadow_vs {
+    #[derive(VulkanoShader)]
+    #[ty = "vertex"]
+    #[src = "
+#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 4) in uvec4 joint_indices;
+layout(location = 5) in vec4 joint_weights;
+
+// per instance, see `convert::InstanceData`
+layout(location = 6) in vec4 instance_world_0;
+layout(location = 7) in vec4 instance_world_1;
+layout(location = 8) in vec4 instance_world_2;
+layout(location = 9) in vec4 instance_world_3;
+layout(location = 10) in uint palette_offset;
+
+layout(set = 0, binding = 0) uniform Data {
+    mat4 world;
+    mat4 light;
+} uniforms;
+
+layout(set = 0, binding = 1) readonly buffer Palette {
+    mat4 joints[];
+} palette;
+
+void main() {
+    uvec4 joints = joint_indices + palette_offset;
+    mat4 skin = joint_weights.x * palette.joints[joints.x]
+              + joint_weights.y * palette.joints[joints.y]
+              + joint_weights.z * palette.joints[joints.z]
+              + joint_weights.w * palette.joints[joints.w];
+    mat4 world = uniforms.world * mat4(instance_world_0, instance_world_1, instance_world_2, instance_world_3);
+
+    gl_Position = uniforms.light * world * skin * vec4(position, 1.0);
+}
+"]
+    struct Dummy;
+}
+
+mod shadow_fs {
+    #[derive(VulkanoShader)]
+    #[ty = "fragment"]
+    #[src = "
+#version 450
+
+void main() {
+}
+"]
+    struct Dummy;
+}
+
+mod debug_vs {
+    #[derive(VulkanoShader)]
+    #[ty = "vertex"]
+    #[src = "
+#version 450
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 color;
+
+layout(location = 0) out vec3 v_color;
+
+layout(set = 0, binding = 0) uniform Data {
+    mat4 world;
+    mat4 view;
+    mat4 proj;
+} uniforms;
+
+void main() {
+    v_color = color;
+    gl_Position = uniforms.proj * uniforms.view * uniforms.world * vec4(position, 1.0);
+}
+"]
+    struct Dummy;
+}
+
+mod debug_fs {
+    #[derive(VulkanoShader)]
+    #[ty = "fragment"]
+    #[src = "
+#version 450
+
+layout(location = 0) in vec3 v_color;
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    f_color = vec4(v_color, 1.0);
+}
+"]
+    struct Dummy;
+}
@@ -1,8 +1,10 @@
  use md5::md5mesh::*;
- use cgmath::{Vector3, InnerSpace};
+ use cgmath::{Vector2, Vector3, Vector4, InnerSpace};
+ use std::f32;
+ use std::ops::Range;
  use vertex_computation::convert::generate_indices;
 
- pub fn prepare_mesh(m: &Mesh, v_joints: &Vec<Joint>) -> Vec<Vector3<f32>> {
+ pub fn prepare_mesh(m: &Mesh, v_joints: &[Joint]) -> Vec<Vector3<f32>> {
      let mut position_buffer : Vec<Vector3<f32>> = Vec::new();
 
      for vertice in &m.vertices {
@@ -56,13 +58,20 @@
  }
 
  pub fn prepare_full_mesh(ms: &Md5Mesh) -> (Vec<Vector3<f32>>, Vec<Vector3<f32>>, Vec<u16>) {
+     for m in &ms.meshes {
+         println!("{:?} : {:?}", m.shader, m.vertices.len());
+     }
+     prepare_skinned_mesh(ms, &ms.joints)
+ }
+
+ // Same as `prepare_full_mesh` but skinned against `v_joints` instead of the bind pose
+ pub fn prepare_skinned_mesh(ms: &Md5Mesh, v_joints: &[Joint]) -> (Vec<Vector3<f32>>, Vec<Vector3<f32>>, Vec<u16>) {
      let mut res_v : Vec<Vector3<f32>> = Vec::new();
      let mut res_n : Vec<Vector3<f32>> = Vec::new();
      let mut res_i : Vec<u16> = Vec::new();
 
     for m in &ms.meshes {
-        println!("{:?} : {:?}", m.shader, m.vertices.len());
-        let mut tmp = prepare_mesh(m, &ms.joints);
+        let mut tmp = prepare_mesh(m, v_joints);
         let mut tmp_normals = prepare_normals(m, &tmp);
         let mut tmp_i = generate_indices(&m);
 
@@ -75,4 +84,131 @@
         res_i.append(&mut tmp_i);
     }
     (res_v, res_n, res_i)
- }
\ No newline at end of file
+ }
+
+ // Part of the `prepare_full_mesh` indices drawing each mesh
+ pub fn prepare_index_ranges(ms: &Md5Mesh) -> Vec<Range<usize>> {
+     let mut res: Vec<Range<usize>> = Vec::new();
+     let mut start = 0;
+
+     for m in &ms.meshes {
+         let end = start + m.triangles.len() * 3;
+         res.push(start..end);
+         start = end;
+     }
+     res
+ }
+
+ // Axis-aligned bounding box of a set of positions, as (min, max)
+ pub fn compute_bounds(positions: &[Vector3<f32>]) -> (Vector3<f32>, Vector3<f32>) {
+     let mut min = Vector3::new(f32::MAX, f32::MAX, f32::MAX);
+     let mut max = Vector3::new(f32::MIN, f32::MIN, f32::MIN);
+
+     for p in positions {
+         min = Vector3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+         max = Vector3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+     }
+
+     (min, max)
+ }
+
+ pub fn prepare_tex_coords(m: &Mesh) -> Vec<Vector2<f32>> {
+     m.vertices.iter().map(|v| v.tex_coords).collect()
+ }
+
+ // Concatenation of `prepare_tex_coords` over every mesh, in the order of `prepare_full_mesh`
+ pub fn prepare_full_tex_coords(ms: &Md5Mesh) -> Vec<Vector2<f32>> {
+     let mut res: Vec<Vector2<f32>> = Vec::new();
+
+     for m in &ms.meshes {
+         res.append(&mut prepare_tex_coords(m));
+     }
+     res
+ }
+
+ // Per-vertex tangent following the U direction, orthogonalized against the normal.
+ // W holds the handedness of the (tangent, bitangent, normal) basis.
+ pub fn prepare_tangents(m: &Mesh, vertices_position: &[Vector3<f32>], normals: &[Vector3<f32>], tex_coords: &[Vector2<f32>]) -> Vec<Vector4<f32>> {
+     let mut tangents: Vec<Vector3<f32>> = vec![ Vector3::new(0., 0., 0.); vertices_position.len() ];
+     let mut bitangents: Vec<Vector3<f32>> = vec![ Vector3::new(0., 0., 0.); vertices_position.len() ];
+
+     for t in &m.triangles {
+         let (i0, i1, i2) = (t.vertex_indices.0 as usize, t.vertex_indices.1 as usize, t.vertex_indices.2 as usize);
+
+         let e1 = vertices_position[ i1 ] - vertices_position[ i0 ];
+         let e2 = vertices_position[ i2 ] - vertices_position[ i0 ];
+         let d1 = tex_coords[ i1 ] - tex_coords[ i0 ];
+         let d2 = tex_coords[ i2 ] - tex_coords[ i0 ];
+
+         let det = d1.x * d2.y - d2.x * d1.y;
+         if det.abs() < 1e-12 {
+             continue;
+         }
+
+         let tangent = (e1 * d2.y - e2 * d1.y) / det;
+         let bitangent = (e2 * d1.x - e1 * d2.x) / det;
+
+         for &i in &[i0, i1, i2] {
+             tangents[ i ] += tangent;
+             bitangents[ i ] += bitangent;
+         }
+     }
+
+     let mut res: Vec<Vector4<f32>> = Vec::with_capacity(tangents.len());
+
+     for i in 0..tangents.len() {
+         let n = normals[ i ];
+         let mut t = tangents[ i ] - n * n.dot(tangents[ i ]);
+
+         // no usable UV gradient, any direction perpendicular to the normal will do
+         if t.magnitude2() < 1e-12 {
+             let axis = if n.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+             t = axis - n * n.dot(axis);
+         }
+
+         let t = t.normalize();
+         let w = if n.cross(t).dot(bitangents[ i ]) < 0.0 { -1.0 } else { 1.0 };
+         res.push(t.extend(w));
+     }
+
+     res
+ }
+
+ // `prepare_tangents` over every mesh, from the output of `prepare_skinned_mesh`
+ pub fn prepare_full_tangents(ms: &Md5Mesh, vertices_position: &[Vector3<f32>], normals: &[Vector3<f32>]) -> Vec<Vector4<f32>> {
+     let mut res: Vec<Vector4<f32>> = Vec::new();
+     let mut offset = 0;
+
+     for m in &ms.meshes {
+         let end = offset + m.vertices.len();
+         res.append(&mut prepare_tangents(m, &vertices_position[offset..end], &normals[offset..end], &prepare_tex_coords(m)));
+         offset = end;
+     }
+     res
+ }
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector2, Vector3, Vector4};
+    use md5::md5mesh::{Mesh, Vertex, Triangle};
+
+    #[test]
+    fn prepare_tangents() {
+        let uv = |u: f32, v: f32| Vertex { index: 0, tex_coords: Vector2::new(u, v), start_weight: 0, weight_count: 0 };
+        let m = Mesh {
+            shader: String::from("test"),
+            vertices: vec![uv(0.0, 0.0), uv(1.0, 0.0), uv(0.0, 1.0)],
+            triangles: vec![Triangle { index: 0, vertex_indices: (0, 1, 2) }],
+            weights: vec![],
+        };
+
+        // U runs along -Y, V along X
+        let positions = vec![Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, -2.0, 0.0), Vector3::new(2.0, 0.0, 0.0)];
+        let normals = vec![Vector3::new(0.0, 0.0, 1.0); 3];
+        let tex_coords = super::prepare_tex_coords(&m);
+
+        let tangents = super::prepare_tangents(&m, &positions, &normals, &tex_coords);
+
+        assert_eq!(tangents[0], Vector4::new(0.0, -1.0, 0.0, 1.0));
+    }
+}
@@ -1,6 +1,8 @@
 use vulkano;
-use cgmath::{Vector3, InnerSpace};
+use cgmath::{Vector2, Vector3, Vector4, Matrix4, InnerSpace};
 use md5::md5mesh::{Mesh};
+use vertex_computation::skinning::Influences;
+use renderer::debug::LinePoint;
 
 #[derive(Copy, Clone, Debug)]
 pub struct Vertex {
@@ -49,4 +51,82 @@ pub fn generate_indices(m: &Mesh) -> Vec<u16> {
     }
 
     res
-}
\ No newline at end of file
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct SkinnedVertex {
+    position: (f32, f32, f32),
+    normal: (f32, f32, f32),
+    tangent: (f32, f32, f32, f32),
+    tex_coords: (f32, f32),
+    joint_indices: (u32, u32, u32, u32),
+    joint_weights: (f32, f32, f32, f32)
+}
+
+impl_vertex!(SkinnedVertex, position, normal, tangent, tex_coords, joint_indices, joint_weights);
+
+pub fn skinned_to_vulkano(v_p: &Vec<Vector3<f32>>, v_n: &Vec<Vector3<f32>>, v_tan: &Vec<Vector4<f32>>, v_t: &Vec<Vector2<f32>>, v_i: &Vec<Influences>) -> Vec<SkinnedVertex> {
+    let mut res : Vec<SkinnedVertex> = Vec::new();
+
+    for ((((p, n), tan), t), i) in v_p.iter().zip(v_n.iter()).zip(v_tan.iter()).zip(v_t.iter()).zip(v_i.iter()) {
+        res.push(SkinnedVertex {
+            position: (p.x, p.y, p.z),
+            normal: (n.x, n.y, n.z),
+            tangent: (tan.x, tan.y, tan.z, tan.w),
+            tex_coords: (t.x, t.y),
+            joint_indices: (i.joints[0], i.joints[1], i.joints[2], i.joints[3]),
+            joint_weights: (i.weights[0], i.weights[1], i.weights[2], i.weights[3])
+        });
+    }
+    res
+}
+
+pub fn palette_to_vulkano(palette: &Vec<Matrix4<f32>>) -> Vec<[[f32; 4]; 4]> {
+    palette.iter().map(|m| (*m).into()).collect()
+}
+
+// Per-instance attributes: the columns of the instance transform and the index
+// of the first matrix of its joint palette
+#[derive(Copy, Clone, Debug)]
+pub struct InstanceData {
+    instance_world_0: (f32, f32, f32, f32),
+    instance_world_1: (f32, f32, f32, f32),
+    instance_world_2: (f32, f32, f32, f32),
+    instance_world_3: (f32, f32, f32, f32),
+    palette_offset: u32
+}
+
+impl_vertex!(InstanceData, instance_world_0, instance_world_1, instance_world_2, instance_world_3, palette_offset);
+
+pub fn instance_to_vulkano(transform: &Matrix4<f32>, palette_offset: u32) -> InstanceData {
+    let column = |c: &Vector4<f32>| (c.x, c.y, c.z, c.w);
+
+    InstanceData {
+        instance_world_0: column(&transform.x),
+        instance_world_1: column(&transform.y),
+        instance_world_2: column(&transform.z),
+        instance_world_3: column(&transform.w),
+        palette_offset: palette_offset
+    }
+}
+
+
+#[derive(Copy, Clone, Debug)]
+pub struct DebugVertex {
+    position: (f32, f32, f32),
+    color: (f32, f32, f32)
+}
+
+impl_vertex!(DebugVertex, position, color);
+
+pub fn lines_to_vulkano(v_l: &[LinePoint]) -> Vec<DebugVertex> {
+    let mut res : Vec<DebugVertex> = Vec::new();
+
+    for l in v_l {
+        res.push(DebugVertex {
+            position: (l.position.x, l.position.y, l.position.z),
+            color: (l.color.x, l.color.y, l.color.z)
+        });
+    }
+    res
+}
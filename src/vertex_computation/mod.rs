@@ -1,2 +1,4 @@
 pub mod compute;
-pub mod convert;
\ No newline at end of file
+pub mod convert;
+pub mod skinning;
+pub mod shadow_volume;
\ No newline at end of file
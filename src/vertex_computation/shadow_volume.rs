@@ -0,0 +1,221 @@
+#![allow(dead_code)]
+use cgmath::{Vector3, Vector4, InnerSpace};
+use std::collections::HashMap;
+
+use md5::md5mesh::{Mesh, Joint};
+use renderer::lighting::Light;
+use vertex_computation::compute::prepare_mesh;
+use vertex_computation::convert::generate_indices;
+
+// Edge between two welded vertices, going from `v0` to `v1` in the winding of
+// triangle `t0` and the other way in `t1`. Open edges have no `t1`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Edge {
+    pub v0: u16,
+    pub v1: u16,
+    pub t0: usize,
+    pub t1: Option<usize>,
+}
+
+// Connectivity of the triangles of a mesh. Vertices split along texture seams are
+// welded, otherwise the seams would be taken for open edges and leak light.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Adjacency {
+    // triangles on the welded vertices, in the winding of the mesh
+    pub triangles: Vec<[u16; 3]>,
+    pub edges: Vec<Edge>,
+}
+
+// Shadow volume of a mesh, closed and with the winding of the mesh triangles facing
+// out of the volume, for a z-fail stencil test with an infinite far plane. The first
+// half of `positions` are the mesh vertices (w = 1) and the second half the same
+// vertices pushed away from the light to infinity (w = 0).
+#[derive(Clone, PartialEq, Debug)]
+pub struct ShadowVolume {
+    pub positions: Vec<Vector4<f32>>,
+    pub indices: Vec<u32>,
+    // silhouette edges, from the light facing side
+    pub silhouette: Vec<(u16, u16)>,
+}
+
+// For each vertex, the first vertex at the same position. None past the 65536
+// vertices 16 bit indices can reach.
+pub fn weld_vertices(positions: &[Vector3<f32>]) -> Option<Vec<u16>> {
+    if positions.len() > 1 << 16 {
+        return None;
+    }
+    let mut first: HashMap<(u32, u32, u32), u16> = HashMap::new();
+
+    Some(positions.iter().enumerate().map(|(i, p)| {
+        // adding 0 turns -0 into 0, which have different bits
+        let key = ((p.x + 0.0).to_bits(), (p.y + 0.0).to_bits(), (p.z + 0.0).to_bits());
+        *first.entry(key).or_insert(i as u16)
+    }).collect())
+}
+
+// Pairs up the edges of the triangles of `indices`, welding vertices on `positions`.
+// Edges shared by more than two triangles are kept open past the first pair.
+// None when there are too many vertices to weld, see `weld_vertices`.
+pub fn build_adjacency(positions: &[Vector3<f32>], indices: &[u16]) -> Option<Adjacency> {
+    let welded = weld_vertices(positions)?;
+    let mut triangles = Vec::with_capacity(indices.len() / 3);
+    let mut edges: Vec<Edge> = Vec::new();
+    // edges waiting for the triangle going the other way, by (v0, v1)
+    let mut open: HashMap<(u16, u16), usize> = HashMap::new();
+
+    for (t, tri) in indices.chunks(3).filter(|tri| tri.len() == 3).enumerate() {
+        let tri = [welded[tri[0] as usize], welded[tri[1] as usize], welded[tri[2] as usize]];
+        triangles.push(tri);
+
+        for k in 0..3 {
+            let (a, b) = (tri[k], tri[(k + 1) % 3]);
+            if a == b {
+                continue;
+            }
+
+            match open.remove(&(b, a)) {
+                Some(e) => edges[e].t1 = Some(t),
+                None => {
+                    open.insert((a, b), edges.len());
+                    edges.push(Edge { v0: a, v1: b, t0: t, t1: None });
+                }
+            }
+        }
+    }
+
+    Some(Adjacency { triangles: triangles, edges: edges })
+}
+
+// Adjacency of `m`, welded in the pose of `skeleton`, usually the bind pose
+pub fn mesh_adjacency(m: &Mesh, skeleton: &[Joint]) -> Option<Adjacency> {
+    build_adjacency(&prepare_mesh(m, skeleton), &generate_indices(m))
+}
+
+// Light as a homogeneous position: the position of point lights (w = 1) or the
+// direction towards directional lights (w = 0)
+pub fn light_vector(light: &Light) -> Vector4<f32> {
+    match *light {
+        Light::Directional { direction, .. } => (-direction).normalize().extend(0.0),
+        Light::Point { position, .. } => position.extend(1.0),
+    }
+}
+
+// Whether each triangle faces `light`, with the normals of `prepare_normals`
+pub fn light_facing(positions: &[Vector3<f32>], adjacency: &Adjacency, light: Vector4<f32>) -> Vec<bool> {
+    adjacency.triangles.iter().map(|t| {
+        let (p0, p1, p2) = (positions[t[0] as usize], positions[t[1] as usize], positions[t[2] as usize]);
+        let normal = (p2 - p0).cross(p1 - p0);
+        let to_light = light.truncate() - p0 * light.w;
+
+        normal.dot(to_light) > 0.0
+    }).collect()
+}
+
+// Edges between a triangle facing `light` and one facing away or nothing, oriented
+// as in the light facing triangle
+pub fn silhouette_edges(adjacency: &Adjacency, facing: &[bool]) -> Vec<(u16, u16)> {
+    adjacency.edges.iter().filter_map(|e| {
+        match e.t1 {
+            Some(t1) if facing[e.t0] == facing[t1] => None,
+            Some(_) if !facing[e.t0] => Some((e.v1, e.v0)),
+            None if !facing[e.t0] => None,
+            _ => Some((e.v0, e.v1)),
+        }
+    }).collect()
+}
+
+// Shadow volume of the triangles of `adjacency` at `positions`, `light` being in the
+// same space. The light facing triangles make the front cap, and again at infinity
+// the back cap, joined by the silhouette edges extruded to infinity.
+pub fn shadow_volume(positions: &[Vector3<f32>], adjacency: &Adjacency, light: Vector4<f32>) -> ShadowVolume {
+    let n = positions.len() as u32;
+    let facing = light_facing(positions, adjacency, light);
+    let silhouette = silhouette_edges(adjacency, &facing);
+
+    let mut volume_positions: Vec<Vector4<f32>> = positions.iter().map(|p| p.extend(1.0)).collect();
+    volume_positions.extend(positions.iter().map(|p| (p * light.w - light.truncate()).extend(0.0)));
+
+    let mut indices = Vec::new();
+    for (t, _) in adjacency.triangles.iter().zip(&facing).filter(|&(_, &f)| f) {
+        let (a, b, c) = (t[0] as u32, t[1] as u32, t[2] as u32);
+        indices.extend_from_slice(&[a, b, c]);
+        indices.extend_from_slice(&[c + n, b + n, a + n]);
+    }
+
+    for &(a, b) in &silhouette {
+        let (a, b) = (a as u32, b as u32);
+        indices.extend_from_slice(&[b, a, a + n]);
+        indices.extend_from_slice(&[b, a + n, b + n]);
+    }
+
+    ShadowVolume { positions: volume_positions, indices: indices, silhouette: silhouette }
+}
+
+// Shadow volume of `m` in the pose of `skeleton`, `adjacency` coming from `mesh_adjacency`
+pub fn mesh_shadow_volume(m: &Mesh, skeleton: &[Joint], adjacency: &Adjacency, light: Vector4<f32>) -> ShadowVolume {
+    shadow_volume(&prepare_mesh(m, skeleton), adjacency, light)
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector3, Vector4};
+    use std::collections::HashSet;
+
+    // Tetrahedron standing on the Z = 0 plane, wound like the md5 meshes
+    fn tetrahedron() -> (Vec<Vector3<f32>>, Vec<u16>) {
+        let positions = vec![
+            Vector3::new(1.0, 0.0, 0.0), Vector3::new(-0.5, 0.87, 0.0), Vector3::new(-0.5, -0.87, 0.0),
+            Vector3::new(0.0, 0.0, 1.0),
+        ];
+        (positions, vec![0, 1, 2, 1, 0, 3, 2, 1, 3, 0, 2, 3])
+    }
+
+    #[test]
+    fn build_adjacency() {
+        let (positions, indices) = tetrahedron();
+        let adjacency = super::build_adjacency(&positions, &indices).unwrap();
+
+        assert_eq!(adjacency.edges.len(), 6);
+        assert!(adjacency.edges.iter().all(|e| e.t1.is_some()));
+
+        // a copy of the apex, as at a texture seam, is welded
+        let mut seam = positions.clone();
+        seam.push(seam[3]);
+        let adjacency = super::build_adjacency(&seam, &[0, 1, 2, 1, 0, 3, 2, 1, 4, 0, 2, 3]).unwrap();
+
+        assert!(adjacency.edges.iter().all(|e| e.t1.is_some()));
+    }
+
+    #[test]
+    fn weld_vertices() {
+        let positions = vec![Vector3::new(0.0, 1.0, 0.0), Vector3::new(-0.0, 1.0, -0.0), Vector3::new(0.0, 2.0, 0.0)];
+        assert_eq!(super::weld_vertices(&positions), Some(vec![0, 0, 2]));
+
+        assert_eq!(super::weld_vertices(&vec![Vector3::new(0.0, 0.0, 0.0); (1 << 16) + 1]), None);
+    }
+
+    #[test]
+    fn shadow_volume() {
+        let (positions, indices) = tetrahedron();
+        let adjacency = super::build_adjacency(&positions, &indices).unwrap();
+
+        // from above, the sides face the light and the base outlines the shadow
+        let volume = super::shadow_volume(&positions, &adjacency, Vector4::new(0.0, 0.0, 1.0, 0.0));
+        let base = volume.silhouette.iter().map(|&(a, b)| if a < b { (a, b) } else { (b, a) }).collect::<HashSet<_>>();
+
+        assert_eq!(base, [(0, 1), (1, 2), (0, 2)].iter().cloned().collect());
+        assert_eq!(volume.positions[4], Vector4::new(0.0, 0.0, -1.0, 0.0));
+
+        // closed: every edge is crossed once each way, with a point light too
+        for light in &[Vector4::new(0.0, 0.0, 1.0, 0.0), Vector4::new(3.0, 1.0, 0.5, 1.0)] {
+            let volume = super::shadow_volume(&positions, &adjacency, *light);
+            let edges = volume.indices.chunks(3)
+                .flat_map(|t| vec![(t[0], t[1]), (t[1], t[2]), (t[2], t[0])])
+                .collect::<Vec<_>>();
+            let set = edges.iter().cloned().collect::<HashSet<_>>();
+
+            assert_eq!(set.len(), edges.len());
+            assert!(edges.iter().all(|&(a, b)| set.contains(&(b, a))));
+        }
+    }
+}
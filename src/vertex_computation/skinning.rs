@@ -0,0 +1,186 @@
+ use std::cmp::Ordering;
+ use md5::md5mesh::*;
+ use cgmath::{Vector3, Vector4, Matrix3, Matrix4, SquareMatrix, Zero};
+
+ // Influences kept per vertex for GPU skinning
+ pub const MAX_INFLUENCES: usize = 4;
+
+ #[derive(Copy, Clone, PartialEq, Debug)]
+ pub struct Influences {
+     pub joints: [u32; MAX_INFLUENCES],
+     pub weights: [f32; MAX_INFLUENCES],
+ }
+
+ // Keeps the `MAX_INFLUENCES` heaviest weights of each vertex, renormalized so they sum to 1
+ pub fn prepare_influences(m: &Mesh) -> Vec<Influences> {
+     let mut res: Vec<Influences> = Vec::new();
+
+     for vertice in &m.vertices {
+         let start = vertice.start_weight as usize;
+         // a malformed mesh may have NaN or infinite biases, which are left out
+         let mut weights = m.weights[start..start + vertice.weight_count as usize].iter()
+             .filter(|w| w.bias.is_finite())
+             .collect::<Vec<_>>();
+         weights.sort_by(|a, b| b.bias.partial_cmp(&a.bias).unwrap_or(Ordering::Equal));
+         weights.truncate(MAX_INFLUENCES);
+
+         let total: f32 = weights.iter().map(|w| w.bias).sum();
+         let mut influences = Influences { joints: [0; MAX_INFLUENCES], weights: [0.0; MAX_INFLUENCES] };
+
+         for (i, w) in weights.iter().enumerate() {
+             influences.joints[i] = w.joint_index;
+             influences.weights[i] = if total > 0.0 { w.bias / total } else { 0.0 };
+         }
+
+         res.push(influences);
+     }
+
+     res
+ }
+
+ // Concatenation of `prepare_influences` over every mesh, in the order of `prepare_full_mesh`
+ pub fn prepare_full_influences(ms: &Md5Mesh) -> Vec<Influences> {
+     let mut res: Vec<Influences> = Vec::new();
+
+     for m in &ms.meshes {
+         res.append(&mut prepare_influences(m));
+     }
+     res
+ }
+
+ // Weight of `joint` in each vertex, as seen by the vertex shader
+ pub fn joint_weights(influences: &[Influences], joint: u32) -> Vec<f32> {
+     influences.iter().map(|inf| {
+         let mut w = 0.0;
+         for i in 0..MAX_INFLUENCES {
+             if inf.joints[i] == joint {
+                 w += inf.weights[i];
+             }
+         }
+         w
+     }).collect()
+ }
+
+ // Object space transform of a joint
+ pub fn joint_matrix(j: &Joint) -> Matrix4<f32> {
+     Matrix4::from_translation(j.position) * Matrix4::from(j.orientation)
+ }
+
+ // Matrices taking bind pose positions to `pose`. Exporters write weight positions
+ // that all agree on the bind pose vertex, in which case skinning with this palette
+ // matches `prepare_mesh` exactly. There is one matrix per bind joint, joints
+ // missing from `pose` staying in their bind pose.
+ pub fn joint_palette(bind: &[Joint], pose: &[Joint]) -> Vec<Matrix4<f32>> {
+     bind.iter().enumerate().map(|(i, b)| match pose.get(i) {
+         Some(p) => joint_matrix(p) * joint_matrix(b).invert().unwrap_or(Matrix4::identity()),
+         None => Matrix4::identity()
+     }).collect()
+ }
+
+ fn blend(influences: &Influences, palette: &[Matrix4<f32>]) -> Matrix4<f32> {
+     let mut m = Matrix4::zero();
+
+     for i in 0..MAX_INFLUENCES {
+         if influences.weights[i] > 0.0 {
+             // joints past the palette don't move
+             let joint = palette.get(influences.joints[i] as usize).cloned().unwrap_or(Matrix4::identity());
+             m = m + joint * influences.weights[i];
+         }
+     }
+     m
+ }
+
+ // CPU version of the skinning done in the vertex shader, used as a reference
+ pub fn skin_vertices(bind_positions: &[Vector3<f32>], bind_normals: &[Vector3<f32>], influences: &[Influences], palette: &[Matrix4<f32>]) -> (Vec<Vector3<f32>>, Vec<Vector3<f32>>) {
+     let mut positions: Vec<Vector3<f32>> = Vec::with_capacity(bind_positions.len());
+     let mut normals: Vec<Vector3<f32>> = Vec::with_capacity(bind_normals.len());
+
+     for i in 0..bind_positions.len() {
+         let m = blend(&influences[i], palette);
+         let p = m * Vector4::new(bind_positions[i].x, bind_positions[i].y, bind_positions[i].z, 1.0);
+         let rotation = Matrix3::from_cols(m.x.truncate(), m.y.truncate(), m.z.truncate());
+
+         positions.push(p.truncate());
+         normals.push(rotation * bind_normals[i]);
+     }
+
+     (positions, normals)
+ }
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector2, Vector3, Quaternion, Rotation3, Deg, InnerSpace};
+    use md5::md5mesh::{Joint, Mesh, Vertex, Weight};
+    use vertex_computation::compute::{prepare_mesh, prepare_normals};
+
+    fn joints() -> Vec<Joint> {
+        vec![
+            Joint { name: String::from("root"), parent_index: -1, position: Vector3::new(0.0, 0.0, 0.0), orientation: Quaternion::from_angle_z(Deg(0.0)) },
+            Joint { name: String::from("arm"), parent_index: 0, position: Vector3::new(1.0, 0.0, 0.0), orientation: Quaternion::from_angle_z(Deg(90.0)) },
+        ]
+    }
+
+    // Two weights per vertex, both placing the vertex at its bind position
+    fn mesh(bind: &[Joint], points: &[Vector3<f32>]) -> Mesh {
+        let mut vertices = Vec::new();
+        let mut weights = Vec::new();
+
+        for (i, p) in points.iter().enumerate() {
+            vertices.push(Vertex { index: i as u32, tex_coords: Vector2::new(0.0, 0.0), start_weight: 2 * i as u32, weight_count: 2 });
+
+            for (j, bias) in vec![(0, 0.25), (1, 0.75)] {
+                let joint: &Joint = &bind[j];
+                let local = joint.orientation.conjugate() * (*p - joint.position);
+                weights.push(Weight { index: weights.len() as u32, joint_index: j as u32, bias: bias, position: local });
+            }
+        }
+
+        Mesh { shader: String::from("test"), vertices: vertices, triangles: vec![], weights: weights }
+    }
+
+    #[test]
+    fn prepare_influences() {
+        let m = mesh(&joints(), &[Vector3::new(2.0, 0.0, 0.0)]);
+        let influences = super::prepare_influences(&m);
+
+        assert_eq!(influences[0].joints, [1, 0, 0, 0]);
+        assert_eq!(influences[0].weights, [0.75, 0.25, 0.0, 0.0]);
+
+        let mut m = m;
+        m.weights[1].bias = ::std::f32::NAN;
+        let influences = super::prepare_influences(&m);
+        assert_eq!(influences[0].joints, [0, 0, 0, 0]);
+        assert_eq!(influences[0].weights, [1.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn skin_vertices() {
+        let bind = joints();
+        let points = vec![Vector3::new(2.0, 0.0, 0.0), Vector3::new(1.5, 0.5, 0.0), Vector3::new(1.0, 0.0, 1.0)];
+        let mut m = mesh(&bind, &points);
+        m.triangles.push(::md5::md5mesh::Triangle { index: 0, vertex_indices: (0, 1, 2) });
+
+        let mut pose = bind.clone();
+        pose[1].position = Vector3::new(0.5, 1.0, 0.0);
+        pose[1].orientation = Quaternion::from_angle_y(Deg(30.0)) * pose[1].orientation;
+
+        let bind_positions = prepare_mesh(&m, &bind);
+        let bind_normals = prepare_normals(&m, &bind_positions);
+        let expected = prepare_mesh(&m, &pose);
+
+        let palette = super::joint_palette(&bind, &pose);
+        let (positions, _) = super::skin_vertices(&bind_positions, &bind_normals, &super::prepare_influences(&m), &palette);
+
+        for i in 0..points.len() {
+            assert!((positions[i] - expected[i]).magnitude() < 1e-5);
+        }
+
+        // a pose without the arm leaves it in its bind pose
+        let palette = super::joint_palette(&bind, &pose[0..1]);
+        assert_eq!(palette.len(), 2);
+        let (positions, _) = super::skin_vertices(&bind_positions, &bind_normals, &super::prepare_influences(&m), &palette[0..1]);
+        for i in 0..points.len() {
+            assert!((positions[i] - bind_positions[i]).magnitude() < 1e-5);
+        }
+    }
+}